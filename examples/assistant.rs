@@ -133,9 +133,19 @@ fn main() {
         .register(FetchCalendar)
         .register(FetchEmail)
         .register(Summarize::new(llm))
-        .start_at("fetch_weather")
-        .then("fetch_calendar")
-        .then("fetch_email")
+        // The three fetches are independent, so run them side by side and
+        // copy each branch's field back into one state.
+        .parallel(
+            "fetch_all",
+            ["fetch_weather", "fetch_calendar", "fetch_email"],
+            |mut state, branches| {
+                state.weather = branches[0].weather.clone();
+                state.calendar = branches[1].calendar.clone();
+                state.emails = branches[2].emails.clone();
+                state
+            },
+        )
+        .start_at("fetch_all")
         .then("summarize")
         .build()
         .unwrap();
@@ -2,6 +2,9 @@
 //
 // Pipeline: planner -> coder -> tester -> (loop back to coder on failure)
 //
+// Once the tests pass, the tester pauses the run and asks on stdin whether
+// to keep the generated file.
+//
 // The coder agent switches its system prompt based on whether test failures
 // exist -- first pass writes from the plan, subsequent passes fix based on
 // test output.
@@ -9,7 +12,10 @@
 // Run: cargo run --example coder
 // Requires an LLM (Ollama by default, or set AGENT_LINE_PROVIDER).
 
-use agent_line::{Agent, Ctx, LlmConfig, Outcome, Runner, StepResult, Workflow, tools};
+use agent_line::{
+    Agent, Ctx, LlmConfig, Outcome, Routes, RunStatus, Runner, StepResult, Workflow, tools,
+};
+use std::io::{self, BufRead, Write};
 
 // ---------------------------------------------------------------------------
 // State
@@ -22,7 +28,6 @@ struct Task {
     code: String,
     test_output: String,
     attempts: u32,
-    max_attempts: u32,
 }
 
 // ---------------------------------------------------------------------------
@@ -70,6 +75,9 @@ impl Agent<Task> for Planner {
         ctx.log(format!("planner: created plan for {}", state.file_path));
         Ok((state, Outcome::Continue))
     }
+    fn routes(&self) -> Option<Routes> {
+        Some(Routes::new().continues())
+    }
 }
 
 struct Coder {
@@ -129,6 +137,9 @@ impl Agent<Task> for Coder {
         tools::write_file(&state.file_path, &state.code)?;
         Ok((state, Outcome::Continue))
     }
+    fn routes(&self) -> Option<Routes> {
+        Some(Routes::new().continues())
+    }
 }
 
 struct Tester;
@@ -137,27 +148,35 @@ impl Agent<Task> for Tester {
         "tester"
     }
     fn run(&mut self, mut state: Task, ctx: &mut Ctx) -> StepResult<Task> {
+        // Resumed after asking for approval: don't re-run the tests.
+        if let Some(answer) = ctx.resume_input() {
+            return if answer.trim().eq_ignore_ascii_case("y") {
+                ctx.log("tester: write approved");
+                Ok((state, Outcome::Done))
+            } else {
+                tools::write_file(&state.file_path, "")?;
+                ctx.log("tester: write rejected, file cleared");
+                Ok((state, Outcome::Fail("write rejected by reviewer".into())))
+            };
+        }
+
         let manifest = ctx.get("manifest_path").unwrap_or("Cargo.toml").to_string();
         let result = tools::run_cmd(&format!("cargo test --manifest-path {manifest} --lib"))?;
 
         if result.success {
-            ctx.log("tester: all passed");
-            Ok((state, Outcome::Done))
+            ctx.log("tester: all passed, asking for approval");
+            let code = state.code.clone();
+            Ok((state, Outcome::pause("tests pass, keep this file?", code)))
         } else {
             state.test_output = result.stderr;
             state.attempts += 1;
             ctx.log(format!("tester: failed (attempt {})", state.attempts));
-
-            if state.attempts >= state.max_attempts {
-                Ok((
-                    state,
-                    Outcome::Fail("max fix attempts reached, tests still failing".into()),
-                ))
-            } else {
-                Ok((state, Outcome::Next("coder")))
-            }
+            Ok((state, Outcome::next("coder")))
         }
     }
+    fn routes(&self) -> Option<Routes> {
+        Some(Routes::new().done().next("coder"))
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -198,12 +217,18 @@ fn main() {
         .start_at("planner")
         .then("coder")
         .then("tester")
+        // The first write and two fixes, then give up.
+        .max_visits("coder", 3)
         .build()
         .unwrap();
 
+    for issue in wf.validate() {
+        eprintln!("warning: {issue}");
+    }
+
     let mut runner = Runner::new(wf);
 
-    let result = runner.run(
+    let mut status = runner.start(
         Task {
             description: "Add a function called `reverse_string` that reverses a string \
                           and add unit tests"
@@ -212,11 +237,26 @@ fn main() {
             code: String::new(),
             test_output: String::new(),
             attempts: 0,
-            max_attempts: 3,
         },
         &mut ctx,
     );
 
+    // Keep asking until the run finishes or fails.
+    let result = loop {
+        match status {
+            Ok(RunStatus::Paused(paused)) => {
+                println!("=== {} ===\n{}\n", paused.reason, paused.payload);
+                print!("[y/N] ");
+                io::stdout().flush().unwrap();
+                let mut answer = String::new();
+                io::stdin().lock().read_line(&mut answer).unwrap();
+                status = runner.resume_with(paused, answer, &mut ctx);
+            }
+            Ok(RunStatus::Done(task)) => break Ok(task),
+            Err(e) => break Err(e),
+        }
+    };
+
     println!("=== Result ===");
     match result {
         Ok(task) => {
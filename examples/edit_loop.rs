@@ -48,7 +48,7 @@ impl Agent<Doc> for Validator {
             for e in &errors {
                 ctx.log(format!("validator: {e}"));
             }
-            Ok((state, Outcome::Next("fixer")))
+            Ok((state, Outcome::next("fixer")))
         }
     }
 }
@@ -82,7 +82,7 @@ impl Agent<Doc> for Fixer {
             Ok((state, Outcome::Retry(RetryHint::new("double-checking"))))
         } else {
             self.retried = false;
-            Ok((state, Outcome::Next("validator")))
+            Ok((state, Outcome::next("validator")))
         }
     }
 }
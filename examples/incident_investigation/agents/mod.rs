@@ -1,11 +1,11 @@
 mod correlate_timeline;
-mod find_anomalies;
 mod investigation_report;
 mod load_evidence;
+mod scan_service;
 mod triage_narrative;
 
 pub use correlate_timeline::CorrelateTimeline;
-pub use find_anomalies::FindAnomalies;
 pub use investigation_report::InvestigationReport;
 pub use load_evidence::LoadEvidence;
+pub use scan_service::ScanService;
 pub use triage_narrative::TriageNarrative;
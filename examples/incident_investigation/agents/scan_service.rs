@@ -1,23 +1,24 @@
-use crate::state::{Anomaly, IncidentState};
+use crate::state::{Anomaly, ServiceScan};
 use agent_line::{Agent, Ctx, Outcome, StepResult};
 use std::collections::BTreeMap;
 
-pub struct FindAnomalies;
+/// Looks for anomalous metrics on one service. The workflow runs one of
+/// these per service as a map step.
+pub struct ScanService;
 
-type MetricKey<'a> = (&'a str, &'a str);
 type MetricBuckets = (Vec<f64>, Vec<f64>);
 
-impl Agent<IncidentState> for FindAnomalies {
+impl Agent<ServiceScan> for ScanService {
     fn name(&self) -> &'static str {
-        "find_anomalies"
+        "scan_service"
     }
 
-    fn run(&mut self, mut state: IncidentState, ctx: &mut Ctx) -> StepResult<IncidentState> {
-        let mut grouped: BTreeMap<MetricKey<'_>, MetricBuckets> = BTreeMap::new();
+    fn run(&mut self, mut scan: ServiceScan, ctx: &mut Ctx) -> StepResult<ServiceScan> {
+        let mut grouped: BTreeMap<&str, MetricBuckets> = BTreeMap::new();
 
-        for point in &state.metrics {
+        for point in &scan.metrics {
             let (baseline, incident) = grouped
-                .entry((point.service, point.metric))
+                .entry(point.metric)
                 .or_insert_with(|| (Vec::new(), Vec::new()));
 
             if point.minute <= 3 {
@@ -27,9 +28,10 @@ impl Agent<IncidentState> for FindAnomalies {
             }
         }
 
-        state.anomalies = grouped
+        let service = scan.service;
+        scan.anomalies = grouped
             .into_iter()
-            .filter_map(|((service, metric), (baseline, incident))| {
+            .filter_map(|(metric, (baseline, incident))| {
                 let before_avg = average(&baseline)?;
                 let during_avg = average(&incident)?;
                 let ratio = if before_avg == 0.0 {
@@ -48,13 +50,11 @@ impl Agent<IncidentState> for FindAnomalies {
             })
             .collect();
 
-        state.anomalies.sort_by(|a, b| b.ratio.total_cmp(&a.ratio));
-
         ctx.log(format!(
-            "identified {} anomalous metrics",
-            state.anomalies.len()
+            "{service}: {} anomalous metrics",
+            scan.anomalies.len()
         ));
-        Ok((state, Outcome::Continue))
+        Ok((scan, Outcome::Continue))
     }
 }
 
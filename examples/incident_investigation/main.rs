@@ -3,9 +3,7 @@ mod data;
 mod state;
 
 use agent_line::{Ctx, LlmConfig, Provider, Runner, Workflow};
-use agents::{
-    CorrelateTimeline, FindAnomalies, InvestigationReport, LoadEvidence, TriageNarrative,
-};
+use agents::{CorrelateTimeline, InvestigationReport, LoadEvidence, ScanService, TriageNarrative};
 use state::IncidentState;
 use std::env;
 
@@ -62,7 +60,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut ctx = Ctx::new();
     let workflow = Workflow::builder("incident-investigation")
         .register(LoadEvidence)
-        .register(FindAnomalies)
+        // One scan per service, four at a time; the anomalies are merged
+        // back, worst first.
+        .map(
+            "find_anomalies",
+            IncidentState::service_scans,
+            || ScanService,
+            |mut state, scans| {
+                state.anomalies = scans.into_iter().flat_map(|s| s.anomalies).collect();
+                state.anomalies.sort_by(|a, b| b.ratio.total_cmp(&a.ratio));
+                state
+            },
+            4,
+        )
         .register(CorrelateTimeline)
         .register(TriageNarrative::new(fast_llm))
         .register(InvestigationReport::new(deep_llm))
@@ -25,6 +25,36 @@ impl IncidentState {
     }
 }
 
+impl IncidentState {
+    /// One scan per service that reported metrics, in service order.
+    pub fn service_scans(&self) -> Vec<ServiceScan> {
+        let mut services: Vec<&'static str> = self.metrics.iter().map(|m| m.service).collect();
+        services.sort_unstable();
+        services.dedup();
+        services
+            .into_iter()
+            .map(|service| ServiceScan {
+                service,
+                metrics: self
+                    .metrics
+                    .iter()
+                    .filter(|m| m.service == service)
+                    .cloned()
+                    .collect(),
+                anomalies: vec![],
+            })
+            .collect()
+    }
+}
+
+/// The metrics of one service, and the anomalies found in them.
+#[derive(Clone, Debug)]
+pub struct ServiceScan {
+    pub service: &'static str,
+    pub metrics: Vec<MetricPoint>,
+    pub anomalies: Vec<Anomaly>,
+}
+
 #[derive(Clone, Debug)]
 pub struct LogLine {
     pub minute: u32,
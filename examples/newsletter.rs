@@ -1,4 +1,4 @@
-use agent_line::{Agent, Ctx, LlmConfig, Outcome, Runner, StepResult, Workflow};
+use agent_line::{Agent, Ctx, LlmConfig, Outcome, RetryHint, Runner, StepResult, Workflow};
 
 // ---------------------------------------------------------------------------
 // State types
@@ -18,6 +18,14 @@ struct ArticleState {
     revision: u32,
 }
 
+/// The whole newsletter run: phase 1 fills `topics`, phase 2 writes one
+/// finished article per selected topic.
+#[derive(Clone, Debug)]
+struct NewsletterState {
+    topics: TopicState,
+    articles: Vec<ArticleState>,
+}
+
 // ---------------------------------------------------------------------------
 // Phase 1 agents: find and pick topics
 // ---------------------------------------------------------------------------
@@ -84,96 +92,73 @@ impl Agent<TopicState> for TopicPicker {
 }
 
 // ---------------------------------------------------------------------------
-// Phase 2 agents: write, validate, fix one article
+// Phase 2 agent: write, validate and fix one article
 // ---------------------------------------------------------------------------
 
-struct ArticleWriter;
-impl Agent<ArticleState> for ArticleWriter {
-    fn name(&self) -> &'static str {
-        "article_writer"
-    }
-    fn run(&mut self, mut state: ArticleState, ctx: &mut Ctx) -> StepResult<ArticleState> {
-        state.revision += 1;
-        ctx.log(format!(
-            "writing draft {} for: {}",
-            state.revision, state.topic
-        ));
-
-        // Stub: first draft is sloppy, second is clean
-        if state.revision == 1 {
-            state.draft = format!(
-                "# {}\n\nThis is a artcle about {}. It has lots of good infomation.",
-                state.topic, state.topic
-            );
-        } else {
-            state.draft = format!(
-                "# {}\n\nThis is an article about {}. It has lots of good information.",
-                state.topic, state.topic
-            );
-        }
-
-        Ok((state, Outcome::Continue))
-    }
-}
-
-struct ArticleValidator {
+/// Runs once per selected topic. Each pass validates the current draft and,
+/// if the editor finds errors, fixes them and retries so the next pass
+/// validates the fix. The runner's retry limit bounds the loop.
+struct ArticleEditor {
     llm: LlmConfig,
 }
 
-impl ArticleValidator {
+impl ArticleEditor {
     fn new(llm: LlmConfig) -> Self {
         Self { llm }
     }
 }
 
-impl Agent<ArticleState> for ArticleValidator {
+impl Agent<ArticleState> for ArticleEditor {
     fn name(&self) -> &'static str {
-        "article_validator"
+        "article_editor"
     }
-    fn run(&mut self, state: ArticleState, ctx: &mut Ctx) -> StepResult<ArticleState> {
-        // use store k/v to pull in validation rules and pass them to the llm
-        let response = self
+    fn run(&mut self, mut state: ArticleState, ctx: &mut Ctx) -> StepResult<ArticleState> {
+        if state.draft.is_empty() {
+            ctx.log(format!("writing draft for: {}", state.topic));
+            // Stub: the first draft is sloppy
+            state.draft = format!(
+                "# {}\n\nThis is a artcle about {}. It has lots of good infomation.",
+                state.topic, state.topic
+            );
+        }
+        state.revision += 1;
+
+        let errors = self
             .llm
             .request()
             .system("You are a strict editor. List any errors. Say PASS if none.")
             .user(&state.draft)
             .send()?;
-
-        // ctx,store get rules or previous work?
-        if response.contains("PASS") {
-            Ok((state, Outcome::Done))
-        } else {
-            ctx.set("errors", &response);
-            Ok((state, Outcome::Next("article_fixer")))
+        if errors.contains("PASS") {
+            ctx.log(format!("approved rev {}: {}", state.revision, state.topic));
+            return Ok((state, Outcome::Continue));
         }
-    }
-}
 
-struct ArticleFixer {
-    llm: LlmConfig,
-}
-
-impl ArticleFixer {
-    fn new(llm: LlmConfig) -> Self {
-        Self { llm }
-    }
-}
-
-impl Agent<ArticleState> for ArticleFixer {
-    fn name(&self) -> &'static str {
-        "article_fixer"
-    }
-    fn run(&mut self, mut state: ArticleState, ctx: &mut Ctx) -> StepResult<ArticleState> {
-        let errors = ctx.get("errors").unwrap_or("no errors found").to_string();
-        let response = self
+        state.draft = self
             .llm
             .request()
             .system("You are a writer. Rewrite the article fixing only the listed errors.")
             .user(format!("Errors:\n{errors}\n\nArticle:\n{}", state.draft))
             .send()?;
+        Ok((
+            state,
+            Outcome::Retry(RetryHint::new("fixed errors, validate again")),
+        ))
+    }
+}
 
-        state.draft = response;
-        Ok((state, Outcome::Next("article_validator")))
+// ---------------------------------------------------------------------------
+// Last step: hand the finished articles off
+// ---------------------------------------------------------------------------
+
+struct Publish;
+impl Agent<NewsletterState> for Publish {
+    fn name(&self) -> &'static str {
+        "publish"
+    }
+    fn run(&mut self, state: NewsletterState, ctx: &mut Ctx) -> StepResult<NewsletterState> {
+        ctx.log(format!("publishing {} articles", state.articles.len()));
+        Ok((state, Outcome::Done))
     }
 }
 
@@ -196,66 +181,74 @@ fn main() {
         .build()
         .unwrap();
 
-    let mut topic_runner = Runner::new(topic_wf);
-    let topics = topic_runner
+    // Phase 2: one article per selected topic, two at a time. Each article
+    // runs its own editor; the results come back in topic order.
+    let newsletter_wf = Workflow::builder("newsletter")
+        .subworkflow_mapped(
+            topic_wf,
+            |n: &NewsletterState| n.topics.clone(),
+            |mut n, topics| {
+                n.topics = topics;
+                n
+            },
+        )
+        .map(
+            "write-articles",
+            |n: &NewsletterState| {
+                n.topics
+                    .selected
+                    .iter()
+                    .map(|topic| ArticleState {
+                        topic: topic.clone(),
+                        draft: String::new(),
+                        revision: 0,
+                    })
+                    .collect()
+            },
+            move || ArticleEditor::new(llm.clone()),
+            |mut n, articles| {
+                n.articles = articles;
+                n
+            },
+            2,
+        )
+        .register(Publish)
+        .start_at("find-topics")
+        .then("write-articles")
+        .then("publish")
+        .build()
+        .unwrap();
+
+    // Steps inside each phase show up as `find-topics/topic_picker`,
+    // `write-articles/article_editor[0]`, and so on.
+    let mut runner = Runner::new(newsletter_wf).with_tracing();
+    let newsletter = runner
         .run(
-            TopicState {
-                query: "bluecollar engineering newsletter".into(),
-                topics: vec![],
-                selected: vec![],
+            NewsletterState {
+                topics: TopicState {
+                    query: "bluecollar engineering newsletter".into(),
+                    topics: vec![],
+                    selected: vec![],
+                },
+                articles: vec![],
             },
             &mut ctx,
         )
         .unwrap();
 
-    println!("=== Topics ===");
+    println!("\n=== Log ===");
     for entry in ctx.logs() {
         println!("  {entry}");
     }
-    ctx.clear_logs();
-    println!();
-
-    // Phase 2: write one article per topic
-    let article_wf = Workflow::builder("write-article")
-        .register(ArticleWriter)
-        .register(ArticleValidator::new(llm.clone()))
-        .register(ArticleFixer::new(llm))
-        .start_at("article_writer")
-        .then("article_validator")
-        .build()
-        .unwrap();
-
-    let mut article_runner = Runner::new(article_wf);
-    let mut finished_articles: Vec<String> = Vec::new();
-
-    for (i, topic) in topics.selected.iter().enumerate() {
-        println!("=== Article {} ===", i + 1);
-
-        let result = article_runner
-            .run(
-                ArticleState {
-                    topic: topic.clone(),
-                    draft: String::new(),
-                    revision: 0,
-                },
-                &mut ctx,
-            )
-            .unwrap();
-
-        finished_articles.push(result.draft.clone());
-        println!("  Revisions: {}", result.revision);
-
-        for entry in ctx.logs() {
-            println!("  {entry}");
-        }
-        ctx.clear_logs();
-        println!();
-    }
 
     // Phase 3: "store" the articles
-    println!("=== Stored ===");
-    for (i, article) in finished_articles.iter().enumerate() {
-        let preview: String = article.chars().take(60).collect();
-        println!("  article_{}.md: {preview}...", i + 1);
+    println!("\n=== Stored ===");
+    for (i, article) in newsletter.articles.iter().enumerate() {
+        let preview: String = article.draft.chars().take(60).collect();
+        println!(
+            "  article_{}.md (rev {}): {preview}...",
+            i + 1,
+            article.revision
+        );
     }
 }
@@ -1,6 +1,9 @@
-use agent_line::{Agent, Ctx, ErrorEvent, Outcome, Runner, StepEvent, StepResult, Workflow};
-use opentelemetry::trace::{Span, Status, TraceContextExt, Tracer};
-use opentelemetry::{Context, KeyValue, global};
+// Export each run and step as OpenTelemetry spans, printed to stdout.
+//
+// Run: cargo run --example otel_tracing --features otel
+
+use agent_line::{Agent, Ctx, OtelObserver, Outcome, Runner, StepResult, Workflow};
+use opentelemetry::{KeyValue, global};
 use opentelemetry_sdk::Resource;
 use opentelemetry_sdk::trace::SdkTracerProvider;
 
@@ -80,42 +83,11 @@ fn init_tracer() -> impl FnOnce() {
     }
 }
 
-fn record_step(workflow: &str, parent: &Context, event: &StepEvent) {
-    let tracer = global::tracer("agent-line.examples.otel");
-    let mut span = tracer.start_with_context("agent.step", parent);
-    span.set_attribute(KeyValue::new("workflow.name", workflow.to_string()));
-    span.set_attribute(KeyValue::new("agent.name", event.agent.to_string()));
-    span.set_attribute(KeyValue::new("step.number", event.step_number as i64));
-    span.set_attribute(KeyValue::new("step.retries", event.retries as i64));
-    span.set_attribute(KeyValue::new(
-        "step.duration_ms",
-        event.duration.as_millis() as i64,
-    ));
-    span.set_attribute(KeyValue::new(
-        "step.outcome",
-        format!("{:?}", event.outcome),
-    ));
-    span.end();
-}
-
-fn record_error(workflow: &str, parent: &Context, event: &ErrorEvent) {
-    let tracer = global::tracer("agent-line.examples.otel");
-    let mut span = tracer.start_with_context("agent.step.error", parent);
-    span.set_status(Status::error(event.error.to_string()));
-    span.set_attribute(KeyValue::new("workflow.name", workflow.to_string()));
-    span.set_attribute(KeyValue::new("agent.name", event.agent.to_string()));
-    span.set_attribute(KeyValue::new("step.number", event.step_number as i64));
-    span.set_attribute(KeyValue::new("error.message", event.error.to_string()));
-    span.end();
-}
-
 fn main() {
     let shutdown = init_tracer();
-    let tracer = global::tracer("agent-line.examples.otel");
-    let workflow_name = "draft-workflow";
 
     let mut ctx = Ctx::new();
-    let wf = Workflow::builder(workflow_name)
+    let wf = Workflow::builder("draft-workflow")
         .register(Research)
         .register(Write)
         .register(Finalize)
@@ -125,28 +97,15 @@ fn main() {
         .build()
         .expect("workflow should be valid");
 
-    let mut workflow_span = tracer.start("workflow.run");
-    workflow_span.set_attribute(KeyValue::new("workflow.name", workflow_name.to_string()));
-    let parent = Context::new().with_remote_span_context(workflow_span.span_context().clone());
-    let parent_for_step = parent.clone();
-    let parent_for_error = parent.clone();
-
-    let mut runner = Runner::new(wf)
-        .on_step(move |event| record_step(workflow_name, &parent_for_step, event))
-        .on_error(move |event| record_error(workflow_name, &parent_for_error, event));
+    // One `workflow draft-workflow` span with an `invoke_agent <step>` child
+    // per step. LLM calls made inside a step would nest under its span.
+    let mut runner = Runner::new(wf).with_observer(OtelObserver::new());
 
-    let initial = DraftState::new("OpenTelemetry tracing with agent-line hooks");
+    let initial = DraftState::new("OpenTelemetry tracing with agent-line");
     match runner.run(initial, &mut ctx) {
-        Ok(final_state) => {
-            workflow_span.set_status(Status::Ok);
-            println!("=== Final Draft ===\n{}", final_state.draft);
-        }
-        Err(err) => {
-            workflow_span.set_status(Status::error(err.to_string()));
-            eprintln!("workflow failed: {err}");
-        }
+        Ok(final_state) => println!("=== Final Draft ===\n{}", final_state.draft),
+        Err(err) => eprintln!("workflow failed: {err}"),
     }
 
-    workflow_span.end();
     shutdown();
 }
@@ -1,9 +1,10 @@
 // Threaded fan-out / fan-in example.
 //
-// Spawns one thread per topic to write articles in parallel. Each thread gets
-// its own Ctx and Runner -- no shared mutable state, no async runtime.
+// Writes one article per topic in parallel with a BatchRunner. Each topic gets
+// its own workflow, Ctx and Runner on a worker thread -- no shared mutable
+// state, no async runtime.
 //
-// Pipeline per thread: researcher -> writer -> editor -> (loop back to writer if needed) -> done
+// Pipeline per topic: researcher -> writer -> editor -> (loop back to writer if needed) -> done
 //
 // All data flows through the state struct. To use a real LLM, give each
 // agent an `llm: LlmConfig` field and replace the stubs with
@@ -11,8 +12,7 @@
 //
 // Run: cargo run --example parallel
 
-use agent_line::{Agent, Ctx, Outcome, Runner, StepResult, Workflow};
-use std::thread;
+use agent_line::{Agent, BatchRunner, Ctx, Outcome, StepResult, Workflow};
 
 // ---------------------------------------------------------------------------
 // State
@@ -185,7 +185,7 @@ impl Agent<ArticleState> for Editor {
             state.feedback =
                 "opening is bland, needs a hook. 'a interesting' should be 'an interesting'".into();
             ctx.log(format!("needs revision: {}", state.feedback));
-            Ok((state, Outcome::Next("writer")))
+            Ok((state, Outcome::next("writer")))
         } else {
             ctx.log(format!("approved: {}", state.topic));
             Ok((state, Outcome::Done))
@@ -212,54 +212,47 @@ fn main() {
         Keep it under 300 words."
         .to_string();
 
-    println!("=== Fan-out: {} threads ===\n", topics.len());
+    println!("=== Fan-out: {} topics ===\n", topics.len());
 
-    // Fan-out: spawn one thread per topic
-    let handles: Vec<_> = topics
+    let inputs = topics
         .into_iter()
-        .enumerate()
-        .map(|(i, topic)| {
-            let guidelines = guidelines.clone();
-
-            thread::spawn(move || {
-                // Each thread gets its own Ctx and Runner -- no shared mutable state
-                let mut ctx = Ctx::new();
-
-                let wf = Workflow::builder("write-article")
-                    .register(Researcher)
-                    .register(Writer)
-                    .register(Editor)
-                    .start_at("researcher")
-                    .then("writer")
-                    .then("editor")
-                    .build()
-                    .unwrap();
-
-                let mut runner = Runner::new(wf).with_max_retries(5);
-
-                let result = runner.run(ArticleState::new(topic.clone(), guidelines), &mut ctx);
-
-                // Print the log from this thread's pipeline
-                for entry in ctx.logs() {
-                    println!("  [thread {}] {}", i, entry);
-                }
-
-                result
-            })
-        })
-        .collect();
-
-    // Fan-in: join all threads, collect results
+        .map(|topic| ArticleState::new(topic, guidelines.clone()));
+
+    // Each topic gets a fresh workflow from the factory, so agents never
+    // share state between topics.
+    let report = BatchRunner::new(|| {
+        Workflow::builder("write-article")
+            .register(Researcher)
+            .register(Writer)
+            .register(Editor)
+            .start_at("researcher")
+            .then("writer")
+            .then("editor")
+            .build()
+    })
+    .concurrency(3)
+    .configure(|runner| runner.with_max_retries(5))
+    .on_progress(|p| println!("  [{}/{} done]", p.completed, p.total))
+    .run_all(inputs);
+
+    // Fan-in: results come back in input order, each with its own log
     let mut finished = Vec::new();
-    for handle in handles {
-        match handle.join().unwrap() {
+    for item in report.items {
+        for entry in item.ctx.logs() {
+            println!("  [topic {}] {}", item.index, entry);
+        }
+        match item.result {
             Ok(state) => finished.push(state),
-            Err(e) => eprintln!("thread failed: {e}"),
+            Err(e) => eprintln!("topic {} failed: {e}", item.index),
         }
     }
 
     // Show results
-    println!("\n=== Fan-in: {} articles ===\n", finished.len());
+    println!(
+        "\n=== Fan-in: {} articles in {:.2}s ===\n",
+        finished.len(),
+        report.duration.as_secs_f64()
+    );
     for (i, article) in finished.iter().enumerate() {
         let preview: String = article.draft.chars().take(72).collect();
         println!(
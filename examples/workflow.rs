@@ -24,7 +24,7 @@ impl Agent<State> for StopAtThree {
         if state.n >= 3 {
             Ok((state, Outcome::Done))
         } else {
-            Ok((state, Outcome::Next("add_one")))
+            Ok((state, Outcome::next("add_one")))
         }
     }
 }
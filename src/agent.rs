@@ -1,4 +1,6 @@
 use crate::ctx::Ctx;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::fmt;
 
 /// The result of running a step: a new state plus what to do next.
@@ -10,11 +12,79 @@ pub type StepResult<S> = Result<(S, Outcome), StepError>;
 /// [`crate::Workflow`] to build a pipeline.
 pub trait Agent<S>: Send + 'static {
     /// A unique name for this agent, used for routing with [`Outcome::Next`].
-    fn name(&self) -> &'static str;
+    /// Usually a literal, but it may come from the agent's own fields, e.g.
+    /// one reviewer per configured persona.
+    fn name(&self) -> &str;
 
     /// Run one step. Returns the updated state and an [`Outcome`] that tells
     /// the runner what to do next.
     fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S>;
+
+    /// Optionally declare where this agent can send the run, so
+    /// [`crate::WorkflowBuilder::build`] and [`crate::Workflow::validate`]
+    /// can check the graph. `None` (the default) means the agent may do
+    /// anything.
+    fn routes(&self) -> Option<Routes> {
+        None
+    }
+}
+
+/// The outcomes an agent can return, declared with [`Agent::routes`].
+///
+/// `Retry`, `Wait`, `Fail` and `Pause` don't need declaring.
+///
+/// ```rust
+/// use agent_line::Routes;
+///
+/// // A tester that either finishes or sends the run back to the coder.
+/// let routes = Routes::new().done().next("coder");
+/// assert!(routes.can_finish());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Routes {
+    next: Vec<Cow<'static, str>>,
+    done: bool,
+    continues: bool,
+}
+
+impl Routes {
+    /// Declare no outcomes yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The agent can return `Outcome::Next(step)`.
+    pub fn next(mut self, step: impl Into<Cow<'static, str>>) -> Self {
+        self.next.push(step.into());
+        self
+    }
+
+    /// The agent can return `Outcome::Done`.
+    pub fn done(mut self) -> Self {
+        self.done = true;
+        self
+    }
+
+    /// The agent can return `Outcome::Continue`.
+    pub fn continues(mut self) -> Self {
+        self.continues = true;
+        self
+    }
+
+    /// Steps the agent can jump to with `Outcome::Next`.
+    pub fn next_steps(&self) -> &[Cow<'static, str>] {
+        &self.next
+    }
+
+    /// Whether the agent can return `Outcome::Done`.
+    pub fn can_finish(&self) -> bool {
+        self.done
+    }
+
+    /// Whether the agent can return `Outcome::Continue`.
+    pub fn can_continue(&self) -> bool {
+        self.continues
+    }
 }
 
 /// Control flow for the runner.
@@ -25,14 +95,39 @@ pub enum Outcome {
 
     /// Workflow complete, return the final state.
     Done,
-    /// Jump to a specific agent by name.
-    Next(&'static str),
+    /// Jump to a specific agent by name. [`Outcome::next`] builds one from
+    /// a literal or a `String`.
+    Next(Cow<'static, str>),
     /// Re-run the current agent (counted against `max_retries`).
     Retry(RetryHint),
     /// Sleep for the given duration, then re-run (counted against `max_retries`).
     Wait(std::time::Duration),
     /// Stop the workflow with an error.
     Fail(String),
+    /// Hand control back to the caller, e.g. to ask a human for input or
+    /// approval. [`crate::Runner::start`] returns a [`crate::Paused`] handle
+    /// that [`crate::Runner::resume_with`] continues from.
+    Pause {
+        /// Why the workflow paused.
+        reason: String,
+        /// Data for whoever resumes it (a diff to approve, a question, ...).
+        payload: String,
+    },
+}
+
+impl Outcome {
+    /// Create a [`Next`](Outcome::Next) outcome.
+    pub fn next(step: impl Into<Cow<'static, str>>) -> Self {
+        Outcome::Next(step.into())
+    }
+
+    /// Create a [`Pause`](Outcome::Pause) outcome.
+    pub fn pause(reason: impl Into<String>, payload: impl Into<String>) -> Self {
+        Outcome::Pause {
+            reason: reason.into(),
+            payload: payload.into(),
+        }
+    }
 }
 
 /// Metadata attached to an [`Outcome::Retry`] to explain why the agent
@@ -54,7 +149,7 @@ impl RetryHint {
 
 /// Error type for agent steps, with variants designed around what the caller
 /// can do about them.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum StepError {
     /// Bad input or agent logic error. Don't retry, fix the code.
     Invalid(String),
@@ -64,6 +159,13 @@ pub enum StepError {
     Failed(String),
     /// Everything else. Inspect the message for details.
     Other(String),
+    /// The run was cancelled or ran past its deadline. Don't retry.
+    Cancelled(String),
+    /// A step ran longer than its timeout. Retrying might help.
+    Timeout(String),
+    /// A step was entered more often than its visit limit allows, e.g. two
+    /// agents handing work back and forth forever. Don't retry.
+    LoopLimit(String),
 }
 
 impl From<ureq::Error> for StepError {
@@ -102,6 +204,9 @@ impl fmt::Display for StepError {
             Self::Other(msg) => write!(f, "{msg}"),
             Self::Transient(msg) => write!(f, "transient: {msg}"),
             Self::Failed(msg) => write!(f, "failed: {msg}"),
+            Self::Cancelled(msg) => write!(f, "cancelled: {msg}"),
+            Self::Timeout(msg) => write!(f, "timeout: {msg}"),
+            Self::LoopLimit(msg) => write!(f, "loop limit: {msg}"),
         }
     }
 }
@@ -152,6 +257,27 @@ mod tests {
         assert_eq!(err.to_string(), "transient: timeout");
     }
 
+    #[test]
+    fn display_cancelled() {
+        let err = StepError::Cancelled("deadline exceeded".into());
+        assert_eq!(err.to_string(), "cancelled: deadline exceeded");
+    }
+
+    #[test]
+    fn display_timeout() {
+        let err = StepError::Timeout("too slow".into());
+        assert_eq!(err.to_string(), "timeout: too slow");
+    }
+
+    #[test]
+    fn display_loop_limit() {
+        let err = StepError::LoopLimit("step 'editor' was entered more than 3 times".into());
+        assert_eq!(
+            err.to_string(),
+            "loop limit: step 'editor' was entered more than 3 times"
+        );
+    }
+
     #[test]
     fn display_failed() {
         let err = StepError::Failed("nope".into());
@@ -0,0 +1,1040 @@
+use crate::cancel::Cancel;
+use crate::observer::{ErrorFn, StepFn};
+use crate::runner::{Route, route};
+use crate::workflow::{Edge, Edges, Name, Wiring, check_edges, follow_edges, step_name};
+use crate::{
+    BeforeStepEvent, CancellationToken, CatchEvent, CaughtError, Ctx, ErrorEvent, Outcome,
+    RetryEvent, RetryPolicy, RouteEvent, RunEndEvent, RunObserver, RunStartEvent, StepError,
+    StepEvent, StepResult, WaitEvent, WorkflowError,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+/// An async agent that transforms state one step at a time.
+///
+/// The async counterpart of [`crate::Agent`]. Implement it with an
+/// `async fn run` and register it into an [`AsyncWorkflow`]:
+///
+/// ```rust
+/// use agent_line::{AsyncAgent, Ctx, Outcome, StepResult};
+///
+/// #[derive(Clone)]
+/// struct State { n: i32 }
+///
+/// struct AddOne;
+/// impl AsyncAgent<State> for AddOne {
+///     fn name(&self) -> &'static str { "add_one" }
+///     async fn run(&mut self, state: State, _ctx: &mut Ctx) -> StepResult<State> {
+///         Ok((State { n: state.n + 1 }, Outcome::Done))
+///     }
+/// }
+/// ```
+pub trait AsyncAgent<S>: Send + 'static {
+    /// A unique name for this agent, used for routing with [`crate::Outcome::Next`].
+    fn name(&self) -> &str;
+
+    /// Run one step. Returns the updated state and an [`crate::Outcome`]
+    /// that tells the runner what to do next.
+    fn run(&mut self, state: S, ctx: &mut Ctx) -> impl Future<Output = StepResult<S>> + Send;
+}
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Object-safe form of [`AsyncAgent`] so a workflow can hold mixed agents.
+trait DynAsyncAgent<S>: Send {
+    fn run_boxed<'a>(&'a mut self, state: S, ctx: &'a mut Ctx) -> BoxFuture<'a, StepResult<S>>;
+}
+
+impl<S: Send + 'static, A: AsyncAgent<S>> DynAsyncAgent<S> for A {
+    fn run_boxed<'a>(&'a mut self, state: S, ctx: &'a mut Ctx) -> BoxFuture<'a, StepResult<S>> {
+        Box::pin(AsyncAgent::run(self, state, ctx))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// AsyncWorkflowBuilder
+// ---------------------------------------------------------------------------
+
+/// Step-by-step builder for an [`AsyncWorkflow`]. Obtained via
+/// [`AsyncWorkflow::builder`].
+pub struct AsyncWorkflowBuilder<S: Clone + Send + 'static> {
+    name: Name,
+    agents: HashMap<Name, Box<dyn DynAsyncAgent<S>>>,
+    wiring: Wiring,
+    edges: Edges<S>,
+    unanchored_edge: bool,
+    retry_policies: HashMap<Name, RetryPolicy>,
+    error_handlers: HashMap<Name, Name>,
+    catch: Option<Name>,
+}
+
+impl<S: Clone + Send + 'static> AsyncWorkflowBuilder<S> {
+    /// Register an agent. The first agent registered becomes the default start step.
+    pub fn register<A: AsyncAgent<S>>(mut self, agent: A) -> Self {
+        let name = Name::from(AsyncAgent::name(&agent));
+        if self.agents.contains_key(&name) {
+            self.wiring.duplicate = Some(name.clone());
+        }
+        self.agents.insert(name.clone(), Box::new(agent));
+        self.wiring.registered(name);
+        self
+    }
+
+    /// Set which agent runs first (overrides the default).
+    pub fn start_at(mut self, step: impl Into<Cow<'static, str>>) -> Self {
+        self.wiring.start_at(step_name(step));
+        self
+    }
+
+    /// Chain the next step: current(chain_last) -> next
+    pub fn then(mut self, next: impl Into<Cow<'static, str>>) -> Self {
+        self.wiring.then(step_name(next));
+        self
+    }
+
+    /// Add a conditional edge from the current chain step, as
+    /// [`crate::WorkflowBuilder::when`] does.
+    pub fn when(
+        mut self,
+        predicate: impl Fn(&S) -> bool + Send + Sync + 'static,
+        to: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        match self.wiring.chain_last() {
+            Some(from) => self
+                .edges
+                .entry(from)
+                .or_default()
+                .push(Edge::when(predicate, step_name(to))),
+            None => self.unanchored_edge = true,
+        }
+        self
+    }
+
+    /// Route out of `from` with a function of the state, as
+    /// [`crate::WorkflowBuilder::branch`] does.
+    pub fn branch<R, T>(
+        mut self,
+        from: impl Into<Cow<'static, str>>,
+        router: impl Fn(&S) -> R + Send + Sync + 'static,
+        targets: impl IntoIterator<Item = T>,
+    ) -> Self
+    where
+        R: Into<Cow<'static, str>>,
+        T: Into<Cow<'static, str>>,
+    {
+        self.edges
+            .entry(step_name(from))
+            .or_default()
+            .push(Edge::branch(router, targets));
+        self
+    }
+
+    /// Retry `step` according to `policy` instead of the runner's
+    /// `max_retries`.
+    pub fn retry_policy(mut self, step: impl Into<Cow<'static, str>>, policy: RetryPolicy) -> Self {
+        self.retry_policies.insert(step_name(step), policy);
+        self
+    }
+
+    /// Route failures of `from` to `handler` instead of ending the run, as
+    /// [`crate::WorkflowBuilder::on_error`] does.
+    pub fn on_error(
+        mut self,
+        from: impl Into<Cow<'static, str>>,
+        handler: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.error_handlers
+            .insert(step_name(from), step_name(handler));
+        self
+    }
+
+    /// Route failures of every step without its own
+    /// [`on_error`](Self::on_error) handler to `handler`, as
+    /// [`crate::WorkflowBuilder::catch`] does.
+    pub fn catch(mut self, handler: impl Into<Cow<'static, str>>) -> Self {
+        self.catch = Some(step_name(handler));
+        self
+    }
+
+    /// Validate and build the workflow, with the same rules as
+    /// [`crate::WorkflowBuilder::build`].
+    pub fn build(self) -> Result<AsyncWorkflow<S>, WorkflowError> {
+        let agents = self.agents;
+        let start = self.wiring.validate(|name| agents.contains_key(name))?;
+
+        // A `when` with no chain step to hang it on.
+        if self.unanchored_edge {
+            return Err(WorkflowError::MissingStart);
+        }
+        check_edges(&self.edges, |name| agents.contains_key(name))?;
+        let handlers = self.error_handlers.iter().flat_map(|(from, to)| [from, to]);
+        for step in self
+            .retry_policies
+            .keys()
+            .chain(handlers)
+            .chain(&self.catch)
+        {
+            if !agents.contains_key(step) {
+                return Err(WorkflowError::UnknownStep(step.to_string()));
+            }
+        }
+
+        Ok(AsyncWorkflow {
+            name: self.name,
+            start,
+            agents,
+            default_next: self.wiring.default_next,
+            edges: self.edges,
+            retry_policies: self.retry_policies,
+            error_handlers: self.error_handlers,
+            catch: self.catch,
+        })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// AsyncWorkflow
+// ---------------------------------------------------------------------------
+
+/// A validated workflow of async agents. Built via [`AsyncWorkflow::builder`].
+pub struct AsyncWorkflow<S: Clone + Send + 'static> {
+    name: Name,
+    start: Name,
+    agents: HashMap<Name, Box<dyn DynAsyncAgent<S>>>,
+    default_next: HashMap<Name, Name>,
+    edges: Edges<S>,
+    retry_policies: HashMap<Name, RetryPolicy>,
+    error_handlers: HashMap<Name, Name>,
+    catch: Option<Name>,
+}
+
+impl<S: Clone + Send + 'static> AsyncWorkflow<S> {
+    /// Create a new builder with the given workflow name.
+    pub fn builder(name: impl Into<Cow<'static, str>>) -> AsyncWorkflowBuilder<S> {
+        AsyncWorkflowBuilder {
+            name: step_name(name),
+            agents: HashMap::new(),
+            wiring: Wiring::default(),
+            edges: HashMap::new(),
+            unanchored_edge: false,
+            retry_policies: HashMap::new(),
+            error_handlers: HashMap::new(),
+            catch: None,
+        }
+    }
+
+    /// The workflow's name (set at builder creation).
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Where a failure of `from` goes instead of ending the run, as in
+    /// the sync workflow. Cancellation always ends it.
+    fn error_handler(&self, from: &str, err: &StepError) -> Option<Name> {
+        if matches!(err, StepError::Cancelled(_)) {
+            return None;
+        }
+        let handler = self.error_handlers.get(from).or(self.catch.as_ref())?;
+        (**handler != *from).then(|| handler.clone())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// AsyncRunner
+// ---------------------------------------------------------------------------
+
+/// Executes an [`AsyncWorkflow`] inside a tokio runtime.
+///
+/// Routing follows the same rules as [`crate::Runner`]: `Next`, `then`,
+/// `when` and `branch` edges, retry policies with backoff, `on_error` and
+/// `catch` error handlers, cancellation tokens and deadlines, and
+/// [`RunObserver`]s alongside stacking `on_step`/`on_error` hooks.
+/// Retries, backoff and [`crate::Outcome::Wait`] sleep with tokio timers
+/// instead of blocking the thread, and wake early on cancellation.
+///
+/// It covers a subset of the sync runner. [`AsyncWorkflowBuilder`] has no
+/// methods for the rest, and [`crate::Outcome::Pause`] fails the run. Not
+/// supported:
+///
+/// - pausing, checkpoints, recordings and run reports;
+/// - step timeouts and visit limits;
+/// - parallel steps, map steps, sub-workflows and middleware.
+pub struct AsyncRunner<S: Clone + Send + 'static> {
+    wf: AsyncWorkflow<S>,
+    max_steps: usize,
+    max_retries: usize,
+    observers: Vec<Box<dyn RunObserver>>,
+    cancellation: Option<CancellationToken>,
+    deadline: Option<Duration>,
+}
+
+impl<S: Clone + Send + 'static> AsyncRunner<S> {
+    /// Create a runner for the given workflow with default limits
+    /// (max_steps: 10,000, max_retries: 3).
+    pub fn new(wf: AsyncWorkflow<S>) -> Self {
+        Self {
+            wf,
+            max_steps: 10_000,
+            max_retries: 3,
+            observers: Vec::new(),
+            cancellation: None,
+            deadline: None,
+        }
+    }
+
+    /// Prevent accidental infinite loops.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Set the maximum consecutive retries per agent before failing.
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Stop runs when `token` is cancelled, as
+    /// [`crate::Runner::with_cancellation`] does.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Fail a run with [`StepError::Cancelled`] once it has taken longer
+    /// than `limit`, as [`crate::Runner::with_deadline`] does.
+    pub fn with_deadline(mut self, limit: Duration) -> Self {
+        self.deadline = Some(limit);
+        self
+    }
+
+    /// Register a callback that fires after each successful agent step.
+    /// Callbacks add up: registering another keeps the earlier ones.
+    pub fn on_step(self, cb: impl FnMut(&StepEvent) + Send + 'static) -> Self {
+        self.with_observer(StepFn(cb))
+    }
+
+    /// Register a callback that fires when an agent errors or a limit is
+    /// exceeded and the run gives up. Callbacks add up like
+    /// [`on_step`](Self::on_step).
+    pub fn on_error(self, cb: impl FnMut(&ErrorEvent) + Send + 'static) -> Self {
+        self.with_observer(ErrorFn(cb))
+    }
+
+    /// Attach a [`RunObserver`], as [`crate::Runner::with_observer`] does.
+    pub fn with_observer(mut self, observer: impl RunObserver + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Run the workflow to completion, returning the final state or an error.
+    /// Can be called multiple times on the same runner.
+    pub async fn run(&mut self, state: S, ctx: &mut Ctx) -> Result<S, StepError> {
+        let began = Instant::now();
+        let start = RunStartEvent {
+            workflow: &self.wf.name,
+            agent: &self.wf.start,
+            step_number: 0,
+        };
+        for observer in &mut self.observers {
+            observer.on_run_start(&start);
+        }
+
+        let mut step_number = 0;
+        ctx.set_cancel(Cancel::new(self.cancellation.clone(), self.deadline));
+        let result = self.execute(state, ctx, &mut step_number).await;
+        ctx.set_cancel(Cancel::default());
+
+        let end = RunEndEvent {
+            workflow: &self.wf.name,
+            step_number,
+            duration: began.elapsed(),
+            error: result.as_ref().err(),
+            paused: false,
+        };
+        for observer in &mut self.observers {
+            observer.on_run_end(&end);
+        }
+        result
+    }
+
+    async fn execute(
+        &mut self,
+        mut state: S,
+        ctx: &mut Ctx,
+        step_number: &mut usize,
+    ) -> Result<S, StepError> {
+        let mut current = self.wf.start.clone();
+        let mut retries: usize = 0;
+
+        for _ in 0..self.max_steps {
+            if let Err(err) = ctx.cancel().check() {
+                self.report_error(&current, &err, *step_number, retries);
+                return Err(err);
+            }
+            let before = BeforeStepEvent {
+                agent: &current,
+                item: None,
+                completed_steps: *step_number,
+                retries,
+            };
+            self.notify(|o| o.before_step(&before));
+            *step_number += 1;
+            let step = *step_number;
+
+            let agent = self
+                .wf
+                .agents
+                .get_mut(&current)
+                .ok_or_else(|| StepError::other(format!("unknown step: {current}")))?;
+
+            let policy = match self.wf.retry_policies.get(&current) {
+                Some(policy) => policy.clone(),
+                None => RetryPolicy::new(self.max_retries),
+            };
+
+            let start = Instant::now();
+            let result = agent.run_boxed(state.clone(), ctx).await;
+            let duration = start.elapsed();
+
+            let (next_state, outcome) = match result {
+                Ok(step) => step,
+                Err(err) if policy.retries_error(&err) && retries < policy.max_retries() => {
+                    retries += 1;
+                    let delay = policy.backoff(retries);
+                    let retry = RetryEvent {
+                        agent: &current,
+                        step_number: step,
+                        retry: retries,
+                        delay,
+                        error: Some(&err),
+                    };
+                    self.notify(|o| o.on_retry(&retry));
+                    if let Err(err) = ctx.cancel().sleep_async(delay).await {
+                        self.report_error(&current, &err, step, retries);
+                        return Err(err);
+                    }
+                    continue;
+                }
+                Err(err) => match self.wf.error_handler(&current, &err) {
+                    Some(handler) => {
+                        self.catch(ctx, &current, &handler, err, step);
+                        retries = 0;
+                        current = handler;
+                        continue;
+                    }
+                    None => {
+                        self.report_error(&current, &err, step, retries);
+                        return Err(err);
+                    }
+                },
+            };
+
+            let event = StepEvent {
+                agent: &current,
+                item: None,
+                outcome: &outcome,
+                duration,
+                step_number: step,
+                retries,
+            };
+            self.notify(|o| o.after_step(&event));
+
+            state = next_state;
+
+            // Declared edges only apply to `Continue`.
+            let default_next = match outcome {
+                Outcome::Continue => follow_edges(
+                    &self.wf.edges,
+                    &current,
+                    &state,
+                    self.wf.default_next.get(&current).cloned(),
+                ),
+                _ => Ok(None),
+            };
+            let step_retries = retries;
+            let route = match default_next {
+                Ok(default_next) => route(
+                    &current,
+                    outcome,
+                    default_next,
+                    |name| {
+                        self.wf
+                            .agents
+                            .get_key_value(name)
+                            .map(|(step, _)| step.clone())
+                    },
+                    &mut retries,
+                    policy.max_retries(),
+                ),
+                Err(err) => Route::Raised(err),
+            };
+            let raised = matches!(route, Route::Raised(_));
+            match route {
+                Route::Done => return Ok(state),
+                Route::Goto(next) => {
+                    let event = RouteEvent {
+                        from: &current,
+                        to: &next,
+                        step_number: step,
+                    };
+                    self.notify(|o| o.on_route(&event));
+                    current = next;
+                }
+                Route::Rerun => {
+                    let delay = policy.backoff(retries);
+                    let retry = RetryEvent {
+                        agent: &current,
+                        step_number: step,
+                        retry: retries,
+                        delay,
+                        error: None,
+                    };
+                    self.notify(|o| o.on_retry(&retry));
+                    if let Err(err) = ctx.cancel().sleep_async(delay).await {
+                        self.report_error(&current, &err, step, retries);
+                        return Err(err);
+                    }
+                }
+                Route::Sleep(dur) => {
+                    let wait = WaitEvent {
+                        agent: &current,
+                        step_number: step,
+                        duration: dur,
+                    };
+                    self.notify(|o| o.on_wait(&wait));
+                    if let Err(err) = ctx.cancel().sleep_async(dur).await {
+                        self.report_error(&current, &err, step, retries);
+                        return Err(err);
+                    }
+                }
+                Route::Pause { reason, .. } => {
+                    let err = StepError::other(format!(
+                        "workflow paused at '{current}': {reason} (AsyncRunner does not support Outcome::Pause)"
+                    ));
+                    self.report_error(&current, &err, step, step_retries);
+                    return Err(err);
+                }
+                Route::Stop(err) | Route::Raised(err) => {
+                    let Some(handler) = self.wf.error_handler(&current, &err) else {
+                        // As in the sync runner, a `Fail` is already in its
+                        // step event.
+                        if raised {
+                            self.report_error(&current, &err, step, step_retries);
+                        }
+                        return Err(err);
+                    };
+                    self.catch(ctx, &current, &handler, err, step);
+                    retries = 0;
+                    current = handler;
+                }
+            }
+        }
+
+        let err = StepError::other(format!(
+            "max_steps exceeded (possible infinite loop) in workflow {}",
+            self.wf.name
+        ));
+        self.report_error(&current, &err, *step_number, 0);
+        Err(err)
+    }
+
+    /// Hand a failure of `agent` over to `handler` and leave it in
+    /// [`Ctx::caught`].
+    fn catch(
+        &mut self,
+        ctx: &mut Ctx,
+        agent: &str,
+        handler: &str,
+        error: StepError,
+        step_number: usize,
+    ) {
+        let event = CatchEvent {
+            agent,
+            handler,
+            step_number,
+            error: &error,
+        };
+        self.notify(|o| o.on_catch(&event));
+        ctx.set_caught(CaughtError {
+            agent: agent.to_string(),
+            step_number,
+            error,
+        });
+    }
+
+    fn report_error(&mut self, agent: &str, error: &StepError, step_number: usize, retries: usize) {
+        let event = ErrorEvent {
+            agent,
+            item: None,
+            error,
+            step_number,
+            retries,
+        };
+        self.notify(|o| o.on_error(&event));
+    }
+
+    fn notify(&mut self, mut f: impl FnMut(&mut dyn RunObserver)) {
+        for observer in &mut self.observers {
+            f(observer.as_mut());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Outcome, RetryHint};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct S(u32);
+
+    struct AddOne;
+    impl AsyncAgent<S> for AddOne {
+        fn name(&self) -> &'static str {
+            "add_one"
+        }
+        async fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            Ok((S(state.0 + 1), Outcome::Continue))
+        }
+    }
+
+    struct StopAtThree;
+    impl AsyncAgent<S> for StopAtThree {
+        fn name(&self) -> &'static str {
+            "stop"
+        }
+        async fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            if state.0 >= 3 {
+                Ok((state, Outcome::Done))
+            } else {
+                Ok((state, Outcome::next("add_one")))
+            }
+        }
+    }
+
+    struct WaitOnce {
+        waited: bool,
+    }
+    impl AsyncAgent<S> for WaitOnce {
+        fn name(&self) -> &'static str {
+            "wait_once"
+        }
+        async fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+            if !self.waited {
+                self.waited = true;
+                ctx.log("waiting");
+                Ok((state, Outcome::Wait(Duration::from_millis(1))))
+            } else {
+                Ok((state, Outcome::Done))
+            }
+        }
+    }
+
+    struct AlwaysRetry;
+    impl AsyncAgent<S> for AlwaysRetry {
+        fn name(&self) -> &'static str {
+            "always_retry"
+        }
+        async fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            Ok((state, Outcome::Retry(RetryHint::new("never ready"))))
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_like_the_sync_runner() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(AddOne)
+            .register(StopAtThree)
+            .start_at("add_one")
+            .then("stop")
+            .build()
+            .unwrap();
+
+        let steps = Arc::new(Mutex::new(Vec::new()));
+        let steps_clone = Arc::clone(&steps);
+        let mut runner = AsyncRunner::new(wf).on_step(move |e| {
+            steps_clone.lock().unwrap().push(e.agent.to_string());
+        });
+
+        let mut ctx = Ctx::new();
+        let result = runner.run(S(0), &mut ctx).await.unwrap();
+        assert_eq!(result.0, 3);
+        assert_eq!(steps.lock().unwrap().len(), 6);
+    }
+
+    struct Named(String);
+    impl AsyncAgent<S> for Named {
+        fn name(&self) -> &str {
+            &self.0
+        }
+        async fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            Ok((S(state.0 * 10), Outcome::Done))
+        }
+    }
+
+    #[tokio::test]
+    async fn names_can_be_built_at_runtime() {
+        let step = format!("scale-{}", 10);
+        let wf = AsyncWorkflow::builder(String::from("runtime"))
+            .register(AddOne)
+            .register(Named(step.clone()))
+            .start_at("add_one")
+            .then(step)
+            .build()
+            .unwrap();
+        assert_eq!(wf.name(), "runtime");
+
+        let result = AsyncRunner::new(wf).run(S(1), &mut Ctx::new()).await;
+        assert_eq!(result.unwrap().0, 20);
+    }
+
+    #[tokio::test]
+    async fn wait_sleeps_and_reruns() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(WaitOnce { waited: false })
+            .build()
+            .unwrap();
+
+        let mut ctx = Ctx::new();
+        AsyncRunner::new(wf).run(S(0), &mut ctx).await.unwrap();
+        assert_eq!(ctx.logs(), &["waiting"]);
+    }
+
+    #[tokio::test]
+    async fn retry_exceeds_limit_and_fires_on_error() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(AlwaysRetry)
+            .build()
+            .unwrap();
+
+        let count = Arc::new(Mutex::new(0usize));
+        let count_clone = Arc::clone(&count);
+        let mut runner = AsyncRunner::new(wf)
+            .with_max_retries(2)
+            .on_error(move |_e| *count_clone.lock().unwrap() += 1);
+
+        let mut ctx = Ctx::new();
+        let err = runner.run(S(0), &mut ctx).await.err().unwrap();
+        assert!(err.to_string().contains("exceeded max retries"));
+        assert_eq!(*count.lock().unwrap(), 1);
+    }
+
+    struct Flaky {
+        failures: u32,
+    }
+    impl AsyncAgent<S> for Flaky {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+        async fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(StepError::invalid("not yet"));
+            }
+            Ok((S(state.0 + 1), Outcome::Continue))
+        }
+    }
+
+    struct Log(&'static str);
+    impl AsyncAgent<S> for Log {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        async fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+            ctx.log(self.0);
+            Ok((state, Outcome::Done))
+        }
+    }
+
+    #[tokio::test]
+    async fn when_and_branch_edges_route_continue() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(AddOne)
+            .register(Log("small"))
+            .register(Log("big"))
+            .register(Log("never"))
+            .start_at("add_one")
+            .when(|s: &S| s.0 > 100, "never")
+            .branch(
+                "add_one",
+                |s: &S| if s.0 > 5 { "big" } else { "small" },
+                ["small", "big"],
+            )
+            .then("never")
+            .build()
+            .unwrap();
+        let mut runner = AsyncRunner::new(wf);
+
+        let mut ctx = Ctx::new();
+        runner.run(S(0), &mut ctx).await.unwrap();
+        assert_eq!(ctx.logs(), &["small"]);
+
+        let mut ctx = Ctx::new();
+        runner.run(S(9), &mut ctx).await.unwrap();
+        assert_eq!(ctx.logs(), &["big"]);
+    }
+
+    #[tokio::test]
+    async fn retry_policy_retries_chosen_errors_with_backoff() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(Flaky { failures: 2 })
+            .register(Log("done"))
+            .then("done")
+            .retry_policy(
+                "flaky",
+                RetryPolicy::new(2)
+                    .with_backoff(Duration::from_millis(20), Duration::from_millis(20))
+                    .retry_on(|e| matches!(e, StepError::Invalid(_))),
+            )
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        let result = AsyncRunner::new(wf)
+            .with_max_retries(0)
+            .run(S(0), &mut Ctx::new())
+            .await
+            .unwrap();
+        assert_eq!(result.0, 1);
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn hooks_stack() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(AlwaysRetry)
+            .build()
+            .unwrap();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (first, second) = (Arc::clone(&seen), Arc::clone(&seen));
+        let mut runner = AsyncRunner::new(wf)
+            .with_max_retries(1)
+            .on_step(move |_| first.lock().unwrap().push("step"))
+            .on_error(move |_| second.lock().unwrap().push("error"));
+        let third = Arc::clone(&seen);
+        runner = runner.on_error(move |_| third.lock().unwrap().push("error again"));
+
+        assert!(runner.run(S(0), &mut Ctx::new()).await.is_err());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["step", "step", "error", "error again"]
+        );
+    }
+
+    #[test]
+    fn build_rejects_unknown_then_target() {
+        let err = AsyncWorkflow::builder("test")
+            .register(AddOne)
+            .then("missing")
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, WorkflowError::UnknownStep(name) if name == "missing"));
+    }
+
+    #[tokio::test]
+    async fn on_error_and_catch_route_failures_to_handlers() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(Flaky { failures: 1 })
+            .register(Log("handled"))
+            .register(AlwaysRetry)
+            .register(Log("caught"))
+            .on_error("flaky", "handled")
+            .catch("caught")
+            .build()
+            .unwrap();
+        let mut runner = AsyncRunner::new(wf).with_max_retries(0);
+
+        let mut ctx = Ctx::new();
+        runner.run(S(0), &mut ctx).await.unwrap();
+        assert_eq!(ctx.logs(), &["handled"]);
+        let caught = ctx.caught().unwrap();
+        assert_eq!(caught.agent, "flaky");
+        assert!(matches!(caught.error, StepError::Invalid(_)));
+
+        let wf = AsyncWorkflow::builder("test")
+            .register(AlwaysRetry)
+            .register(Log("caught"))
+            .catch("caught")
+            .build()
+            .unwrap();
+        let mut ctx = Ctx::new();
+        AsyncRunner::new(wf)
+            .with_max_retries(0)
+            .run(S(0), &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(ctx.logs(), &["caught"]);
+        assert_eq!(ctx.caught().unwrap().agent, "always_retry");
+    }
+
+    #[test]
+    fn build_rejects_unknown_handlers() {
+        let err = AsyncWorkflow::builder("test")
+            .register(AddOne)
+            .on_error("add_one", "missing")
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, WorkflowError::UnknownStep(name) if name == "missing"));
+
+        let err = AsyncWorkflow::builder("test")
+            .register(AddOne)
+            .catch("nowhere")
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, WorkflowError::UnknownStep(name) if name == "nowhere"));
+    }
+
+    #[derive(Default)]
+    struct Lifecycle(Arc<Mutex<Vec<String>>>);
+    impl RunObserver for Lifecycle {
+        fn on_run_start(&mut self, event: &RunStartEvent) {
+            let mut seen = self.0.lock().unwrap();
+            seen.push(format!("start {} at {}", event.workflow, event.agent));
+        }
+        fn before_step(&mut self, event: &BeforeStepEvent) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("before {}", event.agent));
+        }
+        fn after_step(&mut self, event: &StepEvent) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("after {}", event.agent));
+        }
+        fn on_route(&mut self, event: &RouteEvent) {
+            let mut seen = self.0.lock().unwrap();
+            seen.push(format!("route {} -> {}", event.from, event.to));
+        }
+        fn on_catch(&mut self, event: &CatchEvent) {
+            let mut seen = self.0.lock().unwrap();
+            seen.push(format!("catch {} -> {}", event.agent, event.handler));
+        }
+        fn on_run_end(&mut self, event: &RunEndEvent) {
+            let mut seen = self.0.lock().unwrap();
+            seen.push(format!("end after {} steps", event.step_number));
+        }
+    }
+
+    #[tokio::test]
+    async fn observers_see_the_run_lifecycle() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(Flaky { failures: 1 })
+            .register(AddOne)
+            .register(StopAtThree)
+            .start_at("add_one")
+            .then("stop")
+            .start_at("flaky")
+            .on_error("flaky", "add_one")
+            .build()
+            .unwrap();
+        let observer = Lifecycle::default();
+        let seen = Arc::clone(&observer.0);
+        let mut runner = AsyncRunner::new(wf)
+            .with_max_retries(0)
+            .with_observer(observer);
+
+        let result = runner.run(S(2), &mut Ctx::new()).await.unwrap();
+        assert_eq!(result.0, 3);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                "start test at flaky",
+                "before flaky",
+                "catch flaky -> add_one",
+                "before add_one",
+                "after add_one",
+                "route add_one -> stop",
+                "before stop",
+                "after stop",
+                "end after 3 steps",
+            ]
+        );
+    }
+
+    struct WaitAMinute;
+    impl AsyncAgent<S> for WaitAMinute {
+        fn name(&self) -> &'static str {
+            "wait"
+        }
+        async fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            Ok((state, Outcome::Wait(Duration::from_secs(60))))
+        }
+    }
+
+    #[tokio::test]
+    async fn cancellation_wakes_a_waiting_run() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(WaitAMinute)
+            .build()
+            .unwrap();
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let errors_clone = Arc::clone(&errors);
+        let mut runner = AsyncRunner::new(wf)
+            .with_cancellation(token)
+            .on_error(move |e| errors_clone.lock().unwrap().push(e.agent.to_string()));
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            cancel.cancel();
+        });
+        let start = Instant::now();
+        let err = runner.run(S(0), &mut Ctx::new()).await.err().unwrap();
+        assert!(matches!(err, StepError::Cancelled(_)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(*errors.lock().unwrap(), ["wait"]);
+    }
+
+    #[tokio::test]
+    async fn deadline_fails_the_run() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(AlwaysRetry)
+            .retry_policy(
+                "always_retry",
+                RetryPolicy::new(1_000)
+                    .with_backoff(Duration::from_millis(10), Duration::from_millis(10)),
+            )
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        let err = AsyncRunner::new(wf)
+            .with_deadline(Duration::from_millis(50))
+            .run(S(0), &mut Ctx::new())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, StepError::Cancelled(_)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn run_future_is_send() {
+        let wf = AsyncWorkflow::builder("test")
+            .register(AddOne)
+            .register(StopAtThree)
+            .start_at("add_one")
+            .then("stop")
+            .build()
+            .unwrap();
+
+        let handle = tokio::spawn(async move {
+            let mut ctx = Ctx::new();
+            AsyncRunner::new(wf).run(S(0), &mut ctx).await
+        });
+        assert_eq!(handle.await.unwrap().unwrap().0, 3);
+    }
+}
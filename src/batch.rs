@@ -0,0 +1,419 @@
+use crate::{Ctx, Runner, StepError, Workflow, WorkflowError};
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The run of one input, handed back by a [`BatchRunner`].
+pub struct BatchItem<S> {
+    /// Position of the input in the batch.
+    pub index: usize,
+    /// The final state, or why the run failed.
+    pub result: Result<S, StepError>,
+    /// The run's own context, with its store and log.
+    pub ctx: Ctx,
+    /// Wall-clock time for the run.
+    pub duration: Duration,
+}
+
+/// How far a batch has got, passed to [`BatchRunner::on_progress`] after
+/// each input finishes.
+pub struct BatchProgress {
+    /// Inputs finished so far, failed ones included.
+    pub completed: usize,
+    /// Inputs that failed so far.
+    pub failed: usize,
+    /// Number of inputs in the batch.
+    pub total: usize,
+    /// Time since the batch started.
+    pub elapsed: Duration,
+}
+
+/// Every run of a batch, in input order, plus totals.
+pub struct BatchReport<S> {
+    /// One entry per input, in the order the inputs were given.
+    pub items: Vec<BatchItem<S>>,
+    /// Runs that finished.
+    pub succeeded: usize,
+    /// Runs that failed.
+    pub failed: usize,
+    /// Wall-clock time for the whole batch.
+    pub duration: Duration,
+}
+
+impl<S> BatchReport<S> {
+    /// Whether every run finished.
+    pub fn is_ok(&self) -> bool {
+        self.failed == 0
+    }
+
+    /// The final states in input order, or the first error.
+    pub fn into_results(self) -> Result<Vec<S>, StepError> {
+        self.items.into_iter().map(|item| item.result).collect()
+    }
+}
+
+type Configure<S> = Box<dyn Fn(Runner<S>) -> Runner<S> + Sync>;
+type ProgressFn = Box<dyn FnMut(&BatchProgress)>;
+
+/// Runs one workflow per input on a pool of worker threads.
+///
+/// Each input gets a workflow fresh from the factory, so agents never share
+/// state between inputs, its own [`Runner`] and an empty [`Ctx`]. The
+/// factory and [`configure`](Self::configure) are called on the worker
+/// thread that runs the input.
+///
+/// ```rust
+/// use agent_line::{Agent, BatchRunner, Ctx, Outcome, StepResult, Workflow};
+///
+/// struct Shout;
+/// impl Agent<String> for Shout {
+///     fn name(&self) -> &'static str { "shout" }
+///     fn run(&mut self, topic: String, _ctx: &mut Ctx) -> StepResult<String> {
+///         Ok((topic.to_uppercase(), Outcome::Done))
+///     }
+/// }
+///
+/// let report = BatchRunner::new(|| Workflow::builder("shout").register(Shout).build())
+///     .concurrency(4)
+///     .on_progress(|p| eprintln!("{}/{} done", p.completed, p.total))
+///     .run_all(vec!["rust".to_string(), "plumbing".to_string()]);
+///
+/// assert_eq!(report.into_results().unwrap(), ["RUST", "PLUMBING"]);
+/// ```
+pub struct BatchRunner<S: Clone + Send + 'static, F> {
+    factory: F,
+    concurrency: usize,
+    configure: Option<Configure<S>>,
+    progress: Option<ProgressFn>,
+    ordered: bool,
+}
+
+impl<S, F> BatchRunner<S, F>
+where
+    S: Clone + Send + 'static,
+    F: Fn() -> Result<Workflow<S>, WorkflowError> + Sync,
+{
+    /// Create a batch runner that builds each input's workflow with
+    /// `factory`. Runs as many inputs at once as the machine has cores.
+    pub fn new(factory: F) -> Self {
+        Self {
+            factory,
+            concurrency: std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            configure: None,
+            progress: None,
+            ordered: false,
+        }
+    }
+
+    /// Run at most `n` inputs at once (at least one).
+    pub fn concurrency(mut self, n: usize) -> Self {
+        self.concurrency = n.max(1);
+        self
+    }
+
+    /// Set up each input's runner: limits, hooks, observers, timeouts.
+    pub fn configure(mut self, f: impl Fn(Runner<S>) -> Runner<S> + Sync + 'static) -> Self {
+        self.configure = Some(Box::new(f));
+        self
+    }
+
+    /// Call `f` on the calling thread each time an input finishes.
+    pub fn on_progress(mut self, f: impl FnMut(&BatchProgress) + 'static) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Make [`for_each`](Self::for_each) hand back results in input order
+    /// rather than as they finish.
+    pub fn ordered(mut self) -> Self {
+        self.ordered = true;
+        self
+    }
+
+    /// Run every input and collect the results in input order.
+    pub fn run_all(&mut self, inputs: impl IntoIterator<Item = S>) -> BatchReport<S> {
+        let began = Instant::now();
+        let mut items = Vec::new();
+        self.for_each(inputs, |item| items.push(item));
+        items.sort_by_key(|item| item.index);
+        let failed = items.iter().filter(|item| item.result.is_err()).count();
+        BatchReport {
+            succeeded: items.len() - failed,
+            failed,
+            items,
+            duration: began.elapsed(),
+        }
+    }
+
+    /// Run every input, calling `f` on the calling thread with each result
+    /// as soon as it is available: as runs finish, or in input order with
+    /// [`ordered`](Self::ordered).
+    pub fn for_each(
+        &mut self,
+        inputs: impl IntoIterator<Item = S>,
+        mut f: impl FnMut(BatchItem<S>),
+    ) {
+        let began = Instant::now();
+        let inputs: Vec<S> = inputs.into_iter().collect();
+        let total = inputs.len();
+        let queue = Mutex::new(inputs.into_iter().enumerate());
+        let workers = self.concurrency.min(total);
+        let (done, finished) = mpsc::channel();
+
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                let done = done.clone();
+                let queue = &queue;
+                let factory = &self.factory;
+                let configure = self.configure.as_deref();
+                scope.spawn(move || {
+                    loop {
+                        let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                        let Some((index, input)) = next else { break };
+                        if done
+                            .send(run_one(factory, configure, index, input))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(done);
+
+            let mut failed = 0;
+            let mut waiting = BTreeMap::new();
+            let mut next_index = 0;
+            for (completed, item) in finished.iter().enumerate() {
+                failed += usize::from(item.result.is_err());
+                if let Some(progress) = self.progress.as_mut() {
+                    progress(&BatchProgress {
+                        completed: completed + 1,
+                        failed,
+                        total,
+                        elapsed: began.elapsed(),
+                    });
+                }
+                if !self.ordered {
+                    f(item);
+                    continue;
+                }
+                waiting.insert(item.index, item);
+                while let Some(item) = waiting.remove(&next_index) {
+                    f(item);
+                    next_index += 1;
+                }
+            }
+        });
+    }
+}
+
+/// Build and run the workflow for one input, turning a panic into an error.
+fn run_one<S, F>(
+    factory: &F,
+    configure: Option<&(dyn Fn(Runner<S>) -> Runner<S> + Sync)>,
+    index: usize,
+    input: S,
+) -> BatchItem<S>
+where
+    S: Clone + Send + 'static,
+    F: Fn() -> Result<Workflow<S>, WorkflowError>,
+{
+    let began = Instant::now();
+    let mut ctx = Ctx::new();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let wf = factory().map_err(|e| StepError::invalid(format!("invalid workflow: {e}")))?;
+        let mut runner = Runner::new(wf);
+        if let Some(configure) = configure {
+            runner = configure(runner);
+        }
+        runner.run(input, &mut ctx)
+    }))
+    .unwrap_or_else(|_| Err(StepError::other(format!("batch input {index} panicked"))));
+    BatchItem {
+        index,
+        result,
+        ctx,
+        duration: began.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Agent, Outcome, StepResult};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::{Arc, Condvar, Mutex};
+
+    /// Counts its own runs, so shared instances would show up.
+    #[derive(Default)]
+    struct Double {
+        runs: u32,
+    }
+
+    impl Agent<u32> for Double {
+        fn name(&self) -> &'static str {
+            "double"
+        }
+        fn run(&mut self, n: u32, ctx: &mut Ctx) -> StepResult<u32> {
+            self.runs += 1;
+            ctx.log(format!("doubling {n}"));
+            match n {
+                0 => Err(StepError::invalid("zero")),
+                13 => panic!("unlucky"),
+                n => Ok((n * 2 + self.runs - 1, Outcome::Done)),
+            }
+        }
+    }
+
+    /// Holds each input back until the gate is opened for it.
+    #[derive(Clone, Default)]
+    struct Gate(Arc<(Mutex<u32>, Condvar)>);
+
+    impl Gate {
+        fn open(&self, n: u32) {
+            *self.0.0.lock().unwrap() = n;
+            self.0.1.notify_all();
+        }
+
+        fn wait_for(&self, n: u32) {
+            let open = self.0.0.lock().unwrap();
+            drop(self.0.1.wait_while(open, |open| *open != n).unwrap());
+        }
+    }
+
+    /// Finishes once the gate opens for its input, and with `pass_on`
+    /// opens it for the input below.
+    struct Gated {
+        gate: Gate,
+        pass_on: bool,
+    }
+
+    impl Agent<u32> for Gated {
+        fn name(&self) -> &'static str {
+            "gated"
+        }
+        fn run(&mut self, n: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            self.gate.wait_for(n);
+            if self.pass_on {
+                self.gate.open(n - 1);
+            }
+            Ok((n, Outcome::Done))
+        }
+    }
+
+    fn gated(
+        gate: &Gate,
+        pass_on: bool,
+    ) -> BatchRunner<u32, impl Fn() -> Result<Workflow<u32>, WorkflowError> + Sync> {
+        let gate = gate.clone();
+        BatchRunner::new(move || {
+            Workflow::builder("gated")
+                .register(Gated {
+                    gate: gate.clone(),
+                    pass_on,
+                })
+                .build()
+        })
+        .concurrency(4)
+    }
+
+    fn batch() -> BatchRunner<u32, impl Fn() -> Result<Workflow<u32>, WorkflowError> + Sync> {
+        BatchRunner::new(|| {
+            Workflow::builder("double")
+                .register(Double::default())
+                .build()
+        })
+        .concurrency(3)
+    }
+
+    #[test]
+    fn run_all_keeps_input_order_with_per_item_errors() {
+        let report = batch().run_all([1, 2, 0, 3, 4, 13, 5]);
+
+        assert_eq!(report.items.len(), 7);
+        assert_eq!((report.succeeded, report.failed), (5, 2));
+        assert!(!report.is_ok());
+        let indexes: Vec<_> = report.items.iter().map(|item| item.index).collect();
+        assert_eq!(indexes, [0, 1, 2, 3, 4, 5, 6]);
+
+        let results: Vec<_> = report
+            .items
+            .iter()
+            .map(|item| item.result.as_ref().ok())
+            .collect();
+        assert_eq!(
+            results,
+            [
+                Some(&2),
+                Some(&4),
+                None,
+                Some(&6),
+                Some(&8),
+                None,
+                Some(&10)
+            ]
+        );
+        assert!(
+            report.items[5]
+                .result
+                .as_ref()
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("panicked")
+        );
+        assert_eq!(report.items[3].ctx.logs(), ["doubling 3"]);
+    }
+
+    #[test]
+    fn for_each_streams_as_completed_or_in_order() {
+        // The last input finishes first, and each one handed back lets the
+        // one before it finish.
+        let gate = Gate::default();
+        gate.open(4);
+        let progress = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&progress);
+        let mut runner = gated(&gate, false)
+            .on_progress(move |p| seen.borrow_mut().push((p.completed, p.total)));
+        let mut finished = Vec::new();
+        runner.for_each([1, 2, 3, 4], |item| {
+            finished.push(item.index);
+            gate.open(item.index as u32);
+        });
+        assert_eq!(finished, [3, 2, 1, 0]);
+        assert_eq!(*progress.borrow(), [(1, 4), (2, 4), (3, 4), (4, 4)]);
+
+        // Inputs still finish last first, but are handed back in order.
+        let gate = Gate::default();
+        gate.open(4);
+        let mut in_order = Vec::new();
+        gated(&gate, true)
+            .ordered()
+            .for_each([1, 2, 3, 4], |item| in_order.push(item.index));
+        assert_eq!(in_order, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn configure_and_build_errors_apply_per_input() {
+        let report = batch()
+            .configure(|runner| runner.with_max_steps(0))
+            .run_all([1, 2]);
+        assert_eq!(report.failed, 2);
+        assert!(report.into_results().is_err());
+
+        let mut broken =
+            BatchRunner::new(|| Workflow::<u32>::builder("empty").build()).concurrency(2);
+        let report = broken.run_all([1]);
+        let err = report.items[0].result.as_ref().err().unwrap();
+        assert!(err.to_string().contains("invalid workflow"));
+
+        let report = batch().run_all(Vec::new());
+        assert!(report.is_ok());
+        assert!(report.items.is_empty());
+    }
+}
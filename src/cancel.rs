@@ -0,0 +1,207 @@
+use crate::StepError;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A cloneable handle for stopping a run from another thread.
+///
+/// Pass it to [`crate::Runner::with_cancellation`] (or the async runner's)
+/// and keep a clone. After [`cancel`](Self::cancel) the runner stops before
+/// its next step, and any `Outcome::Wait` sleep in progress wakes up
+/// immediately. Agents can poll
+/// [`crate::Ctx::is_cancelled`] to stop long work early.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Signal>,
+}
+
+#[derive(Default)]
+struct Signal {
+    cancelled: Mutex<bool>,
+    wake: Condvar,
+    /// Wakes async sleeps, which can't wait on the condvar.
+    #[cfg(feature = "async")]
+    notify: tokio::sync::Notify,
+}
+
+impl CancellationToken {
+    /// Create a token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every run using this token or one of its clones.
+    pub fn cancel(&self) {
+        *self.inner.cancelled.lock().unwrap() = true;
+        self.inner.wake.notify_all();
+        #[cfg(feature = "async")]
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Whether [`cancel`](Self::cancel) has been called.
+    pub fn is_cancelled(&self) -> bool {
+        *self.inner.cancelled.lock().unwrap()
+    }
+
+    /// Sleep until `until` or until cancelled, whichever comes first.
+    fn sleep_until(&self, until: Instant) {
+        let mut cancelled = self.inner.cancelled.lock().unwrap();
+        while !*cancelled {
+            let now = Instant::now();
+            if now >= until {
+                return;
+            }
+            cancelled = self
+                .inner
+                .wake
+                .wait_timeout(cancelled, until - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Wait until cancelled.
+    #[cfg(feature = "async")]
+    async fn cancelled(&self) {
+        loop {
+            // Register for the wake-up before checking, so a cancel in
+            // between isn't missed.
+            let mut notified = std::pin::pin!(self.inner.notify.notified());
+            notified.as_mut().enable();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// What can stop one run: the runner's token and its deadline, if any.
+/// Lives in [`crate::Ctx`] for the duration of a run.
+#[derive(Clone, Default)]
+pub(crate) struct Cancel {
+    token: Option<CancellationToken>,
+    deadline: Option<(Instant, Duration)>,
+}
+
+impl Cancel {
+    /// A deadline of `limit` counts from now.
+    pub(crate) fn new(token: Option<CancellationToken>, limit: Option<Duration>) -> Self {
+        Self {
+            token,
+            deadline: limit.map(|limit| (Instant::now() + limit, limit)),
+        }
+    }
+
+    /// `Err(StepError::Cancelled)` once cancelled or past the deadline.
+    pub(crate) fn check(&self) -> Result<(), StepError> {
+        if self.token.as_ref().is_some_and(|t| t.is_cancelled()) {
+            return Err(StepError::Cancelled("cancellation requested".into()));
+        }
+        if let Some((deadline, limit)) = self.deadline
+            && Instant::now() >= deadline
+        {
+            return Err(StepError::Cancelled(format!(
+                "deadline of {limit:?} exceeded"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Sleep for `dur`, waking early if the run is cancelled or reaches its
+    /// deadline first.
+    pub(crate) fn sleep(&self, dur: Duration) -> Result<(), StepError> {
+        let mut until = Instant::now() + dur;
+        if let Some((deadline, _)) = self.deadline {
+            until = until.min(deadline);
+        }
+        match &self.token {
+            Some(token) => token.sleep_until(until),
+            None => std::thread::sleep(until.saturating_duration_since(Instant::now())),
+        }
+        self.check()
+    }
+
+    /// [`sleep`](Self::sleep) without blocking the thread, for async runs.
+    #[cfg(feature = "async")]
+    pub(crate) async fn sleep_async(&self, dur: Duration) -> Result<(), StepError> {
+        let mut until = Instant::now() + dur;
+        if let Some((deadline, _)) = self.deadline {
+            until = until.min(deadline);
+        }
+        let until = tokio::time::Instant::from_std(until);
+        match &self.token {
+            Some(token) => {
+                let _ = tokio::time::timeout_at(until, token.cancelled()).await;
+            }
+            None => tokio::time::sleep_until(until).await,
+        }
+        self.check()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_cancellation() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!token.is_cancelled());
+        clone.cancel();
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn check_reports_cancel_and_deadline() {
+        assert!(Cancel::default().check().is_ok());
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let err = Cancel::new(Some(token), None).check().unwrap_err();
+        assert!(matches!(err, StepError::Cancelled(_)));
+
+        let err = Cancel::new(None, Some(Duration::ZERO)).check().unwrap_err();
+        assert!(err.to_string().contains("deadline"));
+    }
+
+    #[test]
+    fn cancel_wakes_a_sleep() {
+        let token = CancellationToken::new();
+        let cancel = Cancel::new(Some(token.clone()), None);
+
+        let start = Instant::now();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                token.cancel();
+            });
+            assert!(cancel.sleep(Duration::from_secs(10)).is_err());
+        });
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn sleep_stops_at_deadline() {
+        let cancel = Cancel::new(None, Some(Duration::from_millis(10)));
+        let start = Instant::now();
+        assert!(cancel.sleep(Duration::from_secs(10)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn cancel_wakes_an_async_sleep() {
+        let token = CancellationToken::new();
+        let cancel = Cancel::new(Some(token.clone()), None);
+
+        let start = Instant::now();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        });
+        assert!(cancel.sleep_async(Duration::from_secs(10)).await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        canceller.join().unwrap();
+    }
+}
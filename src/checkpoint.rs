@@ -0,0 +1,164 @@
+use crate::{Ctx, StepError};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// A snapshot of a run between two steps, written by the [`crate::Runner`]
+/// after every step when checkpointing is enabled.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The id the run was started or resumed under.
+    pub run_id: String,
+    /// Name of the workflow being run.
+    pub workflow: String,
+    /// The agent that runs next, or `None` once the run has finished.
+    pub agent: Option<String>,
+    /// Consecutive retry count for `agent`.
+    pub retries: usize,
+    /// Number of steps completed so far.
+    pub step_number: usize,
+    /// The workflow state, serialized as JSON.
+    pub state: serde_json::Value,
+    /// The context store and log at the time of the checkpoint.
+    pub ctx: Ctx,
+}
+
+/// Somewhere to keep the latest [`Checkpoint`] of each run.
+///
+/// Only the most recent checkpoint per `run_id` needs to be kept;
+/// [`crate::Runner::resume`] always continues from the last one saved.
+pub trait CheckpointStore: Send {
+    /// Save `checkpoint`, replacing any earlier one with the same `run_id`.
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<(), StepError>;
+
+    /// Load the latest checkpoint for `run_id`, or `None` if there is none.
+    fn load(&self, run_id: &str) -> Result<Option<Checkpoint>, StepError>;
+}
+
+/// Stores each run's checkpoint as `<dir>/<run_id>.json`.
+///
+/// Files are written to a temporary name and renamed into place, so a crash
+/// mid-write leaves the previous checkpoint intact.
+pub struct FileCheckpointStore {
+    dir: PathBuf,
+}
+
+impl FileCheckpointStore {
+    /// Keep checkpoints in `dir`. The directory is created on first save.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, run_id: &str) -> Result<PathBuf, StepError> {
+        let valid = !run_id.is_empty()
+            && run_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            && !run_id.starts_with('.');
+        if !valid {
+            return Err(StepError::invalid(format!(
+                "run id '{run_id}' must be non-empty ASCII letters, digits, '-', '_' or '.'"
+            )));
+        }
+        Ok(self.dir.join(format!("{run_id}.json")))
+    }
+}
+
+impl CheckpointStore for FileCheckpointStore {
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<(), StepError> {
+        let path = self.path(&checkpoint.run_id)?;
+        let json = serde_json::to_vec_pretty(checkpoint)
+            .map_err(|e| StepError::other(format!("failed to serialize checkpoint: {e}")))?;
+
+        std::fs::create_dir_all(&self.dir)?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn load(&self, run_id: &str) -> Result<Option<Checkpoint>, StepError> {
+        let path = self.path(run_id)?;
+        let json = match std::fs::read(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_slice(&json)
+            .map(Some)
+            .map_err(|e| StepError::other(format!("corrupt checkpoint {}: {e}", path.display())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "agent-line-checkpoint-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn checkpoint(run_id: &str, step_number: usize) -> Checkpoint {
+        let mut ctx = Ctx::new();
+        ctx.set("k", "v");
+        ctx.log("hello");
+        Checkpoint {
+            run_id: run_id.into(),
+            workflow: "wf".into(),
+            agent: Some("writer".into()),
+            retries: 1,
+            step_number,
+            state: serde_json::json!({ "n": 3 }),
+            ctx,
+        }
+    }
+
+    #[test]
+    fn file_store_round_trips() {
+        let dir = temp_dir("round-trip");
+        let mut store = FileCheckpointStore::new(&dir);
+        store.save(&checkpoint("run-1", 4)).unwrap();
+
+        let loaded = store.load("run-1").unwrap().unwrap();
+        assert_eq!(loaded.workflow, "wf");
+        assert_eq!(loaded.agent.as_deref(), Some("writer"));
+        assert_eq!(loaded.retries, 1);
+        assert_eq!(loaded.step_number, 4);
+        assert_eq!(loaded.state["n"], 3);
+        assert_eq!(loaded.ctx.get("k"), Some("v"));
+        assert_eq!(loaded.ctx.logs(), ["hello"]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn file_store_keeps_latest_checkpoint() {
+        let dir = temp_dir("latest");
+        let mut store = FileCheckpointStore::new(&dir);
+        store.save(&checkpoint("run-1", 1)).unwrap();
+        store.save(&checkpoint("run-1", 2)).unwrap();
+
+        assert_eq!(store.load("run-1").unwrap().unwrap().step_number, 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn file_store_missing_run_is_none() {
+        let store = FileCheckpointStore::new(temp_dir("missing"));
+        assert!(store.load("nope").unwrap().is_none());
+    }
+
+    #[test]
+    fn file_store_rejects_path_like_run_ids() {
+        let mut store = FileCheckpointStore::new(temp_dir("bad-id"));
+        for run_id in ["", "../escape", "a/b", ".hidden"] {
+            let err = store.save(&checkpoint(run_id, 1)).unwrap_err();
+            assert!(matches!(err, StepError::Invalid(_)), "{run_id}");
+        }
+    }
+}
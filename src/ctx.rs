@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use crate::StepError;
+use crate::cancel::Cancel;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
 /// Execution context shared across all agents in a workflow.
 ///
@@ -13,9 +16,18 @@ use std::collections::HashMap;
 /// LLM access lives on [`crate::LlmConfig`], not on `Ctx`. Agents that need
 /// an LLM hold their own [`crate::LlmConfig`] and call
 /// [`crate::LlmConfig::request`] to start a chat request.
+///
+/// `Ctx` is serializable so it can be saved in a [`crate::Checkpoint`].
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Ctx {
     store: HashMap<String, String>,
     log: Vec<String>,
+    #[serde(default)]
+    resume_input: Option<String>,
+    #[serde(default)]
+    caught: Option<CaughtError>,
+    #[serde(skip)]
+    cancel: Cancel,
 }
 
 impl Ctx {
@@ -24,6 +36,9 @@ impl Ctx {
         Self {
             store: HashMap::new(),
             log: vec![],
+            resume_input: None,
+            caught: None,
+            cancel: Cancel::default(),
         }
     }
 
@@ -62,6 +77,140 @@ impl Ctx {
         self.store.clear();
         self.log.clear();
     }
+
+    /// The input passed to [`crate::Runner::resume_with`], visible only to
+    /// the first step after resuming.
+    pub fn resume_input(&self) -> Option<&str> {
+        self.resume_input.as_deref()
+    }
+
+    pub(crate) fn set_resume_input(&mut self, input: Option<String>) {
+        self.resume_input = input;
+    }
+
+    /// The most recent failure routed to an error handler (see
+    /// [`crate::WorkflowBuilder::on_error`]). Set before the handler runs
+    /// and kept until the next one is caught or it is taken.
+    pub fn caught(&self) -> Option<&CaughtError> {
+        self.caught.as_ref()
+    }
+
+    /// Take the caught failure out, so later steps no longer see it.
+    pub fn take_caught(&mut self) -> Option<CaughtError> {
+        self.caught.take()
+    }
+
+    pub(crate) fn set_caught(&mut self, caught: CaughtError) {
+        self.caught = Some(caught);
+    }
+
+    /// Whether the current run has been cancelled through its
+    /// [`crate::CancellationToken`] or has passed its deadline. Long-running
+    /// agents can poll this and return early.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.check().is_err()
+    }
+
+    pub(crate) fn cancel(&self) -> &Cancel {
+        &self.cancel
+    }
+
+    pub(crate) fn set_cancel(&mut self, cancel: Cancel) {
+        self.cancel = cancel;
+    }
+
+    /// Fold the changes a forked copy made since `base` back into this
+    /// context: new log lines are appended, changed keys are overwritten and
+    /// removed keys are removed.
+    pub(crate) fn join(&mut self, base: &Ctx, fork: Ctx) {
+        self.log.extend(fork.log.into_iter().skip(base.log.len()));
+        for key in base.store.keys() {
+            if !fork.store.contains_key(key) {
+                self.store.remove(key);
+            }
+        }
+        for (key, value) in fork.store {
+            if base.store.get(&key) != Some(&value) {
+                self.store.insert(key, value);
+            }
+        }
+    }
+
+    /// What changed between this context and `after`.
+    pub(crate) fn diff(&self, after: &Ctx) -> CtxDiff {
+        let mut removed: Vec<String> = self
+            .store
+            .keys()
+            .filter(|key| !after.store.contains_key(*key))
+            .cloned()
+            .collect();
+        removed.sort_unstable();
+        let set = after
+            .store
+            .iter()
+            .filter(|(key, value)| self.store.get(*key) != Some(value))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let cleared_log = !after.log.starts_with(&self.log);
+        let kept = if cleared_log { 0 } else { self.log.len() };
+        CtxDiff {
+            set,
+            removed,
+            cleared_log,
+            log: after.log[kept..].to_vec(),
+            caught: after.caught.clone(),
+        }
+    }
+
+    /// Replay the changes in `diff`, as [`diff`](Self::diff) recorded them.
+    pub(crate) fn apply(&mut self, diff: &CtxDiff) {
+        for key in &diff.removed {
+            self.store.remove(key);
+        }
+        for (key, value) in &diff.set {
+            self.store.insert(key.clone(), value.clone());
+        }
+        if diff.cleared_log {
+            self.log.clear();
+        }
+        self.log.extend(diff.log.iter().cloned());
+        self.resume_input = None;
+        self.caught = diff.caught.clone();
+    }
+}
+
+/// A step failure handed to an error handler, available through
+/// [`Ctx::caught`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaughtError {
+    /// The step that failed, `parent/child` inside nested workflows.
+    pub agent: String,
+    /// Step number of the failed attempt.
+    pub step_number: usize,
+    /// What went wrong: the agent's error, the message of an
+    /// `Outcome::Fail`, or the retry limit it ran into.
+    pub error: StepError,
+}
+
+/// What one step changed in a [`Ctx`], as kept in a
+/// [`crate::StepRecording`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CtxDiff {
+    /// Keys added or changed by the step, with their new values.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, String>,
+    /// Keys the step removed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+    /// Whether the log was cleared before the lines in `log` were added.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cleared_log: bool,
+    /// Log lines added by the step.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub log: Vec<String>,
+    /// The caught failure once the step was over, see [`Ctx::caught`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caught: Option<CaughtError>,
 }
 
 impl Default for Ctx {
@@ -142,6 +291,64 @@ mod tests {
         assert_eq!(ctx.get("key"), None);
     }
 
+    // --- Fork and join ---
+
+    #[test]
+    fn join_applies_fork_changes() {
+        let mut ctx = Ctx::new();
+        ctx.set("keep", "1");
+        ctx.set("change", "old");
+        ctx.set("drop", "x");
+        ctx.log("before");
+
+        let base = ctx.clone();
+        let mut fork = ctx.clone();
+        fork.set("change", "new");
+        fork.set("added", "yes");
+        fork.remove("drop");
+        fork.log("in branch");
+
+        ctx.join(&base, fork);
+        assert_eq!(ctx.get("keep"), Some("1"));
+        assert_eq!(ctx.get("change"), Some("new"));
+        assert_eq!(ctx.get("added"), Some("yes"));
+        assert_eq!(ctx.get("drop"), None);
+        assert_eq!(ctx.logs(), &["before", "in branch"]);
+    }
+
+    #[test]
+    fn diff_applies_back_onto_the_earlier_context() {
+        let mut before = Ctx::new();
+        before.set("keep", "1");
+        before.set("change", "old");
+        before.set("drop", "x");
+        before.log("before");
+
+        let mut after = before.clone();
+        after.set("change", "new");
+        after.remove("drop");
+        after.log("during");
+        let diff = before.diff(&after);
+        assert_eq!(diff.set.len(), 1);
+        assert_eq!(diff.removed, ["drop"]);
+        assert_eq!(diff.log, ["during"]);
+        assert!(!diff.cleared_log);
+
+        let mut replayed = before.clone();
+        replayed.apply(&diff);
+        assert_eq!(replayed.get("change"), Some("new"));
+        assert_eq!(replayed.get("drop"), None);
+        assert_eq!(replayed.logs(), &["before", "during"]);
+
+        after.clear_logs();
+        after.log("fresh");
+        let diff = before.diff(&after);
+        assert!(diff.cleared_log);
+        replayed = before.clone();
+        replayed.apply(&diff);
+        assert_eq!(replayed.logs(), &["fresh"]);
+    }
+
     #[test]
     fn separate_contexts_have_independent_state() {
         let mut a = Ctx::new();
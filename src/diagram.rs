@@ -0,0 +1,496 @@
+use crate::Workflow;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// The shape of a workflow, as collected by [`Workflow::graph`].
+pub(crate) struct Graph<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) start: &'a str,
+    /// Sorted by name.
+    pub(crate) nodes: Vec<GraphNode<'a>>,
+    pub(crate) edges: Vec<GraphEdge<'a>>,
+}
+
+pub(crate) struct GraphNode<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) kind: NodeKind,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum NodeKind {
+    Agent,
+    Parallel,
+    Workflow,
+    Map,
+}
+
+pub(crate) struct GraphEdge<'a> {
+    pub(crate) from: &'a str,
+    pub(crate) to: &'a str,
+    pub(crate) kind: EdgeKind,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum EdgeKind {
+    /// The step's default next, set with `.then()`.
+    Then,
+    /// A `when` edge.
+    When,
+    /// One of a `branch` router's targets.
+    Branch,
+    /// A `Next` target declared in the agent's `routes`.
+    Next,
+    /// A parallel step to one of its branches.
+    Fork,
+    /// A step to its `on_error` or `catch` handler.
+    Error,
+    /// A step to where the run goes once it has used up its visits.
+    Exit,
+}
+
+impl EdgeKind {
+    fn label(self) -> Option<&'static str> {
+        match self {
+            EdgeKind::Then => None,
+            EdgeKind::When => Some("when"),
+            EdgeKind::Branch => Some("branch"),
+            EdgeKind::Next => Some("next"),
+            EdgeKind::Fork => Some("fork"),
+            EdgeKind::Error => Some("on error"),
+            EdgeKind::Exit => Some("loop exit"),
+        }
+    }
+
+    fn dashed(self) -> bool {
+        matches!(
+            self,
+            EdgeKind::Next | EdgeKind::Fork | EdgeKind::Error | EdgeKind::Exit
+        )
+    }
+}
+
+/// How often each step ran and each hop was taken during one run.
+#[derive(Default)]
+struct Path {
+    visits: HashMap<String, usize>,
+    hops: HashMap<(String, String), usize>,
+}
+
+impl Path {
+    /// Count top-level steps only; `parent/child` entries from sub-workflows
+    /// and parallel branches are folded into their parent step.
+    fn from_trace<I, T>(trace: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mut path = Path::default();
+        let mut prev: Option<String> = None;
+        for step in trace {
+            let step = step.as_ref();
+            if step.contains('/') {
+                continue;
+            }
+            *path.visits.entry(step.to_string()).or_default() += 1;
+            if let Some(prev) = prev {
+                *path.hops.entry((prev, step.to_string())).or_default() += 1;
+            }
+            prev = Some(step.to_string());
+        }
+        path
+    }
+
+    fn visits(&self, step: &str) -> usize {
+        self.visits.get(step).copied().unwrap_or(0)
+    }
+
+    fn hops(&self, from: &str, to: &str) -> usize {
+        self.hops
+            .get(&(from.to_string(), to.to_string()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Hops taken that no declared edge covers, e.g. an undeclared `Next`
+    /// or a retry of the same step. Sorted for stable output.
+    fn extra_hops(&self, graph: &Graph<'_>) -> Vec<(&str, &str, usize)> {
+        let mut extra: Vec<_> = self
+            .hops
+            .iter()
+            .filter(|((from, to), _)| !graph.edges.iter().any(|e| e.from == from && e.to == to))
+            .map(|((from, to), &count)| (from.as_str(), to.as_str(), count))
+            .collect();
+        extra.sort_unstable();
+        extra
+    }
+}
+
+impl<S: Clone + Send + 'static> Workflow<S> {
+    /// Render the workflow as a Graphviz DOT digraph.
+    ///
+    /// Shows every step, an arrow into the start step, `.then()` edges as
+    /// solid lines, `when` and `branch` edges labelled as such, and the
+    /// `Next` targets agents declare in [`crate::Agent::routes`] as dashed
+    /// lines. Parallel steps are drawn with dotted edges to their branches,
+    /// and sub-workflows as a single double-bordered box.
+    ///
+    /// ```text
+    /// dot -Tsvg workflow.dot -o workflow.svg
+    /// ```
+    pub fn to_dot(&self) -> String {
+        render_dot(&self.graph(), None)
+    }
+
+    /// Like [`to_dot`](Self::to_dot), with a run's path drawn on top.
+    ///
+    /// `trace` is the step names in the order they ran, as reported by
+    /// [`crate::StepEvent::agent`]. Visited steps show how many times they
+    /// ran, taken edges are highlighted with how often they were taken, and
+    /// hops no declared edge explains are added in red.
+    pub fn to_dot_with_trace<I, T>(&self, trace: I) -> String
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        render_dot(&self.graph(), Some(&Path::from_trace(trace)))
+    }
+
+    /// Render the workflow as a Mermaid flowchart, with the same content as
+    /// [`to_dot`](Self::to_dot). Paste it into a ```` ```mermaid ```` block.
+    pub fn to_mermaid(&self) -> String {
+        render_mermaid(&self.graph(), None)
+    }
+
+    /// Like [`to_mermaid`](Self::to_mermaid), with a run's path drawn on top.
+    /// See [`to_dot_with_trace`](Self::to_dot_with_trace).
+    pub fn to_mermaid_with_trace<I, T>(&self, trace: I) -> String
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        render_mermaid(&self.graph(), Some(&Path::from_trace(trace)))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// DOT
+// ---------------------------------------------------------------------------
+
+const TAKEN: &str = "#1f77b4";
+const UNDECLARED: &str = "#d62728";
+
+fn render_dot(graph: &Graph<'_>, path: Option<&Path>) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "digraph {} {{", dot_quote(graph.name));
+    out.push_str("  rankdir=TB;\n");
+    out.push_str("  node [shape=box, style=rounded];\n");
+    out.push_str("  \"__start\" [shape=point, label=\"\"];\n");
+
+    for node in &graph.nodes {
+        let mut attrs = vec![format!("label={}", dot_quote(&node_label(node.name, path)))];
+        match node.kind {
+            NodeKind::Agent => {}
+            NodeKind::Parallel => attrs.push("shape=parallelogram".into()),
+            NodeKind::Workflow => attrs.push("peripheries=2".into()),
+            NodeKind::Map => attrs.push("shape=box3d".into()),
+        }
+        if path.is_some_and(|p| p.visits(node.name) > 0) {
+            attrs.push(format!("color=\"{TAKEN}\", penwidth=2"));
+        }
+        let _ = writeln!(out, "  {} [{}];", dot_quote(node.name), attrs.join(", "));
+    }
+
+    let _ = writeln!(out, "  \"__start\" -> {};", dot_quote(graph.start));
+    for edge in &graph.edges {
+        let taken = path.map_or(0, |p| p.hops(edge.from, edge.to));
+        let mut attrs = Vec::new();
+        if let Some(label) = edge_label(edge.kind.label(), taken) {
+            attrs.push(format!("label={}", dot_quote(&label)));
+        }
+        match edge.kind {
+            EdgeKind::Next | EdgeKind::Error | EdgeKind::Exit => attrs.push("style=dashed".into()),
+            EdgeKind::Fork => attrs.push("style=dotted".into()),
+            _ => {}
+        }
+        if taken > 0 {
+            attrs.push(format!("color=\"{TAKEN}\", penwidth=2"));
+        }
+        let _ = writeln!(
+            out,
+            "  {} -> {}{};",
+            dot_quote(edge.from),
+            dot_quote(edge.to),
+            dot_attrs(&attrs)
+        );
+    }
+    for (from, to, count) in path.map(|p| p.extra_hops(graph)).unwrap_or_default() {
+        let _ = writeln!(
+            out,
+            "  {} -> {} [label=\"{count}x\", color=\"{UNDECLARED}\", penwidth=2];",
+            dot_quote(from),
+            dot_quote(to)
+        );
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn dot_attrs(attrs: &[String]) -> String {
+    if attrs.is_empty() {
+        String::new()
+    } else {
+        format!(" [{}]", attrs.join(", "))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Mermaid
+// ---------------------------------------------------------------------------
+
+fn render_mermaid(graph: &Graph<'_>, path: Option<&Path>) -> String {
+    // Step names may contain characters Mermaid won't accept in an id, so
+    // nodes get positional ids and the name goes in the label.
+    let ids: HashMap<&str, String> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.name, format!("n{i}")))
+        .collect();
+    let id = |name: &str| ids.get(name).cloned().unwrap_or_else(|| name.to_string());
+
+    let mut out = String::from("flowchart TD\n");
+    out.push_str("    start__((start))\n");
+    for node in &graph.nodes {
+        let label = mermaid_quote(&node_label(node.name, path));
+        let shape = match node.kind {
+            NodeKind::Agent => format!("({label})"),
+            NodeKind::Parallel => format!("[/{label}/]"),
+            NodeKind::Workflow => format!("[[{label}]]"),
+            NodeKind::Map => format!("{{{{{label}}}}}"),
+        };
+        let _ = writeln!(out, "    {}{shape}", id(node.name));
+    }
+
+    let _ = writeln!(out, "    start__ --> {}", id(graph.start));
+    // Mermaid styles links by their position, counting from the start link.
+    let mut link = 1;
+    let mut taken_links = Vec::new();
+    let mut undeclared_links = Vec::new();
+    for edge in &graph.edges {
+        let taken = path.map_or(0, |p| p.hops(edge.from, edge.to));
+        let arrow = if edge.kind.dashed() { "-.->" } else { "-->" };
+        let label = edge_label(edge.kind.label(), taken)
+            .map(|l| format!("|{}|", mermaid_quote(&l)))
+            .unwrap_or_default();
+        let _ = writeln!(out, "    {} {arrow}{label} {}", id(edge.from), id(edge.to));
+        if taken > 0 {
+            taken_links.push(link);
+        }
+        link += 1;
+    }
+    for (from, to, count) in path.map(|p| p.extra_hops(graph)).unwrap_or_default() {
+        let _ = writeln!(out, "    {} -->|\"{count}x\"| {}", id(from), id(to));
+        undeclared_links.push(link);
+        link += 1;
+    }
+
+    if let Some(path) = path {
+        let visited: Vec<String> = graph
+            .nodes
+            .iter()
+            .filter(|node| path.visits(node.name) > 0)
+            .map(|node| id(node.name))
+            .collect();
+        if !visited.is_empty() {
+            let _ = writeln!(out, "    classDef visited stroke:{TAKEN},stroke-width:2px");
+            let _ = writeln!(out, "    class {} visited", visited.join(","));
+        }
+    }
+    for (links, color) in [(taken_links, TAKEN), (undeclared_links, UNDECLARED)] {
+        if !links.is_empty() {
+            let links: Vec<String> = links.iter().map(usize::to_string).collect();
+            let _ = writeln!(
+                out,
+                "    linkStyle {} stroke:{color},stroke-width:2px",
+                links.join(",")
+            );
+        }
+    }
+    out
+}
+
+fn mermaid_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "#quot;"))
+}
+
+// ---------------------------------------------------------------------------
+// Shared
+// ---------------------------------------------------------------------------
+
+fn node_label(name: &str, path: Option<&Path>) -> String {
+    match path.map_or(0, |p| p.visits(name)) {
+        0 => name.to_string(),
+        visits => format!("{name} ({visits}x)"),
+    }
+}
+
+fn edge_label(kind: Option<&str>, taken: usize) -> Option<String> {
+    match (kind, taken) {
+        (None, 0) => None,
+        (Some(kind), 0) => Some(kind.to_string()),
+        (None, taken) => Some(format!("{taken}x")),
+        (Some(kind), taken) => Some(format!("{kind} {taken}x")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Agent, Ctx, Outcome, Routes, StepResult, Workflow};
+
+    struct Step {
+        name: &'static str,
+        routes: Option<Routes>,
+    }
+
+    impl Step {
+        fn new(name: &'static str) -> Self {
+            Self { name, routes: None }
+        }
+    }
+
+    impl Agent<u32> for Step {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+        fn run(&mut self, state: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            Ok((state, Outcome::Continue))
+        }
+        fn routes(&self) -> Option<Routes> {
+            self.routes.clone()
+        }
+    }
+
+    fn coding() -> Workflow<u32> {
+        Workflow::builder("coding")
+            .register(Step::new("planner"))
+            .register(Step::new("coder"))
+            .register(Step {
+                name: "tester",
+                routes: Some(Routes::new().done().next("coder")),
+            })
+            .register(Step::new("publisher"))
+            .start_at("planner")
+            .then("coder")
+            .then("tester")
+            .when(|n: &u32| *n > 2, "publisher")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn dot_draws_steps_start_and_edges() {
+        let dot = coding().to_dot();
+        assert!(dot.starts_with("digraph \"coding\" {"));
+        assert!(dot.contains("\"__start\" -> \"planner\";"));
+        assert!(dot.contains("\"planner\" -> \"coder\";"));
+        assert!(dot.contains("\"coder\" -> \"tester\";"));
+        assert!(dot.contains("\"tester\" -> \"publisher\" [label=\"when\"];"));
+        assert!(dot.contains("\"tester\" -> \"coder\" [label=\"next\", style=dashed];"));
+        assert!(dot.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn dot_output_is_stable() {
+        assert_eq!(coding().to_dot(), coding().to_dot());
+    }
+
+    #[test]
+    fn mermaid_draws_steps_start_and_edges() {
+        let mermaid = coding().to_mermaid();
+        // Nodes are numbered in name order: coder, planner, publisher, tester.
+        assert!(mermaid.starts_with("flowchart TD\n"));
+        assert!(mermaid.contains("n1(\"planner\")"));
+        assert!(mermaid.contains("start__ --> n1"));
+        assert!(mermaid.contains("n1 --> n0"));
+        assert!(mermaid.contains("n3 -->|\"when\"| n2"));
+        assert!(mermaid.contains("n3 -.->|\"next\"| n0"));
+    }
+
+    #[test]
+    fn parallel_and_subworkflow_nodes() {
+        let inner = Workflow::builder("review")
+            .register(Step::new("editor"))
+            .build()
+            .unwrap();
+        let wf = Workflow::builder("outer")
+            .register(Step::new("a"))
+            .register(Step::new("b"))
+            .parallel("fan", ["a", "b"], |s, _| s)
+            .subworkflow(inner)
+            .start_at("fan")
+            .then("review")
+            .build()
+            .unwrap();
+
+        let dot = wf.to_dot();
+        assert!(dot.contains("\"fan\" [label=\"fan\", shape=parallelogram];"));
+        assert!(dot.contains("\"review\" [label=\"review\", peripheries=2];"));
+        assert!(dot.contains("\"fan\" -> \"a\" [label=\"fork\", style=dotted];"));
+        assert!(dot.contains("\"fan\" -> \"review\";"));
+
+        let mermaid = wf.to_mermaid();
+        assert!(mermaid.contains("[/\"fan\"/]"));
+        assert!(mermaid.contains("[[\"review\"]]"));
+    }
+
+    #[test]
+    fn error_handlers_are_dashed_edges() {
+        let wf = Workflow::builder("coding")
+            .register(Step::new("coder"))
+            .register(Step::new("tester"))
+            .register(Step::new("restore"))
+            .register(Step::new("notify"))
+            .then("tester")
+            .on_error("tester", "restore")
+            .catch("notify")
+            .build()
+            .unwrap();
+
+        let dot = wf.to_dot();
+        assert!(dot.contains("\"tester\" -> \"restore\" [label=\"on error\", style=dashed];"));
+        assert!(dot.contains("\"coder\" -> \"notify\" [label=\"on error\", style=dashed];"));
+        assert!(!dot.contains("\"tester\" -> \"notify\""));
+        assert!(!dot.contains("\"notify\" -> \"notify\""));
+    }
+
+    #[test]
+    fn trace_overlay_counts_visits_and_hops() {
+        let trace = ["planner", "coder", "tester", "coder", "tester", "tester"];
+        let dot = coding().to_dot_with_trace(trace);
+        assert!(dot.contains("label=\"coder (2x)\""));
+        assert!(dot.contains("label=\"tester (3x)\""));
+        assert!(dot.contains("\"publisher\" [label=\"publisher\"];"));
+        assert!(dot.contains("\"coder\" -> \"tester\" [label=\"2x\", color="));
+        assert!(dot.contains("\"tester\" -> \"coder\" [label=\"next 1x\", style=dashed, color="));
+        // A retry isn't a declared edge, so it's drawn as an extra hop.
+        assert!(dot.contains("\"tester\" -> \"tester\" [label=\"1x\", color=\"#d62728\""));
+
+        let mermaid = coding().to_mermaid_with_trace(trace);
+        assert!(mermaid.contains("n0(\"coder (2x)\")"));
+        assert!(mermaid.contains("class n0,n1,n3 visited"));
+        assert!(mermaid.contains("n3 -->|\"1x\"| n3"));
+        assert!(mermaid.contains("linkStyle 5 stroke:#d62728"));
+    }
+
+    #[test]
+    fn trace_overlay_folds_nested_steps_into_parent() {
+        let trace = ["planner", "review/editor", "coder"];
+        let dot = coding().to_dot_with_trace(trace);
+        assert!(dot.contains("\"planner\" -> \"coder\" [label=\"1x\""));
+        assert!(!dot.contains("editor"));
+    }
+}
@@ -3,7 +3,8 @@
 //! Define agents, wire them into workflows, and let the runner execute them.
 //! Agents communicate through shared context ([`Ctx`]) and control flow with
 //! outcomes: [`Outcome::Continue`], [`Outcome::Next`], [`Outcome::Retry`],
-//! [`Outcome::Wait`], [`Outcome::Done`], and [`Outcome::Fail`].
+//! [`Outcome::Wait`], [`Outcome::Done`], [`Outcome::Fail`], and
+//! [`Outcome::Pause`].
 //!
 //! # Quick start
 //!
@@ -151,16 +152,59 @@
 //! )?;
 //! # Ok(()) }
 //! ```
+//!
+//! # Async
+//!
+//! Everything above is blocking. With the `async` cargo feature enabled,
+//! [`AsyncAgent`], [`AsyncWorkflow`] and [`AsyncRunner`] provide the same
+//! routing semantics inside a tokio runtime, for a subset of the sync
+//! runner's features (see [`AsyncRunner`]), and
+//! [`LlmRequestBuilder::send_async`] sends a chat request without blocking
+//! the executor. Sync users pull in no async dependencies.
 
 mod agent;
+#[cfg(feature = "async")]
+mod async_runner;
+mod batch;
+mod cancel;
+mod checkpoint;
 mod ctx;
+mod diagram;
 mod llm;
+mod metrics;
+mod middleware;
+mod observer;
+#[cfg(feature = "otel")]
+mod otel;
+mod replay;
+mod report;
+mod retry;
 mod runner;
+#[cfg(feature = "tracing")]
+mod spans;
+mod spec;
 pub mod tools;
 mod workflow;
 
-pub use agent::{Agent, Outcome, RetryHint, StepError, StepResult};
-pub use ctx::Ctx;
+pub use agent::{Agent, Outcome, RetryHint, Routes, StepError, StepResult};
+#[cfg(feature = "async")]
+pub use async_runner::{AsyncAgent, AsyncRunner, AsyncWorkflow, AsyncWorkflowBuilder};
+pub use batch::{BatchItem, BatchProgress, BatchReport, BatchRunner};
+pub use cancel::CancellationToken;
+pub use checkpoint::{Checkpoint, CheckpointStore, FileCheckpointStore};
+pub use ctx::{CaughtError, Ctx, CtxDiff};
 pub use llm::{LlmConfig, LlmConfigBuilder, LlmConfigError, LlmRequestBuilder, Provider};
-pub use runner::{ErrorEvent, Runner, StepEvent};
-pub use workflow::{Workflow, WorkflowBuilder, WorkflowError};
+pub use metrics::{Metrics, MetricsObserver};
+pub use middleware::{Middleware, Next, middleware_fn};
+pub use observer::{
+    BeforeStepEvent, CatchEvent, RetryEvent, RouteEvent, RunEndEvent, RunObserver, RunStartEvent,
+    WaitEvent,
+};
+#[cfg(feature = "otel")]
+pub use otel::OtelObserver;
+pub use replay::{CallKind, RecordedCall, Replayer, RunRecording, StepRecording};
+pub use report::{RunReport, StepRecord};
+pub use retry::RetryPolicy;
+pub use runner::{ErrorEvent, Paused, RunStatus, Runner, StepEvent};
+pub use spec::{AgentRegistry, SpecError, SpecFormat};
+pub use workflow::{GraphIssue, Workflow, WorkflowBuilder, WorkflowError};
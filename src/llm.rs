@@ -121,6 +121,40 @@ impl Provider {
             .map(|s| s.to_string())
             .ok_or_else(|| StepError::other("llm response missing message content"))
     }
+
+    /// The provider's name as used in telemetry.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Provider::Ollama => "ollama",
+            Provider::OpenAi => "openai",
+            Provider::Anthropic => "anthropic",
+        }
+    }
+
+    /// Token counts reported in a response, where the provider includes them.
+    pub(crate) fn usage(&self, json: &serde_json::Value) -> Usage {
+        let (input, output) = match self {
+            Provider::Ollama => (&json["prompt_eval_count"], &json["eval_count"]),
+            Provider::OpenAi => (
+                &json["usage"]["prompt_tokens"],
+                &json["usage"]["completion_tokens"],
+            ),
+            Provider::Anthropic => (
+                &json["usage"]["input_tokens"],
+                &json["usage"]["output_tokens"],
+            ),
+        };
+        Usage {
+            input_tokens: input.as_u64(),
+            output_tokens: output.as_u64(),
+        }
+    }
+}
+
+/// Token usage of one LLM response.
+pub(crate) struct Usage {
+    pub(crate) input_tokens: Option<u64>,
+    pub(crate) output_tokens: Option<u64>,
 }
 
 impl LlmConfig {
@@ -277,6 +311,32 @@ impl LlmRequestBuilder {
 
     /// Send the request and return the assistant's response text.
     pub fn send(self) -> Result<String, StepError> {
+        let messages = self.messages();
+        crate::replay::llm(self.config.provider, &self.config.model, &messages, || {
+            self.exchange(&messages)
+        })
+    }
+
+    /// Send the request from async code and return the assistant's response
+    /// text.
+    ///
+    /// The HTTP call is made with an async client, so awaiting it never
+    /// blocks the executor. Metrics, `tracing` and OTEL spans are recorded
+    /// as for [`send`](Self::send). Calls made while a
+    /// [`crate::Runner::with_recording`] or [`crate::Replayer`] run is
+    /// driving the thread (from a sync agent through `block_on`) are
+    /// recorded and replayed too; [`crate::AsyncRunner`] runs are neither.
+    /// Requires the `async` feature and a tokio runtime.
+    #[cfg(feature = "async")]
+    pub async fn send_async(self) -> Result<String, StepError> {
+        let messages = self.messages();
+        let provider = self.config.provider;
+        let send = self.exchange_async(&messages);
+        crate::replay::llm_async(provider, &self.config.model, &messages, send).await
+    }
+
+    /// The chat messages: the system prompt, if any, then the user messages.
+    fn messages(&self) -> Vec<serde_json::Value> {
         let mut messages = Vec::new();
 
         if let Some(sys) = &self.system {
@@ -292,7 +352,11 @@ impl LlmRequestBuilder {
                 "content": msg
             }));
         }
+        messages
+    }
 
+    /// The provider's endpoint, headers and body for `messages`.
+    fn prepare(&self, messages: &[serde_json::Value]) -> ChatRequest {
         let body = match &self.config.provider {
             Provider::Ollama => serde_json::json!({
                 "model": self.config.model,
@@ -322,24 +386,25 @@ impl LlmRequestBuilder {
             }),
         };
 
-        let url = self.config.provider.endpoint(&self.config.base_url);
-        let mut request = ureq::post(&url);
-
+        let mut headers = Vec::new();
         match &self.config.provider {
             Provider::Anthropic => {
                 if let Some(key) = &self.config.api_key {
-                    request = request.header("x-api-key", key);
+                    headers.push(("x-api-key", key.clone()));
                 }
-                request = request.header("anthropic-version", "2023-06-01");
-                request = request.header("content-type", "application/json");
+                headers.push(("anthropic-version", "2023-06-01".to_string()));
+                headers.push(("content-type", "application/json".to_string()));
             }
             _ => {
                 if let Some(key) = &self.config.api_key {
-                    request = request.header("Authorization", &format!("Bearer {key}"));
+                    headers.push(("Authorization", format!("Bearer {key}")));
                 }
             }
         }
 
+        let url = self.config.provider.endpoint(&self.config.base_url);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(url = %url, messages = messages.len(), "llm request");
         if std::env::var("AGENT_LINE_DEBUG").is_ok() {
             eprintln!("[debug] LLM request to {}", url);
             eprintln!(
@@ -348,15 +413,76 @@ impl LlmRequestBuilder {
             );
         }
 
-        let mut response = request
-            .send_json(&body)
-            .map_err(|e| StepError::transient(format!("llm request failed: {e}")))?;
-
-        let json: serde_json::Value = response
-            .body_mut()
-            .read_json()
-            .map_err(|e| StepError::transient(format!("llm response parse failed: {e}")))?;
-
+        ChatRequest { url, headers, body }
+    }
+
+    /// [`exchange`](Self::exchange) with the async client.
+    #[cfg(feature = "async")]
+    async fn exchange_async(&self, messages: &[serde_json::Value]) -> Result<String, StepError> {
+        let request = self.prepare(messages);
+        let provider = self.config.provider;
+        let model = self.config.model.as_str();
+
+        let started = std::time::Instant::now();
+        let call = post_async(request);
+        #[cfg(feature = "tracing")]
+        let span = crate::spans::open_chat(provider, model, self.config.max_tokens);
+        #[cfg(feature = "tracing")]
+        let call = tracing::Instrument::instrument(call, span.clone());
+        #[cfg(feature = "otel")]
+        let cx = crate::otel::open_chat(provider, model, self.config.max_tokens);
+        #[cfg(feature = "otel")]
+        let call = opentelemetry::context::FutureExt::with_context(call, cx.clone());
+        let result = call.await;
+        crate::metrics::llm_request(provider, model, started.elapsed(), &result);
+        #[cfg(feature = "tracing")]
+        crate::spans::close_chat(&span, provider, model, &result);
+        #[cfg(feature = "otel")]
+        crate::otel::close_chat(&cx, provider, &result);
+
+        self.parse(result?)
+    }
+
+    /// Send `messages` to the provider and parse the response text.
+    fn exchange(&self, messages: &[serde_json::Value]) -> Result<String, StepError> {
+        let request = self.prepare(messages);
+        let call = || {
+            let started = std::time::Instant::now();
+            let result = post(request);
+            crate::metrics::llm_request(
+                self.config.provider,
+                &self.config.model,
+                started.elapsed(),
+                &result,
+            );
+            result
+        };
+        #[cfg(feature = "tracing")]
+        let call = || {
+            crate::spans::chat_span(
+                self.config.provider,
+                &self.config.model,
+                self.config.max_tokens,
+                call,
+            )
+        };
+        #[cfg(feature = "otel")]
+        let json = crate::otel::chat_span(
+            self.config.provider,
+            &self.config.model,
+            self.config.max_tokens,
+            call,
+        )?;
+        #[cfg(not(feature = "otel"))]
+        let json = call()?;
+
+        self.parse(json)
+    }
+
+    /// Pull the response text out of the provider's JSON.
+    fn parse(&self, json: serde_json::Value) -> Result<String, StepError> {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(response = %json, "llm response");
         if std::env::var("AGENT_LINE_DEBUG").is_ok() {
             eprintln!("[debug] LLM response: {}", &json);
         }
@@ -365,6 +491,52 @@ impl LlmRequestBuilder {
     }
 }
 
+/// A chat request ready to POST, whichever HTTP client sends it.
+struct ChatRequest {
+    url: String,
+    headers: Vec<(&'static str, String)>,
+    body: serde_json::Value,
+}
+
+/// POST `request` with the blocking client.
+fn post(request: ChatRequest) -> Result<serde_json::Value, StepError> {
+    let mut call = ureq::post(&request.url);
+    for (name, value) in &request.headers {
+        call = call.header(*name, value);
+    }
+    let mut response = call
+        .send_json(&request.body)
+        .map_err(|e| StepError::transient(format!("llm request failed: {e}")))?;
+
+    response
+        .body_mut()
+        .read_json()
+        .map_err(|e| StepError::transient(format!("llm response parse failed: {e}")))
+}
+
+/// POST `request` with the async client, shared so connections are reused.
+#[cfg(feature = "async")]
+async fn post_async(request: ChatRequest) -> Result<serde_json::Value, StepError> {
+    static CLIENT: std::sync::OnceLock<reqwest::Client> = std::sync::OnceLock::new();
+    let mut call = CLIENT
+        .get_or_init(reqwest::Client::new)
+        .post(&request.url)
+        .json(&request.body);
+    for (name, value) in request.headers {
+        call = call.header(name, value);
+    }
+    let response = call
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| StepError::transient(format!("llm request failed: {e}")))?;
+
+    response
+        .json()
+        .await
+        .map_err(|e| StepError::transient(format!("llm response parse failed: {e}")))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -467,6 +639,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn usage_reads_each_providers_token_counts() {
+        let ollama = serde_json::json!({"prompt_eval_count": 12, "eval_count": 34});
+        let usage = Provider::Ollama.usage(&ollama);
+        assert_eq!(
+            (usage.input_tokens, usage.output_tokens),
+            (Some(12), Some(34))
+        );
+
+        let openai = serde_json::json!({"usage": {"prompt_tokens": 5, "completion_tokens": 6}});
+        let usage = Provider::OpenAi.usage(&openai);
+        assert_eq!(
+            (usage.input_tokens, usage.output_tokens),
+            (Some(5), Some(6))
+        );
+
+        let anthropic = serde_json::json!({"usage": {"input_tokens": 7, "output_tokens": 8}});
+        let usage = Provider::Anthropic.usage(&anthropic);
+        assert_eq!(
+            (usage.input_tokens, usage.output_tokens),
+            (Some(7), Some(8))
+        );
+
+        let usage = Provider::OpenAi.usage(&serde_json::json!({}));
+        assert_eq!((usage.input_tokens, usage.output_tokens), (None, None));
+    }
+
     #[test]
     fn test_parse_response_missing_content_is_error() {
         let json = serde_json::json!({"unexpected": "shape"});
@@ -589,4 +788,69 @@ mod tests {
         assert_eq!(r1.config.model, "llama3");
         assert_eq!(r2.config.model, "llama3");
     }
+
+    /// Answer one chat request with `reply`, handing back the raw request.
+    #[cfg(feature = "async")]
+    fn fake_provider(reply: serde_json::Value) -> (String, std::thread::JoinHandle<String>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some((name, value)) = line.split_once(':')
+                    && name.eq_ignore_ascii_case("content-length")
+                {
+                    length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+
+            let reply = reply.to_string();
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{reply}",
+                reply.len()
+            )
+            .unwrap();
+            request
+        });
+        (url, handle)
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn send_async_posts_with_the_async_client() {
+        let (url, server) = fake_provider(serde_json::json!({
+            "choices": [{ "message": { "content": "hello back" } }]
+        }));
+        let cfg = LlmConfig::builder()
+            .provider(Provider::OpenAi)
+            .base_url(url)
+            .model("gpt-test")
+            .api_key("secret")
+            .build()
+            .unwrap();
+
+        let text = cfg.request().user("hello").send_async().await.unwrap();
+        assert_eq!(text, "hello back");
+
+        let request = server.join().unwrap().to_lowercase();
+        assert!(request.starts_with("post /v1/chat/completions"));
+        assert!(request.contains("authorization: bearer secret"));
+        assert!(request.contains(r#""model":"gpt-test""#));
+    }
 }
@@ -0,0 +1,688 @@
+use crate::{
+    CatchEvent, ErrorEvent, Provider, RetryEvent, RunEndEvent, RunObserver, RunStartEvent,
+    StepError, StepEvent, WaitEvent,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write as _};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+/// Histogram bucket bounds in seconds, wide enough for slow LLM calls.
+const BUCKETS: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+];
+
+/// How long a scrape connection may stall before it is dropped.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Every metric family: name, help text and whether it is a histogram.
+const FAMILIES: &[(&str, &str, bool)] = &[
+    (
+        "agent_line_runs_started_total",
+        "Runs started or resumed.",
+        false,
+    ),
+    (
+        "agent_line_runs_completed_total",
+        "Runs that finished.",
+        false,
+    ),
+    ("agent_line_runs_failed_total", "Runs that failed.", false),
+    (
+        "agent_line_runs_paused_total",
+        "Runs paused by an agent.",
+        false,
+    ),
+    (
+        "agent_line_run_duration_seconds",
+        "Wall-clock time per run.",
+        true,
+    ),
+    (
+        "agent_line_step_duration_seconds",
+        "Wall-clock time per successful step.",
+        true,
+    ),
+    (
+        "agent_line_step_errors_total",
+        "Steps that failed the run.",
+        false,
+    ),
+    (
+        "agent_line_errors_caught_total",
+        "Step errors routed to an error handler.",
+        false,
+    ),
+    (
+        "agent_line_retries_total",
+        "Steps run again after an error or Outcome::Retry.",
+        false,
+    ),
+    (
+        "agent_line_waits_total",
+        "Steps that returned Outcome::Wait.",
+        false,
+    ),
+    ("agent_line_llm_requests_total", "LLM chat requests.", false),
+    (
+        "agent_line_llm_tokens_total",
+        "LLM tokens reported by the provider.",
+        false,
+    ),
+    (
+        "agent_line_llm_request_duration_seconds",
+        "LLM chat request latency.",
+        true,
+    ),
+    (
+        "agent_line_tool_invocations_total",
+        "Calls to agent_line::tools functions.",
+        false,
+    ),
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+enum Series {
+    Counter(u64),
+    Histogram {
+        buckets: [u64; BUCKETS.len()],
+        sum: f64,
+        count: u64,
+    },
+}
+
+#[derive(Default)]
+struct Registry {
+    series: BTreeMap<&'static str, BTreeMap<Labels, Series>>,
+}
+
+impl Registry {
+    fn inc(&mut self, name: &'static str, labels: Labels, by: u64) {
+        let family = self.series.entry(name).or_default();
+        if let Series::Counter(n) = family.entry(labels).or_insert(Series::Counter(0)) {
+            *n += by;
+        }
+    }
+
+    fn observe(&mut self, name: &'static str, labels: Labels, value: Duration) {
+        let family = self.series.entry(name).or_default();
+        let series = family.entry(labels).or_insert(Series::Histogram {
+            buckets: [0; BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        });
+        if let Series::Histogram {
+            buckets,
+            sum,
+            count,
+        } = series
+        {
+            let secs = value.as_secs_f64();
+            for (bucket, bound) in buckets.iter_mut().zip(BUCKETS) {
+                if secs <= bound {
+                    *bucket += 1;
+                }
+            }
+            *sum += secs;
+            *count += 1;
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for &(name, help, histogram) in FAMILIES {
+            let Some(family) = self.series.get(name) else {
+                continue;
+            };
+            let kind = if histogram { "histogram" } else { "counter" };
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (labels, series) in family {
+                match series {
+                    Series::Counter(n) => {
+                        let _ = writeln!(out, "{name}{} {n}", label_set(labels, None));
+                    }
+                    Series::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        for (bound, n) in BUCKETS.iter().zip(buckets) {
+                            let le = bound.to_string();
+                            let _ =
+                                writeln!(out, "{name}_bucket{} {n}", label_set(labels, Some(&le)));
+                        }
+                        let inf = label_set(labels, Some("+Inf"));
+                        let _ = writeln!(out, "{name}_bucket{inf} {count}");
+                        let _ = writeln!(out, "{name}_sum{} {sum}", label_set(labels, None));
+                        let _ = writeln!(out, "{name}_count{} {count}", label_set(labels, None));
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// `{a="1",b="2"}`, with an optional trailing `le` label for buckets.
+fn label_set(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{le}\""));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Counters and histograms for workflow runs, LLM calls and tools, rendered
+/// in the Prometheus text exposition format.
+///
+/// `Metrics` is a cheap handle; clones share the same counters. Attach
+/// [`Metrics::observer`] to each runner to count runs and steps. LLM
+/// requests and tool calls are counted in the process-wide
+/// [`Metrics::global`] instance only, since they happen outside any runner.
+///
+/// ```rust,no_run
+/// use agent_line::Metrics;
+///
+/// let metrics = Metrics::global();
+/// metrics.serve("0.0.0.0:9464")?;
+/// // Runner::new(wf).with_observer(metrics.observer())
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+static GLOBAL: OnceLock<Metrics> = OnceLock::new();
+
+impl Metrics {
+    /// A new, empty set of metrics. Only runs observed through
+    /// [`Metrics::observer`] are counted; use [`Metrics::global`] to also
+    /// count LLM requests and tool calls.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The process-wide metrics. LLM requests and tool calls are recorded
+    /// here once it has been called, and never before.
+    pub fn global() -> Metrics {
+        GLOBAL.get_or_init(Metrics::new).clone()
+    }
+
+    /// An observer that records runs, steps, retries and waits into these
+    /// metrics. Attach one per runner.
+    pub fn observer(&self) -> MetricsObserver {
+        MetricsObserver {
+            metrics: self.clone(),
+            workflow: String::new(),
+        }
+    }
+
+    /// Everything recorded so far, in the Prometheus text format.
+    pub fn render(&self) -> String {
+        self.lock().render()
+    }
+
+    /// Serve [`Metrics::render`] over HTTP on a background thread, returning
+    /// the bound address. Any `GET` request is answered, so point the
+    /// scraper at `/metrics` or anything else. The thread lives as long as
+    /// the process; each connection gets its own short-lived thread and is
+    /// dropped if it stalls for more than five seconds.
+    pub fn serve(&self, addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local = listener.local_addr()?;
+        let metrics = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let metrics = metrics.clone();
+                // A broken or idle scrape only affects that one connection.
+                std::thread::spawn(move || metrics.respond(stream));
+            }
+        });
+        Ok(local)
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+        stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Drain the headers so the client sees a clean close.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let (status, body) = if request_line.starts_with("GET ") {
+            ("200 OK", self.render())
+        } else {
+            ("405 Method Not Allowed", String::new())
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\n\
+             Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Registry> {
+        // A panicking observer must not take the metrics down with it.
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record_llm(
+        &self,
+        provider: Provider,
+        model: &str,
+        duration: Duration,
+        result: &Result<serde_json::Value, StepError>,
+    ) {
+        let labels = || -> Labels {
+            vec![
+                ("provider", provider.name().to_string()),
+                ("model", model.to_string()),
+            ]
+        };
+        let mut registry = self.lock();
+        let mut status = labels();
+        status.push(("status", if result.is_ok() { "ok" } else { "error" }.into()));
+        registry.inc("agent_line_llm_requests_total", status, 1);
+        registry.observe(
+            "agent_line_llm_request_duration_seconds",
+            labels(),
+            duration,
+        );
+        if let Ok(json) = result {
+            let usage = provider.usage(json);
+            for (kind, tokens) in [
+                ("input", usage.input_tokens),
+                ("output", usage.output_tokens),
+            ] {
+                if let Some(tokens) = tokens {
+                    let mut by_kind = labels();
+                    by_kind.push(("type", kind.into()));
+                    registry.inc("agent_line_llm_tokens_total", by_kind, tokens);
+                }
+            }
+        }
+    }
+}
+
+/// Record one LLM exchange in the global metrics, if they are in use.
+pub(crate) fn llm_request(
+    provider: Provider,
+    model: &str,
+    duration: Duration,
+    result: &Result<serde_json::Value, StepError>,
+) {
+    if let Some(metrics) = GLOBAL.get() {
+        metrics.record_llm(provider, model, duration, result);
+    }
+}
+
+/// Count a call to one of the `tools` functions in the global metrics, if
+/// they are in use.
+pub(crate) fn tool_invoked(tool: &'static str) {
+    if let Some(metrics) = GLOBAL.get() {
+        metrics.lock().inc(
+            "agent_line_tool_invocations_total",
+            vec![("tool", tool.into())],
+            1,
+        );
+    }
+}
+
+/// Records one runner's activity into a [`Metrics`]. Created by
+/// [`Metrics::observer`].
+pub struct MetricsObserver {
+    metrics: Metrics,
+    workflow: String,
+}
+
+impl MetricsObserver {
+    fn workflow(&self) -> Labels {
+        vec![("workflow", self.workflow.clone())]
+    }
+
+    fn step(&self, agent: &str) -> Labels {
+        vec![
+            ("workflow", self.workflow.clone()),
+            ("agent", agent.to_string()),
+        ]
+    }
+}
+
+impl RunObserver for MetricsObserver {
+    fn on_run_start(&mut self, event: &RunStartEvent) {
+        self.workflow = event.workflow.to_string();
+        let labels = self.workflow();
+        self.metrics
+            .lock()
+            .inc("agent_line_runs_started_total", labels, 1);
+    }
+
+    fn after_step(&mut self, event: &StepEvent) {
+        let labels = self.step(event.agent);
+        self.metrics
+            .lock()
+            .observe("agent_line_step_duration_seconds", labels, event.duration);
+    }
+
+    fn on_error(&mut self, event: &ErrorEvent) {
+        let labels = self.step(event.agent);
+        self.metrics
+            .lock()
+            .inc("agent_line_step_errors_total", labels, 1);
+    }
+
+    fn on_catch(&mut self, event: &CatchEvent) {
+        let mut labels = self.step(event.agent);
+        labels.push(("handler", event.handler.to_string()));
+        self.metrics
+            .lock()
+            .inc("agent_line_errors_caught_total", labels, 1);
+    }
+
+    fn on_retry(&mut self, event: &RetryEvent) {
+        let labels = self.step(event.agent);
+        self.metrics
+            .lock()
+            .inc("agent_line_retries_total", labels, 1);
+    }
+
+    fn on_wait(&mut self, event: &WaitEvent) {
+        let labels = self.step(event.agent);
+        self.metrics.lock().inc("agent_line_waits_total", labels, 1);
+    }
+
+    fn on_run_end(&mut self, event: &RunEndEvent) {
+        let outcome = if event.error.is_some() {
+            "agent_line_runs_failed_total"
+        } else if event.paused {
+            "agent_line_runs_paused_total"
+        } else {
+            "agent_line_runs_completed_total"
+        };
+        let mut registry = self.metrics.lock();
+        registry.inc(outcome, self.workflow(), 1);
+        registry.observe(
+            "agent_line_run_duration_seconds",
+            self.workflow(),
+            event.duration,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Agent, Ctx, Outcome, Runner, StepResult, Workflow};
+    use std::io::Read;
+
+    struct Draft;
+
+    impl Agent<u32> for Draft {
+        fn name(&self) -> &'static str {
+            "draft"
+        }
+        fn run(&mut self, state: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            Ok((state + 1, Outcome::Continue))
+        }
+    }
+
+    struct Fail;
+
+    impl Agent<u32> for Fail {
+        fn name(&self) -> &'static str {
+            "publish"
+        }
+        fn run(&mut self, _state: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            Err(StepError::invalid("no"))
+        }
+    }
+
+    fn line<'a>(text: &'a str, prefix: &str) -> &'a str {
+        text.lines()
+            .find(|l| l.starts_with(prefix))
+            .unwrap_or_else(|| panic!("no line starting with {prefix} in\n{text}"))
+    }
+
+    #[test]
+    fn counts_runs_steps_and_failures() {
+        let metrics = Metrics::new();
+        let wf = Workflow::builder("pipeline")
+            .register(Draft)
+            .register(Fail)
+            .start_at("draft")
+            .then("publish")
+            .build()
+            .unwrap();
+        let mut runner = Runner::new(wf).with_observer(metrics.observer());
+        let _ = runner.run(0, &mut Ctx::new());
+        let _ = runner.run(0, &mut Ctx::new());
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE agent_line_runs_started_total counter"));
+        assert!(text.contains("# TYPE agent_line_step_duration_seconds histogram"));
+        assert_eq!(
+            line(&text, "agent_line_runs_started_total"),
+            r#"agent_line_runs_started_total{workflow="pipeline"} 2"#
+        );
+        assert_eq!(
+            line(&text, "agent_line_runs_failed_total"),
+            r#"agent_line_runs_failed_total{workflow="pipeline"} 2"#
+        );
+        assert_eq!(
+            line(&text, "agent_line_step_errors_total"),
+            r#"agent_line_step_errors_total{workflow="pipeline",agent="publish"} 2"#
+        );
+        assert_eq!(
+            line(
+                &text,
+                r#"agent_line_step_duration_seconds_bucket{workflow="pipeline",agent="draft",le="+Inf"}"#
+            ),
+            r#"agent_line_step_duration_seconds_bucket{workflow="pipeline",agent="draft",le="+Inf"} 2"#
+        );
+        assert_eq!(
+            line(&text, "agent_line_run_duration_seconds_count"),
+            r#"agent_line_run_duration_seconds_count{workflow="pipeline"} 2"#
+        );
+        assert!(!text.contains("agent_line_runs_completed_total"));
+    }
+
+    struct Recover;
+
+    impl Agent<u32> for Recover {
+        fn name(&self) -> &'static str {
+            "recover"
+        }
+        fn run(&mut self, state: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            Ok((state, Outcome::Done))
+        }
+    }
+
+    #[test]
+    fn counts_caught_errors() {
+        let metrics = Metrics::new();
+        let wf = Workflow::builder("pipeline")
+            .register(Draft)
+            .register(Fail)
+            .register(Recover)
+            .start_at("draft")
+            .then("publish")
+            .catch("recover")
+            .build()
+            .unwrap();
+        let mut runner = Runner::new(wf).with_observer(metrics.observer());
+        runner.run(0, &mut Ctx::new()).unwrap();
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE agent_line_errors_caught_total counter"));
+        assert_eq!(
+            line(&text, "agent_line_errors_caught_total"),
+            r#"agent_line_errors_caught_total{workflow="pipeline",agent="publish",handler="recover"} 1"#
+        );
+    }
+
+    #[test]
+    fn parallel_failures_count_once_under_the_parallel_step() {
+        let fan = |recover: bool| {
+            let builder = Workflow::builder("pipeline")
+                .register(Draft)
+                .register(Fail)
+                .register(Recover)
+                .parallel("fan", ["draft", "publish"], |s, _| s)
+                .start_at("fan");
+            let builder = if recover {
+                builder.catch("recover")
+            } else {
+                builder
+            };
+            builder.build().unwrap()
+        };
+
+        let metrics = Metrics::new();
+        let mut runner = Runner::new(fan(true)).with_observer(metrics.observer());
+        runner.run(0, &mut Ctx::new()).unwrap();
+        let text = metrics.render();
+        assert_eq!(
+            line(&text, "agent_line_errors_caught_total"),
+            r#"agent_line_errors_caught_total{workflow="pipeline",agent="fan",handler="recover"} 1"#
+        );
+        assert!(!text.contains("agent_line_step_errors_total{"));
+
+        let metrics = Metrics::new();
+        let mut runner = Runner::new(fan(false)).with_observer(metrics.observer());
+        assert!(runner.run(0, &mut Ctx::new()).is_err());
+        let text = metrics.render();
+        let errors: Vec<_> = text
+            .lines()
+            .filter(|l| l.starts_with("agent_line_step_errors_total{"))
+            .collect();
+        assert_eq!(
+            errors,
+            [r#"agent_line_step_errors_total{workflow="pipeline",agent="fan"} 1"#]
+        );
+    }
+
+    #[test]
+    fn records_llm_requests_and_tokens_per_model() {
+        let metrics = Metrics::new();
+        let json = serde_json::json!({"prompt_eval_count": 12, "eval_count": 34});
+        metrics.record_llm(
+            Provider::Ollama,
+            "llama3.1:8b",
+            Duration::from_millis(300),
+            &Ok(json),
+        );
+        metrics.record_llm(
+            Provider::Ollama,
+            "llama3.1:8b",
+            Duration::from_secs(1),
+            &Err(StepError::transient("down")),
+        );
+
+        let text = metrics.render();
+        assert!(text.contains(
+            r#"agent_line_llm_requests_total{provider="ollama",model="llama3.1:8b",status="ok"} 1"#
+        ));
+        assert!(text.contains(
+            r#"agent_line_llm_requests_total{provider="ollama",model="llama3.1:8b",status="error"} 1"#
+        ));
+        assert!(text.contains(
+            r#"agent_line_llm_tokens_total{provider="ollama",model="llama3.1:8b",type="input"} 12"#
+        ));
+        assert!(text.contains(
+            r#"agent_line_llm_request_duration_seconds_bucket{provider="ollama",model="llama3.1:8b",le="0.5"} 1"#
+        ));
+        assert!(text.contains(
+            r#"agent_line_llm_request_duration_seconds_sum{provider="ollama",model="llama3.1:8b"} 1.3"#
+        ));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let metrics = Metrics::new();
+        metrics.lock().inc(
+            "agent_line_runs_started_total",
+            vec![("workflow", "a \"b\"\\\n".into())],
+            1,
+        );
+        assert!(
+            metrics
+                .render()
+                .contains(r#"agent_line_runs_started_total{workflow="a \"b\"\\\n"} 1"#)
+        );
+    }
+
+    #[test]
+    fn global_metrics_count_tool_calls() {
+        let metrics = Metrics::global();
+        crate::tools::file_exists("Cargo.toml");
+        assert!(
+            metrics
+                .render()
+                .contains(r#"agent_line_tool_invocations_total{tool="file_exists"}"#)
+        );
+    }
+
+    #[test]
+    fn serves_metrics_over_http() {
+        let metrics = Metrics::new();
+        metrics
+            .lock()
+            .inc("agent_line_runs_started_total", vec![], 3);
+        let addr = metrics.serve("127.0.0.1:0").unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("text/plain; version=0.0.4"));
+        assert!(response.ends_with("agent_line_runs_started_total 3\n"));
+    }
+
+    #[test]
+    fn idle_connection_does_not_block_scrapes() {
+        let metrics = Metrics::new();
+        let addr = metrics.serve("127.0.0.1:0").unwrap();
+
+        let _idle = TcpStream::connect(addr).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
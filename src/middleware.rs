@@ -0,0 +1,351 @@
+use crate::workflow::Name;
+use crate::{Agent, Ctx, Routes, StepResult};
+use std::sync::Arc;
+
+/// A layer wrapped around agent runs, for cross-cutting concerns such as
+/// logging, timing, validating state or transforming errors.
+///
+/// Add one to every agent with [`crate::WorkflowBuilder::layer`], to a
+/// single agent with [`crate::WorkflowBuilder::layer_for`], or to the items
+/// of a map step with [`crate::WorkflowBuilder::item_layer`]. A layer gets
+/// the state and context before the agent runs and decides whether, and
+/// with what, to call [`Next::run`]; whatever it returns is the step's
+/// result. Closures become layers through [`middleware_fn`].
+///
+/// Layers are shared by every agent they wrap and may run on several
+/// threads at once (parallel branches, timed steps), so they take `&self`;
+/// keep mutable state such as a cache behind a `Mutex`.
+///
+/// ```rust
+/// use agent_line::{Ctx, Middleware, Next, StepError, StepResult};
+///
+/// /// Reject any step that leaves the draft empty.
+/// struct NonEmpty;
+///
+/// impl Middleware<String> for NonEmpty {
+///     fn call(&self, state: String, ctx: &mut Ctx, next: Next<'_, String>) -> StepResult<String> {
+///         let agent = next.agent();
+///         let (state, outcome) = next.run(state, ctx)?;
+///         if state.is_empty() {
+///             return Err(StepError::invalid(format!("{agent} emptied the draft")));
+///         }
+///         Ok((state, outcome))
+///     }
+/// }
+/// ```
+pub trait Middleware<S>: Send + Sync + 'static {
+    /// Handle one run of the agent behind `next`.
+    fn call(&self, state: S, ctx: &mut Ctx, next: Next<'_, S>) -> StepResult<S>;
+}
+
+/// The rest of the layers and the agent itself, handed to
+/// [`Middleware::call`].
+pub struct Next<'a, S> {
+    name: &'a str,
+    agent: &'a mut dyn Agent<S>,
+    layers: &'a [Arc<dyn Middleware<S>>],
+}
+
+impl<'a, S: 'static> Next<'a, S> {
+    /// Name of the step being wrapped.
+    pub fn agent(&self) -> &'a str {
+        self.name
+    }
+
+    /// Run the remaining layers and then the agent.
+    pub fn run(self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+        match self.layers.split_first() {
+            Some((layer, rest)) => layer.call(
+                state,
+                ctx,
+                Next {
+                    name: self.name,
+                    agent: self.agent,
+                    layers: rest,
+                },
+            ),
+            None => self.agent.run(state, ctx),
+        }
+    }
+}
+
+/// Turn a closure into a [`Middleware`].
+///
+/// ```rust
+/// use agent_line::middleware_fn;
+///
+/// let timing = middleware_fn(|state: u32, ctx, next| {
+///     let agent = next.agent();
+///     let start = std::time::Instant::now();
+///     let result = next.run(state, ctx);
+///     ctx.log(format!("{agent} took {:?}", start.elapsed()));
+///     result
+/// });
+/// # let _ = timing;
+/// ```
+pub fn middleware_fn<S, F>(f: F) -> impl Middleware<S>
+where
+    S: 'static,
+    F: Fn(S, &mut Ctx, Next<'_, S>) -> StepResult<S> + Send + Sync + 'static,
+{
+    FnMiddleware(f)
+}
+
+struct FnMiddleware<F>(F);
+
+impl<S, F> Middleware<S> for FnMiddleware<F>
+where
+    S: 'static,
+    F: Fn(S, &mut Ctx, Next<'_, S>) -> StepResult<S> + Send + Sync + 'static,
+{
+    fn call(&self, state: S, ctx: &mut Ctx, next: Next<'_, S>) -> StepResult<S> {
+        (self.0)(state, ctx, next)
+    }
+}
+
+/// An agent wrapped in layers, outermost first. Built by
+/// [`crate::WorkflowBuilder::build`].
+pub(crate) struct Layered<S> {
+    /// The step the agent is registered as.
+    pub(crate) name: Name,
+    pub(crate) agent: Box<dyn Agent<S>>,
+    pub(crate) layers: Vec<Arc<dyn Middleware<S>>>,
+}
+
+impl<S: 'static> Agent<S> for Layered<S> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+        Next {
+            name: &self.name,
+            agent: self.agent.as_mut(),
+            layers: &self.layers,
+        }
+        .run(state, ctx)
+    }
+
+    fn routes(&self) -> Option<Routes> {
+        self.agent.routes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Outcome, Runner, StepError, Workflow, WorkflowError};
+    use std::sync::Mutex;
+
+    struct Add(&'static str, u32);
+
+    impl Agent<u32> for Add {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn run(&mut self, state: u32, ctx: &mut Ctx) -> StepResult<u32> {
+            ctx.log(format!("run {}", self.0));
+            if self.1 == 0 {
+                return Err(StepError::invalid("nothing to add"));
+            }
+            Ok((state + self.1, Outcome::Continue))
+        }
+        fn routes(&self) -> Option<Routes> {
+            Some(Routes::new().continues())
+        }
+    }
+
+    struct Finish;
+
+    impl Agent<u32> for Finish {
+        fn name(&self) -> &'static str {
+            "finish"
+        }
+        fn run(&mut self, state: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            Ok((state, Outcome::Done))
+        }
+    }
+
+    /// Logs around the rest of the chain under `label`.
+    fn tag(label: &'static str) -> impl Middleware<u32> {
+        middleware_fn(move |state, ctx: &mut Ctx, next| {
+            ctx.log(format!("{label} before {}", next.agent()));
+            let result = next.run(state, ctx);
+            ctx.log(format!("{label} after"));
+            result
+        })
+    }
+
+    #[test]
+    fn layers_run_outermost_first() {
+        let wf = Workflow::builder("test")
+            .register(Add("one", 1))
+            .register(Finish)
+            .then("finish")
+            .layer(tag("outer"))
+            .layer(tag("inner"))
+            .layer_for("one", tag("own"))
+            .build()
+            .unwrap();
+        let mut ctx = Ctx::new();
+        let state = Runner::new(wf).run(0, &mut ctx).unwrap();
+
+        assert_eq!(state, 1);
+        assert_eq!(
+            &ctx.logs()[..7],
+            [
+                "outer before one",
+                "inner before one",
+                "own before one",
+                "run one",
+                "own after",
+                "inner after",
+                "outer after",
+            ]
+        );
+        assert_eq!(ctx.logs()[7], "outer before finish");
+    }
+
+    #[test]
+    fn a_layer_can_skip_the_agent() {
+        let cache = Mutex::new(vec![41]);
+        let cached = middleware_fn(move |state: u32, ctx: &mut Ctx, next| {
+            if let Some(hit) = cache.lock().unwrap().pop() {
+                return Ok((hit, Outcome::Continue));
+            }
+            next.run(state, ctx)
+        });
+        let wf = Workflow::builder("test")
+            .register(Add("one", 1))
+            .register(Finish)
+            .then("finish")
+            .layer_for("one", cached)
+            .build()
+            .unwrap();
+        let mut ctx = Ctx::new();
+        assert_eq!(Runner::new(wf).run(0, &mut ctx).unwrap(), 41);
+        assert!(ctx.logs().is_empty());
+    }
+
+    #[test]
+    fn a_layer_can_transform_errors() {
+        let explain = middleware_fn(|state: u32, ctx: &mut Ctx, next| {
+            let agent = next.agent();
+            next.run(state, ctx)
+                .map_err(|e| StepError::other(format!("{agent}: {e}")))
+        });
+        let wf = Workflow::builder("test")
+            .register(Add("zero", 0))
+            .layer(explain)
+            .build()
+            .unwrap();
+        let err = Runner::new(wf).run(0, &mut Ctx::new()).err().unwrap();
+        assert_eq!(err.to_string(), "zero: invalid: nothing to add");
+    }
+
+    fn fan_out(layered: bool) -> Workflow<u32> {
+        let mut builder = Workflow::builder("test")
+            .register(Add("one", 1))
+            .register(Add("ten", 10))
+            .register(Finish)
+            .parallel("fan", ["one", "ten"], |base, branches| {
+                base + branches.iter().map(|b| b - base).sum::<u32>()
+            })
+            .start_at("fan")
+            .then("finish");
+        if layered {
+            builder = builder.layer(tag("layer"));
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn layers_wrap_parallel_branches_and_keep_routes() {
+        let wf = fan_out(true);
+        assert_eq!(
+            format!("{:?}", wf.validate()),
+            format!("{:?}", fan_out(false).validate())
+        );
+
+        let mut ctx = Ctx::new();
+        assert_eq!(Runner::new(wf).run(0, &mut ctx).unwrap(), 11);
+        assert!(ctx.logs().contains(&"layer before ten".to_string()));
+        assert!(!ctx.logs().contains(&"layer before fan".to_string()));
+    }
+
+    #[test]
+    fn layer_for_needs_an_agent() {
+        let result = Workflow::builder("test")
+            .register(Add("one", 1))
+            .parallel("fan", ["one"], |s, _| s)
+            .layer_for("fan", tag("x"))
+            .build();
+        assert!(matches!(
+            result.err().unwrap(),
+            WorkflowError::InvalidLayer(name) if name == "fan"
+        ));
+
+        let result = Workflow::builder("test")
+            .register(Add("one", 1))
+            .layer_for("missing", tag("x"))
+            .build();
+        assert!(matches!(
+            result.err().unwrap(),
+            WorkflowError::UnknownStep(name) if name == "missing"
+        ));
+    }
+
+    fn add_each(layered: bool) -> crate::WorkflowBuilder<u32> {
+        let builder = Workflow::builder("test")
+            .register(Finish)
+            .map(
+                "each",
+                |n: &u32| vec![*n, *n],
+                || Add("add", 1),
+                |_, items| items.iter().sum(),
+                1,
+            )
+            .start_at("each")
+            .then("finish");
+        if layered {
+            builder.layer(tag("outer")).item_layer("each", tag("item"))
+        } else {
+            builder
+        }
+    }
+
+    #[test]
+    fn item_layers_wrap_map_items_inside_workflow_layers() {
+        let mut ctx = Ctx::new();
+        let wf = add_each(true).build().unwrap();
+        assert_eq!(Runner::new(wf).run(1, &mut ctx).unwrap(), 4);
+        assert_eq!(
+            &ctx.logs()[..5],
+            [
+                "outer before add",
+                "item before add",
+                "run add",
+                "item after",
+                "outer after",
+            ]
+        );
+        assert_eq!(ctx.logs()[5], "outer before add");
+        assert!(!ctx.logs().contains(&"outer before each".to_string()));
+    }
+
+    #[test]
+    fn item_layer_needs_a_map_of_its_type() {
+        let shout = middleware_fn(|state: String, ctx: &mut Ctx, next| next.run(state, ctx));
+        let result = add_each(false).item_layer("each", shout).build();
+        assert!(matches!(
+            result.err().unwrap(),
+            WorkflowError::InvalidLayer(name) if name == "each"
+        ));
+
+        let result = add_each(false).item_layer("finish", tag("x")).build();
+        assert!(matches!(
+            result.err().unwrap(),
+            WorkflowError::InvalidLayer(name) if name == "finish"
+        ));
+    }
+}
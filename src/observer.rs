@@ -0,0 +1,159 @@
+use crate::{ErrorEvent, StepError, StepEvent};
+use std::time::Duration;
+
+/// Watches a run from start to finish. Attach any number with
+/// [`crate::Runner::with_observer`]; each sees every event, in the order
+/// the observers were added.
+///
+/// Every method has an empty default, so implement only the events you
+/// need. Step names are `parent/child` inside sub-workflows and parallel
+/// steps, as in [`StepEvent`].
+///
+/// ```rust
+/// use agent_line::{RunObserver, StepEvent};
+///
+/// #[derive(Default)]
+/// struct StepCounter(usize);
+///
+/// impl RunObserver for StepCounter {
+///     fn after_step(&mut self, _event: &StepEvent) {
+///         self.0 += 1;
+///     }
+/// }
+/// ```
+pub trait RunObserver: Send {
+    /// A run started or resumed.
+    fn on_run_start(&mut self, _event: &RunStartEvent) {}
+
+    /// A step is about to run.
+    fn before_step(&mut self, _event: &BeforeStepEvent) {}
+
+    /// A step finished without an error.
+    fn after_step(&mut self, _event: &StepEvent) {}
+
+    /// A step errored or a limit was exceeded, and the run is giving up.
+    fn on_error(&mut self, _event: &ErrorEvent) {}
+
+    /// A step inside a parallel step, map step or sub-workflow errored.
+    /// The step containing it fails next and is reported through
+    /// [`RunObserver::on_error`] or [`RunObserver::on_catch`].
+    fn on_inner_error(&mut self, _event: &ErrorEvent) {}
+
+    /// A step failed and the run is moving on to its error handler instead
+    /// of giving up.
+    fn on_catch(&mut self, _event: &CatchEvent) {}
+
+    /// A step will be run again, after an error its retry policy accepts or
+    /// an [`crate::Outcome::Retry`].
+    fn on_retry(&mut self, _event: &RetryEvent) {}
+
+    /// A step returned [`crate::Outcome::Wait`] and the runner is about to
+    /// sleep before running it again.
+    fn on_wait(&mut self, _event: &WaitEvent) {}
+
+    /// The run is moving from one step to another.
+    fn on_route(&mut self, _event: &RouteEvent) {}
+
+    /// A run finished, failed or paused.
+    fn on_run_end(&mut self, _event: &RunEndEvent) {}
+}
+
+/// Passed to [`RunObserver::on_run_start`].
+pub struct RunStartEvent<'a> {
+    /// Name of the workflow being run.
+    pub workflow: &'a str,
+    /// The step the run starts (or resumes) at.
+    pub agent: &'a str,
+    /// Steps already completed, non-zero when resuming.
+    pub step_number: usize,
+}
+
+/// Passed to [`RunObserver::before_step`].
+pub struct BeforeStepEvent<'a> {
+    /// Name of the step about to run.
+    pub agent: &'a str,
+    /// Which item the agent runs on, inside a map step.
+    pub item: Option<usize>,
+    /// Steps completed so far in the run.
+    pub completed_steps: usize,
+    /// Consecutive retry count for the step.
+    pub retries: usize,
+}
+
+/// Passed to [`RunObserver::on_catch`].
+pub struct CatchEvent<'a> {
+    /// Name of the step that failed.
+    pub agent: &'a str,
+    /// Name of the error handler that runs next.
+    pub handler: &'a str,
+    /// Step number of the failed attempt.
+    pub step_number: usize,
+    /// Why the step failed.
+    pub error: &'a StepError,
+}
+
+/// Passed to [`RunObserver::on_retry`].
+pub struct RetryEvent<'a> {
+    /// Name of the step being retried.
+    pub agent: &'a str,
+    /// Step number of the attempt that will be retried.
+    pub step_number: usize,
+    /// Which retry this is, starting at 1.
+    pub retry: usize,
+    /// How long the runner waits before retrying.
+    pub delay: Duration,
+    /// The error being retried, or `None` for an `Outcome::Retry`.
+    pub error: Option<&'a StepError>,
+}
+
+/// Passed to [`RunObserver::on_wait`].
+pub struct WaitEvent<'a> {
+    /// Name of the step that asked to wait.
+    pub agent: &'a str,
+    /// Step number of the step that asked to wait.
+    pub step_number: usize,
+    /// How long the runner sleeps.
+    pub duration: Duration,
+}
+
+/// Passed to [`RunObserver::on_route`].
+pub struct RouteEvent<'a> {
+    /// The step that just finished.
+    pub from: &'a str,
+    /// The step that runs next.
+    pub to: &'a str,
+    /// Step number of the step that just finished.
+    pub step_number: usize,
+}
+
+/// Passed to [`RunObserver::on_run_end`].
+pub struct RunEndEvent<'a> {
+    /// Name of the workflow that was run.
+    pub workflow: &'a str,
+    /// Steps completed, including any before a resume.
+    pub step_number: usize,
+    /// Wall-clock time for this call to the runner.
+    pub duration: Duration,
+    /// Why the run failed, or `None` if it finished or paused.
+    pub error: Option<&'a StepError>,
+    /// Whether the run stopped because an agent paused.
+    pub paused: bool,
+}
+
+/// Adapts an `on_step` closure to an observer.
+pub(crate) struct StepFn<F>(pub(crate) F);
+
+impl<F: FnMut(&StepEvent) + Send> RunObserver for StepFn<F> {
+    fn after_step(&mut self, event: &StepEvent) {
+        (self.0)(event);
+    }
+}
+
+/// Adapts an `on_error` closure to an observer.
+pub(crate) struct ErrorFn<F>(pub(crate) F);
+
+impl<F: FnMut(&ErrorEvent) + Send> RunObserver for ErrorFn<F> {
+    fn on_error(&mut self, event: &ErrorEvent) {
+        (self.0)(event);
+    }
+}
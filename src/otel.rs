@@ -0,0 +1,494 @@
+//! OpenTelemetry spans for runs, steps and LLM calls (the `otel` feature).
+//!
+//! Attribute names follow the OpenTelemetry GenAI semantic conventions where
+//! one exists (`gen_ai.*`, `error.type`); the rest are under `agent_line.*`.
+
+use crate::{
+    BeforeStepEvent, CatchEvent, ErrorEvent, Provider, RetryEvent, RouteEvent, RunEndEvent,
+    RunObserver, RunStartEvent, StepError, StepEvent, WaitEvent,
+};
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, ContextGuard, KeyValue};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const TRACER: &str = "agent-line";
+
+/// A [`RunObserver`] that records each run as an OpenTelemetry span, with a
+/// child span per step.
+///
+/// Step spans start when the step starts and end when it finishes, so their
+/// timing is real. While a step runs its span is the current context, so
+/// LLM calls made through [`crate::LlmRequestBuilder::send`] become child
+/// `chat` spans with the model and token usage. Steps inside sub-workflows
+/// nest under the sub-workflow's span; LLM calls made by parallel branches
+/// nest under the parallel step.
+///
+/// ```rust
+/// # use agent_line::{Agent, Ctx, OtelObserver, Outcome, Runner, StepResult, Workflow};
+/// # struct Greet;
+/// # impl Agent<String> for Greet {
+/// #     fn name(&self) -> &'static str { "greet" }
+/// #     fn run(&mut self, name: String, _ctx: &mut Ctx) -> StepResult<String> {
+/// #         Ok((format!("hello, {name}"), Outcome::Done))
+/// #     }
+/// # }
+/// # let wf = Workflow::builder("greeting").register(Greet).build().unwrap();
+/// // After installing a tracer provider with opentelemetry::global:
+/// let mut runner = Runner::new(wf).with_observer(OtelObserver::new());
+/// let greeting = runner.run("otel".to_string(), &mut Ctx::new()).unwrap();
+/// # assert_eq!(greeting, "hello, otel");
+/// ```
+pub struct OtelObserver {
+    tracer: BoxedTracer,
+    run: Option<Context>,
+    workflow: String,
+    steps: Vec<OpenStep>,
+}
+
+/// A step whose span has started but not ended.
+struct OpenStep {
+    path: String,
+    item: Option<usize>,
+    cx: Context,
+    _attached: Attached,
+}
+
+thread_local! {
+    /// Guards for the contexts of open steps, by [`Attached`] id. A
+    /// `ContextGuard` can't leave its thread, so it lives here rather than
+    /// in the observer, which must be `Send`.
+    static GUARDS: RefCell<Vec<(u64, ContextGuard)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Keeps a context current on this thread until dropped. Steps start and
+/// end on the run's thread, so the guard is always dropped where it was
+/// made.
+struct Attached(u64);
+
+impl Attached {
+    fn new(cx: &Context) -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        let guard = cx.clone().attach();
+        GUARDS.with_borrow_mut(|guards| guards.push((id, guard)));
+        Self(id)
+    }
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        let guard = GUARDS.with_borrow_mut(|guards| {
+            let pos = guards.iter().rposition(|(id, _)| *id == self.0)?;
+            Some(guards.remove(pos))
+        });
+        drop(guard);
+    }
+}
+
+impl OtelObserver {
+    /// Record spans with the global tracer provider's `agent-line` tracer.
+    pub fn new() -> Self {
+        Self::with_boxed(global::tracer(TRACER))
+    }
+
+    /// Record spans with `tracer` instead of the global provider's.
+    pub fn with_tracer<T>(tracer: T) -> Self
+    where
+        T: Tracer + Send + Sync + 'static,
+        T::Span: Send + Sync + 'static,
+    {
+        Self::with_boxed(BoxedTracer::new(Box::new(tracer)))
+    }
+
+    fn with_boxed(tracer: BoxedTracer) -> Self {
+        Self {
+            tracer,
+            run: None,
+            workflow: String::new(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// The innermost open step that `path` is nested in, else the run.
+    fn parent_of(&self, path: &str) -> Context {
+        self.steps
+            .iter()
+            .rev()
+            .find(|open| {
+                path.strip_prefix(open.path.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .map(|open| open.cx.clone())
+            .or_else(|| self.run.clone())
+            .unwrap_or_default()
+    }
+
+    /// Stop tracking the latest open step named `path`, for map item
+    /// `item` if it is one.
+    fn close(&mut self, path: &str, item: Option<usize>) -> Option<Context> {
+        let pos = self
+            .steps
+            .iter()
+            .rposition(|open| open.path == path && open.item == item)?;
+        Some(self.steps.remove(pos).cx)
+    }
+}
+
+impl Default for OtelObserver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunObserver for OtelObserver {
+    fn on_run_start(&mut self, event: &RunStartEvent) {
+        let span = self
+            .tracer
+            .span_builder(format!("workflow {}", event.workflow))
+            .with_kind(SpanKind::Internal)
+            .with_attributes([
+                KeyValue::new("agent_line.workflow.name", event.workflow.to_string()),
+                KeyValue::new("agent_line.run.start_step", event.agent.to_string()),
+                KeyValue::new("agent_line.run.resumed_at", event.step_number as i64),
+            ])
+            .start_with_context(&self.tracer, &Context::current());
+        self.run = Some(Context::current().with_span(span));
+        self.workflow = event.workflow.to_string();
+    }
+
+    fn before_step(&mut self, event: &BeforeStepEvent) {
+        let parent = self.parent_of(event.agent);
+        let mut attributes = vec![
+            KeyValue::new("gen_ai.operation.name", "invoke_agent"),
+            KeyValue::new("gen_ai.agent.name", event.agent.to_string()),
+            KeyValue::new("agent_line.workflow.name", self.workflow.clone()),
+            KeyValue::new("agent_line.step.retries", event.retries as i64),
+        ];
+        if let Some(item) = event.item {
+            attributes.push(KeyValue::new("agent_line.step.item", item as i64));
+        }
+        let span = self
+            .tracer
+            .span_builder(format!("invoke_agent {}", event.agent))
+            .with_kind(SpanKind::Internal)
+            .with_attributes(attributes)
+            .start_with_context(&self.tracer, &parent);
+        let cx = parent.with_span(span);
+        self.steps.push(OpenStep {
+            path: event.agent.to_string(),
+            item: event.item,
+            _attached: Attached::new(&cx),
+            cx,
+        });
+    }
+
+    fn after_step(&mut self, event: &StepEvent) {
+        if let Some(cx) = self.close(event.agent, event.item) {
+            let span = cx.span();
+            span.set_attributes([
+                KeyValue::new("agent_line.step.number", event.step_number as i64),
+                KeyValue::new("agent_line.step.outcome", format!("{:?}", event.outcome)),
+            ]);
+            span.end();
+        }
+    }
+
+    fn on_error(&mut self, event: &ErrorEvent) {
+        if let Some(cx) = self.close(event.agent, event.item) {
+            let span = cx.span();
+            span.set_attribute(KeyValue::new(
+                "agent_line.step.number",
+                event.step_number as i64,
+            ));
+            fail(&span, event.error);
+            span.end();
+        }
+    }
+
+    fn on_inner_error(&mut self, event: &ErrorEvent) {
+        // The failed step inside a parallel step or sub-workflow still has
+        // a span of its own to close.
+        self.on_error(event);
+    }
+
+    fn on_catch(&mut self, event: &CatchEvent) {
+        // An `Err` never reaches `after_step`; end its span here.
+        if let Some(cx) = self.close(event.agent, None) {
+            let span = cx.span();
+            fail(&span, event.error);
+            span.end();
+        }
+        if let Some(run) = &self.run {
+            run.span().add_event(
+                "agent_line.catch",
+                vec![
+                    KeyValue::new("gen_ai.agent.name", event.agent.to_string()),
+                    KeyValue::new("agent_line.handler", event.handler.to_string()),
+                    KeyValue::new("error.type", error_type(event.error)),
+                ],
+            );
+        }
+    }
+
+    fn on_retry(&mut self, event: &RetryEvent) {
+        // An errored attempt never reaches `after_step`; end its span here.
+        if let Some(err) = event.error
+            && let Some(cx) = self.close(event.agent, None)
+        {
+            let span = cx.span();
+            fail(&span, err);
+            span.end();
+        }
+        if let Some(run) = &self.run {
+            run.span().add_event(
+                "agent_line.retry",
+                vec![
+                    KeyValue::new("gen_ai.agent.name", event.agent.to_string()),
+                    KeyValue::new("agent_line.retry", event.retry as i64),
+                    KeyValue::new("agent_line.delay_ms", event.delay.as_millis() as i64),
+                ],
+            );
+        }
+    }
+
+    fn on_wait(&mut self, event: &WaitEvent) {
+        if let Some(run) = &self.run {
+            run.span().add_event(
+                "agent_line.wait",
+                vec![
+                    KeyValue::new("gen_ai.agent.name", event.agent.to_string()),
+                    KeyValue::new("agent_line.delay_ms", event.duration.as_millis() as i64),
+                ],
+            );
+        }
+    }
+
+    fn on_route(&mut self, event: &RouteEvent) {
+        if let Some(run) = &self.run {
+            run.span().add_event(
+                "agent_line.route",
+                vec![
+                    KeyValue::new("agent_line.route.from", event.from.to_string()),
+                    KeyValue::new("agent_line.route.to", event.to.to_string()),
+                ],
+            );
+        }
+    }
+
+    fn on_run_end(&mut self, event: &RunEndEvent) {
+        // Steps still open here were interrupted by the failure.
+        while let Some(open) = self.steps.pop() {
+            open.cx.span().end();
+        }
+        if let Some(run) = self.run.take() {
+            let span = run.span();
+            span.set_attributes([
+                KeyValue::new("agent_line.run.steps", event.step_number as i64),
+                KeyValue::new("agent_line.run.paused", event.paused),
+            ]);
+            match event.error {
+                Some(err) => fail(&span, err),
+                None => span.set_status(Status::Ok),
+            }
+            span.end();
+        }
+    }
+}
+
+fn fail(span: &opentelemetry::trace::SpanRef<'_>, err: &StepError) {
+    span.set_attribute(KeyValue::new("error.type", error_type(err)));
+    span.set_status(Status::error(err.to_string()));
+}
+
+/// The low-cardinality `error.type` for a step error.
+fn error_type(err: &StepError) -> &'static str {
+    match err {
+        StepError::Invalid(_) => "invalid",
+        StepError::Transient(_) => "transient",
+        StepError::Failed(_) => "failed",
+        StepError::Other(_) => "other",
+        StepError::Cancelled(_) => "cancelled",
+        StepError::Timeout(_) => "timeout",
+        StepError::LoopLimit(_) => "loop_limit",
+    }
+}
+
+/// Run one LLM HTTP exchange inside a GenAI `chat` span, a child of the
+/// current context.
+pub(crate) fn chat_span(
+    provider: Provider,
+    model: &str,
+    max_tokens: u32,
+    call: impl FnOnce() -> Result<serde_json::Value, StepError>,
+) -> Result<serde_json::Value, StepError> {
+    let cx = open_chat(provider, model, max_tokens);
+    let result = call();
+    close_chat(&cx, provider, &result);
+    result
+}
+
+/// Start a GenAI `chat` span for one LLM exchange, a child of the current
+/// context, and return the context holding it.
+pub(crate) fn open_chat(provider: Provider, model: &str, max_tokens: u32) -> Context {
+    let tracer = global::tracer(TRACER);
+    let span = tracer
+        .span_builder(format!("chat {model}"))
+        .with_kind(SpanKind::Client)
+        .with_attributes([
+            KeyValue::new("gen_ai.operation.name", "chat"),
+            KeyValue::new("gen_ai.provider.name", provider.name()),
+            KeyValue::new("gen_ai.request.model", model.to_string()),
+            KeyValue::new("gen_ai.request.max_tokens", max_tokens as i64),
+        ])
+        .start_with_context(&tracer, &Context::current());
+    Context::current().with_span(span)
+}
+
+/// Record the outcome of the exchange started by [`open_chat`] and end its
+/// span.
+pub(crate) fn close_chat(
+    cx: &Context,
+    provider: Provider,
+    result: &Result<serde_json::Value, StepError>,
+) {
+    let span = cx.span();
+    match result {
+        Ok(json) => {
+            if let Some(model) = json["model"].as_str() {
+                span.set_attribute(KeyValue::new("gen_ai.response.model", model.to_string()));
+            }
+            let usage = provider.usage(json);
+            if let Some(tokens) = usage.input_tokens {
+                span.set_attribute(KeyValue::new("gen_ai.usage.input_tokens", tokens as i64));
+            }
+            if let Some(tokens) = usage.output_tokens {
+                span.set_attribute(KeyValue::new("gen_ai.usage.output_tokens", tokens as i64));
+            }
+        }
+        Err(err) => fail(&span, err),
+    }
+    span.end();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Agent, Ctx, Outcome, Runner, StepResult, Workflow};
+    use opentelemetry::trace::{SpanId, TracerProvider};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+
+    struct Step(&'static str, bool);
+
+    impl Agent<u32> for Step {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn run(&mut self, state: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            if self.1 {
+                return Err(StepError::invalid("bad input"));
+            }
+            // The step's span is current while the agent runs.
+            assert!(Context::current().has_active_span());
+            Ok((state + 1, Outcome::Continue))
+        }
+    }
+
+    struct Finish;
+
+    impl Agent<u32> for Finish {
+        fn name(&self) -> &'static str {
+            "finish"
+        }
+        fn run(&mut self, state: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            Ok((state, Outcome::Done))
+        }
+    }
+
+    fn spans(wf: Workflow<u32>) -> Vec<SpanData> {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let observer = OtelObserver::with_tracer(provider.tracer("test"));
+        let _ = Runner::new(wf)
+            .with_observer(observer)
+            .run(0, &mut Ctx::new());
+        exporter.get_finished_spans().unwrap()
+    }
+
+    fn named<'a>(spans: &'a [SpanData], name: &str) -> &'a SpanData {
+        spans.iter().find(|s| s.name == name).unwrap()
+    }
+
+    fn attr(span: &SpanData, key: &str) -> Option<String> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.to_string())
+    }
+
+    #[test]
+    fn run_span_parents_step_spans() {
+        let inner = Workflow::builder("review")
+            .register(Step("edit", false))
+            .register(Finish)
+            .start_at("edit")
+            .then("finish")
+            .build()
+            .unwrap();
+        let wf = Workflow::builder("draft")
+            .register(Step("write", false))
+            .subworkflow(inner)
+            .register(Finish)
+            .start_at("write")
+            .then("review")
+            .then("finish")
+            .build()
+            .unwrap();
+        let spans = spans(wf);
+
+        let run = named(&spans, "workflow draft");
+        let write = named(&spans, "invoke_agent write");
+        let review = named(&spans, "invoke_agent review");
+        let edit = named(&spans, "invoke_agent review/edit");
+        assert_eq!(run.parent_span_id, SpanId::INVALID);
+        assert_eq!(write.parent_span_id, run.span_context.span_id());
+        assert_eq!(review.parent_span_id, run.span_context.span_id());
+        assert_eq!(edit.parent_span_id, review.span_context.span_id());
+        assert_eq!(
+            attr(write, "gen_ai.operation.name").as_deref(),
+            Some("invoke_agent")
+        );
+        assert_eq!(attr(write, "agent_line.step.number").as_deref(), Some("1"));
+        assert!(write.start_time <= write.end_time);
+        assert_eq!(run.status, Status::Ok);
+    }
+
+    #[test]
+    fn failed_step_and_run_are_marked_as_errors() {
+        let wf = Workflow::builder("draft")
+            .register(Step("write", true))
+            .build()
+            .unwrap();
+        let spans = spans(wf);
+
+        let write = named(&spans, "invoke_agent write");
+        assert!(matches!(write.status, Status::Error { .. }));
+        assert_eq!(attr(write, "error.type").as_deref(), Some("invalid"));
+        assert!(matches!(
+            named(&spans, "workflow draft").status,
+            Status::Error { .. }
+        ));
+    }
+
+    #[test]
+    fn error_types_are_low_cardinality() {
+        assert_eq!(
+            error_type(&StepError::transient("429 from api")),
+            "transient"
+        );
+        assert_eq!(error_type(&StepError::Timeout("slow".into())), "timeout");
+    }
+}
@@ -0,0 +1,833 @@
+use crate::runner::Boundary;
+use crate::{Ctx, CtxDiff, Provider, RunStatus, Runner, StepError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Whether a [`RecordedCall`] went to an LLM or to one of the
+/// [`crate::tools`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CallKind {
+    /// A chat request sent with [`crate::LlmRequestBuilder::send`] or
+    /// `send_async`.
+    Llm,
+    /// A function from [`crate::tools`].
+    Tool,
+}
+
+/// One LLM request or tool call made during a step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedCall {
+    /// What was called.
+    pub kind: CallKind,
+    /// The provider for LLM requests (`"ollama"`, ...), the function name
+    /// for tools (`"read_file"`, ...).
+    pub name: String,
+    /// The model and messages sent, or the tool's arguments.
+    pub input: Value,
+    /// The response text or the tool's return value, if the call succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    /// Why the call failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<StepError>,
+}
+
+/// One top-level step of a recorded run.
+///
+/// Parallel steps and sub-workflows are recorded as a single step; the
+/// calls made by their agents are all listed under it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepRecording {
+    /// The step's number, as hooks report it.
+    pub step_number: usize,
+    /// Number of steps completed before this one started.
+    pub completed_steps: usize,
+    /// Name of the step that ran.
+    pub agent: String,
+    /// Consecutive retry count for the step when it started.
+    pub retries: usize,
+    /// The state the step started with, serialized as JSON.
+    pub input: Value,
+    /// The input from [`Runner::resume_with`], if the step had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_input: Option<String>,
+    /// The state the step handed on, or `None` if the run failed here.
+    pub output: Option<Value>,
+    /// The outcome the step returned, as `Debug` prints it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<String>,
+    /// The failure that ended the step, caught or not.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<StepError>,
+    /// What the step changed in the context.
+    pub ctx: CtxDiff,
+    /// Every LLM request and tool call made during the step, retries
+    /// included, in the order they finished.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<RecordedCall>,
+}
+
+/// The first line of a run file.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    workflow: String,
+    ctx: Ctx,
+}
+
+/// A run written by [`Runner::with_recording`], loaded back for inspection
+/// or a [`Replayer`].
+#[derive(Clone)]
+pub struct RunRecording {
+    /// Name of the workflow that ran.
+    pub workflow: String,
+    /// The context before the first recorded step.
+    pub ctx: Ctx,
+    /// Every top-level step, in order.
+    pub steps: Vec<StepRecording>,
+}
+
+impl RunRecording {
+    /// Read a run file. A half-written last line, left by a crash, is
+    /// skipped.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, StepError> {
+        let path = path.as_ref();
+        let corrupt = |e: serde_json::Error| {
+            StepError::other(format!("corrupt run file {}: {e}", path.display()))
+        };
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header: Header = match lines.next() {
+            Some(line) => serde_json::from_str(&line?).map_err(corrupt)?,
+            None => {
+                return Err(StepError::invalid(format!(
+                    "empty run file {}",
+                    path.display()
+                )));
+            }
+        };
+
+        let lines: Vec<String> = lines.collect::<Result<_, _>>()?;
+        let mut steps = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(step) => steps.push(step),
+                Err(e) if i + 1 == lines.len() && e.is_eof() => break,
+                Err(e) => return Err(corrupt(e)),
+            }
+        }
+        Ok(Self {
+            workflow: header.workflow,
+            ctx: header.ctx,
+            steps,
+        })
+    }
+
+    /// The recorded step numbered `step_number`.
+    pub fn step(&self, step_number: usize) -> Option<&StepRecording> {
+        self.position(step_number).map(|i| &self.steps[i])
+    }
+
+    /// The state step `step_number` started with.
+    pub fn state_at<S: DeserializeOwned>(&self, step_number: usize) -> Result<S, StepError> {
+        let step = self.find(step_number)?;
+        serde_json::from_value(step.input.clone())
+            .map_err(|e| StepError::invalid(format!("recorded state at step {step_number}: {e}")))
+    }
+
+    /// The context step `step_number` started with, rebuilt from the
+    /// recorded changes of the steps before it.
+    pub fn ctx_at(&self, step_number: usize) -> Result<Ctx, StepError> {
+        let index = self.index(step_number)?;
+        let mut ctx = self.ctx.clone();
+        for step in &self.steps[..index] {
+            ctx.apply(&step.ctx);
+        }
+        ctx.set_resume_input(self.steps[index].resume_input.clone());
+        Ok(ctx)
+    }
+
+    fn position(&self, step_number: usize) -> Option<usize> {
+        self.steps.iter().position(|s| s.step_number == step_number)
+    }
+
+    fn index(&self, step_number: usize) -> Result<usize, StepError> {
+        self.position(step_number).ok_or_else(|| {
+            StepError::invalid(format!("no top-level step {step_number} in the recording"))
+        })
+    }
+
+    fn find(&self, step_number: usize) -> Result<&StepRecording, StepError> {
+        self.index(step_number).map(|i| &self.steps[i])
+    }
+}
+
+/// Re-runs a recorded run from any top-level step, answering LLM requests
+/// with the responses recorded for them.
+///
+/// The run restarts at the step with the state and context it had in the
+/// recording, so a bad decision at step 12 can be reproduced, and fixed,
+/// without repeating steps 1 to 11. A request is answered by the first
+/// unused recorded response, from that step on, to an identical request;
+/// one the recording has no answer for fails the step unless
+/// [`allow_live`](Self::allow_live) is set. Tools run for real.
+///
+/// ```rust,no_run
+/// # use agent_line::{Agent, Ctx, Outcome, Replayer, RunRecording, Runner, StepResult, Workflow};
+/// # struct Review;
+/// # impl Agent<String> for Review {
+/// #     fn name(&self) -> &'static str { "review" }
+/// #     fn run(&mut self, draft: String, _ctx: &mut Ctx) -> StepResult<String> {
+/// #         Ok((draft, Outcome::Done))
+/// #     }
+/// # }
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let wf = Workflow::builder("review").register(Review).build()?;
+/// let recording = RunRecording::load("runs/review.jsonl")?;
+/// let mut runner = Runner::new(wf);
+/// let mut ctx = Ctx::new();
+/// let status = Replayer::new(recording).run(&mut runner, 12, &mut ctx)?;
+/// # Ok(()) }
+/// ```
+pub struct Replayer {
+    recording: RunRecording,
+    live: bool,
+}
+
+impl Replayer {
+    /// Replay `recording`. Requests it has no answer for fail.
+    pub fn new(recording: RunRecording) -> Self {
+        Self {
+            recording,
+            live: false,
+        }
+    }
+
+    /// Send requests the recording has no answer for to the LLM instead of
+    /// failing, e.g. after changing a prompt.
+    pub fn allow_live(mut self, live: bool) -> Self {
+        self.live = live;
+        self
+    }
+
+    /// The recording being replayed.
+    pub fn recording(&self) -> &RunRecording {
+        &self.recording
+    }
+
+    /// Run `runner` from recorded step `step_number` to the end. `ctx` is
+    /// replaced with the context the step started with.
+    ///
+    /// The replay is not recorded and saves no checkpoints.
+    pub fn run<S>(
+        &self,
+        runner: &mut Runner<S>,
+        step_number: usize,
+        ctx: &mut Ctx,
+    ) -> Result<RunStatus<S>, StepError>
+    where
+        S: Clone + Send + 'static + DeserializeOwned,
+    {
+        let recording = &self.recording;
+        if recording.workflow != runner.workflow_name() {
+            return Err(StepError::invalid(format!(
+                "recording is of workflow '{}', not '{}'",
+                recording.workflow,
+                runner.workflow_name()
+            )));
+        }
+        let index = recording.index(step_number)?;
+        let step = &recording.steps[index];
+        let state = recording.state_at(step_number)?;
+        *ctx = recording.ctx_at(step_number)?;
+
+        let answers = recording.steps[index..]
+            .iter()
+            .flat_map(|s| &s.calls)
+            .filter(|call| call.kind == CallKind::Llm)
+            .cloned()
+            .collect();
+        let tape = Tape::new(Reel {
+            recorded: None,
+            answers,
+            live: self.live,
+        });
+        runner.replay(
+            state,
+            ctx,
+            &step.agent,
+            step.retries,
+            step.completed_steps,
+            &tape,
+        )
+    }
+}
+
+/// Calls collected for the step in progress, and the recorded answers a
+/// replay hands out. Attached to every thread that runs agents for the
+/// run, so calls are found without threading anything through agents.
+#[derive(Clone)]
+pub(crate) struct Tape(Arc<Mutex<Reel>>);
+
+struct Reel {
+    /// Calls made since the last step ended, if recording.
+    recorded: Option<Vec<RecordedCall>>,
+    /// Recorded LLM calls a replay has not used yet.
+    answers: Vec<RecordedCall>,
+    /// Whether requests without an answer go to the LLM.
+    live: bool,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Tape>> = const { RefCell::new(None) };
+}
+
+/// Detaches a [`Tape`] from the thread when dropped.
+pub(crate) struct Attached(Option<Tape>);
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        CURRENT.set(self.0.take());
+    }
+}
+
+impl Tape {
+    fn new(reel: Reel) -> Self {
+        Self(Arc::new(Mutex::new(reel)))
+    }
+
+    /// A tape that records every call.
+    pub(crate) fn recording() -> Self {
+        Self::new(Reel {
+            recorded: Some(Vec::new()),
+            answers: Vec::new(),
+            live: true,
+        })
+    }
+
+    /// The tape attached to this thread, to attach to a thread it spawns.
+    pub(crate) fn current() -> Option<Self> {
+        CURRENT.with_borrow(Clone::clone)
+    }
+
+    /// Attach to this thread until the guard is dropped.
+    pub(crate) fn attach(&self) -> Attached {
+        Attached(CURRENT.replace(Some(self.clone())))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Reel> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The calls recorded since the last time this was called.
+    fn take_calls(&self) -> Vec<RecordedCall> {
+        self.lock()
+            .recorded
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn record(&self, call: RecordedCall) {
+        if let Some(calls) = self.lock().recorded.as_mut() {
+            calls.push(call);
+        }
+    }
+
+    /// Record the result of an LLM request to `provider`.
+    fn record_llm(&self, provider: Provider, input: Value, result: &Result<String, StepError>) {
+        self.record(RecordedCall {
+            kind: CallKind::Llm,
+            name: provider.name().to_string(),
+            input,
+            output: result.as_ref().ok().map(|text| Value::String(text.clone())),
+            error: result.as_ref().err().cloned(),
+        });
+    }
+
+    /// The recorded answer to an LLM request, if this is a replay.
+    fn answer(&self, name: &str, input: &Value) -> Option<Result<String, StepError>> {
+        let mut reel = self.lock();
+        let found = reel
+            .answers
+            .iter()
+            .position(|call| call.name == name && call.input == *input);
+        match found {
+            Some(i) => {
+                let call = reel.answers.remove(i);
+                Some(match (call.error, call.output) {
+                    (Some(err), _) => Err(err),
+                    (None, Some(Value::String(text))) => Ok(text),
+                    (None, output) => Err(StepError::other(format!(
+                        "recorded {name} response is not text: {output:?}"
+                    ))),
+                })
+            }
+            None if reel.live => None,
+            None => Some(Err(StepError::invalid(format!(
+                "no recorded {name} response for this request to {}",
+                input["model"]
+            )))),
+        }
+    }
+}
+
+/// Send an LLM request through the attached tape, if any: answered from
+/// the recording during a replay, recorded while recording.
+pub(crate) fn llm(
+    provider: Provider,
+    model: &str,
+    messages: &[Value],
+    send: impl FnOnce() -> Result<String, StepError>,
+) -> Result<String, StepError> {
+    let Some(tape) = Tape::current() else {
+        return send();
+    };
+    let input = serde_json::json!({ "model": model, "messages": messages });
+    if let Some(answer) = tape.answer(provider.name(), &input) {
+        return answer;
+    }
+
+    let result = send();
+    tape.record_llm(provider, input, &result);
+    result
+}
+
+/// [`llm`] for a request sent from async code. The tape is the one
+/// attached to the thread that first polls the future, so calls made from
+/// a sync agent through `block_on` are recorded and replayed like
+/// blocking ones.
+#[cfg(feature = "async")]
+pub(crate) async fn llm_async(
+    provider: Provider,
+    model: &str,
+    messages: &[Value],
+    send: impl Future<Output = Result<String, StepError>>,
+) -> Result<String, StepError> {
+    let Some(tape) = Tape::current() else {
+        return send.await;
+    };
+    let input = serde_json::json!({ "model": model, "messages": messages });
+    if let Some(answer) = tape.answer(provider.name(), &input) {
+        return answer;
+    }
+
+    let result = send.await;
+    tape.record_llm(provider, input, &result);
+    result
+}
+
+/// Run tool `name`, recording its arguments and result if the thread has
+/// a recording tape attached.
+pub(crate) fn tool<T: Serialize>(
+    name: &str,
+    input: impl FnOnce() -> Value,
+    run: impl FnOnce() -> Result<T, StepError>,
+) -> Result<T, StepError> {
+    let result = run();
+    record_tool(name, input, &result);
+    result
+}
+
+/// Record a tool call that has already run.
+pub(crate) fn record_tool<T: Serialize>(
+    name: &str,
+    input: impl FnOnce() -> Value,
+    result: &Result<T, StepError>,
+) {
+    let Some(tape) = Tape::current() else {
+        return;
+    };
+    tape.record(RecordedCall {
+        kind: CallKind::Tool,
+        name: name.to_string(),
+        input: input(),
+        output: result
+            .as_ref()
+            .ok()
+            .map(|output| serde_json::to_value(output).unwrap_or_default()),
+        error: result.as_ref().err().cloned(),
+    });
+}
+
+/// Where the runner's position was at the last step boundary.
+struct Pending {
+    agent: String,
+    retries: usize,
+    completed_steps: usize,
+    input: Value,
+    resume_input: Option<String>,
+    ctx: Ctx,
+}
+
+/// Appends one [`StepRecording`] per top-level step to a run file.
+pub(crate) struct RecordWriter {
+    file: File,
+    tape: Tape,
+    pending: Option<Pending>,
+}
+
+impl RecordWriter {
+    /// Open the run file at `path`. A `fresh` run replaces it; otherwise
+    /// steps are appended, after a header if the file is new.
+    pub(crate) fn open(
+        path: &Path,
+        workflow: &str,
+        ctx: &Ctx,
+        fresh: bool,
+        tape: Tape,
+    ) -> Result<Self, StepError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(!fresh)
+            .write(true)
+            .truncate(fresh)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            write_line(
+                &mut file,
+                &Header {
+                    workflow: workflow.to_string(),
+                    ctx: ctx.clone(),
+                },
+            )?;
+        }
+        Ok(Self {
+            file,
+            tape,
+            pending: None,
+        })
+    }
+
+    /// The tape collecting calls for the step in progress.
+    pub(crate) fn tape(&self) -> &Tape {
+        &self.tape
+    }
+
+    /// Close the step that led to `boundary`, if any, and remember where
+    /// the next one starts.
+    pub(crate) fn boundary<S>(
+        &mut self,
+        boundary: &Boundary<'_, S>,
+        state: Value,
+    ) -> Result<(), StepError> {
+        if let Some(pending) = self.pending.take() {
+            self.write(
+                pending,
+                boundary.step_number,
+                Some(state.clone()),
+                boundary.outcome.map(|outcome| format!("{outcome:?}")),
+                boundary.error.cloned(),
+                boundary.ctx,
+            )?;
+        }
+        self.pending = boundary.next.map(|agent| Pending {
+            agent: agent.to_string(),
+            retries: boundary.retries,
+            completed_steps: boundary.step_number,
+            input: state,
+            resume_input: boundary.ctx.resume_input().map(str::to_string),
+            ctx: boundary.ctx.clone(),
+        });
+        Ok(())
+    }
+
+    /// Close the step in progress as the one the run failed at.
+    pub(crate) fn fail(
+        &mut self,
+        step_number: usize,
+        ctx: &Ctx,
+        error: &StepError,
+    ) -> Result<(), StepError> {
+        match self.pending.take() {
+            Some(pending) => self.write(pending, step_number, None, None, Some(error.clone()), ctx),
+            None => Ok(()),
+        }
+    }
+
+    fn write(
+        &mut self,
+        pending: Pending,
+        step_number: usize,
+        output: Option<Value>,
+        outcome: Option<String>,
+        error: Option<StepError>,
+        ctx: &Ctx,
+    ) -> Result<(), StepError> {
+        let step = StepRecording {
+            step_number,
+            completed_steps: pending.completed_steps,
+            agent: pending.agent,
+            retries: pending.retries,
+            input: pending.input,
+            resume_input: pending.resume_input,
+            output,
+            outcome,
+            error,
+            ctx: pending.ctx.diff(ctx),
+            calls: self.tape.take_calls(),
+        };
+        write_line(&mut self.file, &step)
+    }
+}
+
+fn write_line(file: &mut File, value: &impl Serialize) -> Result<(), StepError> {
+    let mut line = serde_json::to_vec(value)
+        .map_err(|e| StepError::other(format!("failed to serialize recording: {e}")))?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Agent, Outcome, StepResult, Workflow};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn temp_file(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("agent-line-replay-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("run.jsonl")
+    }
+
+    /// Asks a fake model for the next word; every live answer is new.
+    struct Ask(Arc<AtomicUsize>);
+
+    impl Agent<Vec<String>> for Ask {
+        fn name(&self) -> &'static str {
+            "ask"
+        }
+        fn run(&mut self, mut words: Vec<String>, ctx: &mut Ctx) -> StepResult<Vec<String>> {
+            let prompt = serde_json::json!(format!("word {}", words.len()));
+            let calls = &self.0;
+            let word = llm(Provider::Ollama, "fake", &[prompt], || {
+                Ok(format!("live{}", calls.fetch_add(1, Ordering::SeqCst)))
+            })?;
+            ctx.set("last", word.as_str());
+            ctx.log(format!("asked for {}", words.len()));
+            words.push(word);
+            let outcome = if words.len() < 3 {
+                Outcome::next("ask")
+            } else {
+                Outcome::Continue
+            };
+            Ok((words, outcome))
+        }
+    }
+
+    struct Check;
+
+    impl Agent<Vec<String>> for Check {
+        fn name(&self) -> &'static str {
+            "check"
+        }
+        fn run(&mut self, words: Vec<String>, _ctx: &mut Ctx) -> StepResult<Vec<String>> {
+            let listed = tool("count_words", || serde_json::json!({}), || Ok(words.len()))?;
+            if listed == 3 {
+                Ok((words, Outcome::Done))
+            } else {
+                Err(StepError::invalid("wrong word count"))
+            }
+        }
+    }
+
+    fn runner(calls: &Arc<AtomicUsize>) -> Runner<Vec<String>> {
+        let wf = Workflow::builder("words")
+            .register(Ask(Arc::clone(calls)))
+            .register(Check)
+            .then("check")
+            .build()
+            .unwrap();
+        Runner::new(wf)
+    }
+
+    #[test]
+    fn records_each_top_level_step() {
+        let path = temp_file("record");
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut ctx = Ctx::new();
+        ctx.set("user", "ada");
+        let words = runner(&calls)
+            .with_recording(&path)
+            .run(Vec::new(), &mut ctx)
+            .unwrap();
+        assert_eq!(words, ["live0", "live1", "live2"]);
+
+        let recording = RunRecording::load(&path).unwrap();
+        assert_eq!(recording.workflow, "words");
+        assert_eq!(recording.ctx.get("user"), Some("ada"));
+        let agents: Vec<_> = recording.steps.iter().map(|s| s.agent.as_str()).collect();
+        assert_eq!(agents, ["ask", "ask", "ask", "check"]);
+
+        let second = recording.step(2).unwrap();
+        assert_eq!(second.completed_steps, 1);
+        assert_eq!(second.input, serde_json::json!(["live0"]));
+        assert_eq!(second.output, Some(serde_json::json!(["live0", "live1"])));
+        assert_eq!(second.outcome.as_deref(), Some("Next(\"ask\")"));
+        assert_eq!(second.ctx.set["last"], "live1");
+        assert_eq!(second.ctx.log, ["asked for 1"]);
+        assert_eq!(second.calls.len(), 1);
+        assert_eq!(second.calls[0].kind, CallKind::Llm);
+        assert_eq!(second.calls[0].output, Some(serde_json::json!("live1")));
+
+        let check = recording.step(4).unwrap();
+        assert_eq!(check.calls[0].kind, CallKind::Tool);
+        assert_eq!(check.calls[0].output, Some(serde_json::json!(3)));
+
+        let words: Vec<String> = recording.state_at(3).unwrap();
+        assert_eq!(words, ["live0", "live1"]);
+        let ctx = recording.ctx_at(3).unwrap();
+        assert_eq!(ctx.get("last"), Some("live1"));
+        assert_eq!(ctx.logs(), ["asked for 0", "asked for 1"]);
+    }
+
+    /// Like [`Ask`], but sends through `llm_async` on a runtime of its own.
+    #[cfg(feature = "async")]
+    struct AskAsync(Arc<AtomicUsize>);
+
+    #[cfg(feature = "async")]
+    impl Agent<Vec<String>> for AskAsync {
+        fn name(&self) -> &'static str {
+            "ask"
+        }
+        fn run(&mut self, mut words: Vec<String>, _ctx: &mut Ctx) -> StepResult<Vec<String>> {
+            let prompt = serde_json::json!(format!("word {}", words.len()));
+            let calls = &self.0;
+            let send = async { Ok(format!("live{}", calls.fetch_add(1, Ordering::SeqCst))) };
+            let word = tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(llm_async(Provider::Ollama, "fake", &[prompt], send))?;
+            words.push(word);
+            Ok((words, Outcome::Done))
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_requests_are_recorded_and_replayed() {
+        let path = temp_file("async");
+        let calls = Arc::new(AtomicUsize::new(0));
+        let wf = || {
+            Workflow::builder("words")
+                .register(AskAsync(Arc::clone(&calls)))
+                .build()
+                .unwrap()
+        };
+        Runner::new(wf())
+            .with_recording(&path)
+            .run(Vec::new(), &mut Ctx::new())
+            .unwrap();
+        let recording = RunRecording::load(&path).unwrap();
+        assert_eq!(
+            recording.steps[0].calls[0].output,
+            Some(serde_json::json!("live0"))
+        );
+
+        let status = Replayer::new(recording)
+            .run(&mut Runner::new(wf()), 1, &mut Ctx::new())
+            .unwrap();
+        assert!(matches!(status, RunStatus::Done(words) if words == ["live0"]));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn replays_from_a_step_with_recorded_answers() {
+        let path = temp_file("replay");
+        let calls = Arc::new(AtomicUsize::new(0));
+        runner(&calls)
+            .with_recording(&path)
+            .run(Vec::new(), &mut Ctx::new())
+            .unwrap();
+        let recording = RunRecording::load(&path).unwrap();
+
+        // A fresh runner would get different answers from the model.
+        let mut replay = runner(&calls);
+        let mut ctx = Ctx::new();
+        let status = Replayer::new(recording.clone())
+            .run(&mut replay, 2, &mut ctx)
+            .unwrap();
+        assert_eq!(status.into_done().unwrap(), ["live0", "live1", "live2"]);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(ctx.logs(), ["asked for 0", "asked for 1", "asked for 2"]);
+
+        // The replay was not recorded over the original.
+        assert_eq!(RunRecording::load(&path).unwrap().steps.len(), 4);
+
+        // Requests the recording never saw fail unless live calls are allowed.
+        let mut trimmed = recording;
+        trimmed.steps[2].calls.clear();
+        let replayer = Replayer::new(trimmed);
+        let err = replayer
+            .run(&mut runner(&calls), 2, &mut Ctx::new())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no recorded ollama response"));
+
+        let status = replayer
+            .allow_live(true)
+            .run(&mut runner(&calls), 2, &mut Ctx::new())
+            .unwrap();
+        assert_eq!(status.into_done().unwrap(), ["live0", "live1", "live3"]);
+    }
+
+    #[test]
+    fn records_the_failing_step() {
+        let path = temp_file("fail");
+        let wf = Workflow::builder("words").register(Check).build().unwrap();
+        let err = Runner::new(wf)
+            .with_recording(&path)
+            .run(vec!["only".into()], &mut Ctx::new())
+            .err()
+            .unwrap();
+
+        // A crash can leave a torn last line behind.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"step_number\":").unwrap();
+
+        let recording = RunRecording::load(&path).unwrap();
+        assert_eq!(recording.steps.len(), 1);
+        let step = &recording.steps[0];
+        assert_eq!(step.agent, "check");
+        assert_eq!(step.output, None);
+        assert_eq!(
+            step.error.as_ref().map(ToString::to_string),
+            Some(err.to_string())
+        );
+    }
+
+    #[test]
+    fn replay_needs_a_recorded_step_of_the_same_workflow() {
+        let path = temp_file("mismatch");
+        let calls = Arc::new(AtomicUsize::new(0));
+        runner(&calls)
+            .with_recording(&path)
+            .run(Vec::new(), &mut Ctx::new())
+            .unwrap();
+        let replayer = Replayer::new(RunRecording::load(&path).unwrap());
+
+        let err = replayer
+            .run(&mut runner(&calls), 9, &mut Ctx::new())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no top-level step 9"));
+
+        let other = Workflow::builder("other").register(Check).build().unwrap();
+        let err = replayer
+            .run(&mut Runner::new(other), 1, &mut Ctx::new())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("not 'other'"));
+    }
+}
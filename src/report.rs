@@ -0,0 +1,89 @@
+use crate::{Outcome, StepError, StepEvent};
+use std::time::Duration;
+
+/// One successful step of a run, as recorded in a [`RunReport`].
+#[derive(Debug, Clone)]
+pub struct StepRecord {
+    /// Name of the agent that ran, `parent/child` inside nested workflows
+    /// and parallel steps.
+    pub agent: String,
+    /// Which item the agent ran on, inside a map step.
+    pub item: Option<usize>,
+    /// The outcome the agent returned.
+    pub outcome: Outcome,
+    /// Wall-clock time for the step.
+    pub duration: Duration,
+    /// Sequential step counter (starts at 1).
+    pub step_number: usize,
+    /// Consecutive retry count for the agent when it ran.
+    pub retries: usize,
+}
+
+/// Everything [`crate::Runner::run_with_report`] saw during one run.
+///
+/// Unlike [`crate::Runner::run`], a failed run still hands back its state
+/// and the steps that led up to the failure.
+#[derive(Debug)]
+pub struct RunReport<S> {
+    /// The final state if the run finished; otherwise the state after the
+    /// last top-level step that succeeded (the initial state if none did).
+    pub state: S,
+    /// Every successful step, in order.
+    pub steps: Vec<StepRecord>,
+    /// Wall-clock time for the whole run.
+    pub duration: Duration,
+    /// The agent that failed, if the run failed.
+    pub failed_agent: Option<String>,
+    /// The step number the failure happened at, if the run failed.
+    pub failed_step: Option<usize>,
+    /// Why the run failed, or `None` if it finished.
+    pub error: Option<StepError>,
+}
+
+impl<S> RunReport<S> {
+    /// Whether the run finished without an error.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The final state, or the error, as [`crate::Runner::run`] returns them.
+    pub fn into_result(self) -> Result<S, StepError> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.state),
+        }
+    }
+}
+
+/// Steps and the failure point collected while a run executes.
+#[derive(Default)]
+pub(crate) struct Trace {
+    pub(crate) steps: Vec<StepRecord>,
+    pub(crate) failure: Option<(String, usize)>,
+}
+
+impl Trace {
+    pub(crate) fn step(&mut self, event: &StepEvent) {
+        // `Outcome::Fail` never reaches `on_error`, but it is where the run
+        // failed unless a handler catches it.
+        if matches!(event.outcome, Outcome::Fail(_)) {
+            self.error(event.agent, event.step_number);
+        }
+        self.steps.push(StepRecord {
+            agent: event.agent.to_string(),
+            item: event.item,
+            outcome: event.outcome.clone(),
+            duration: event.duration,
+            step_number: event.step_number,
+            retries: event.retries,
+        });
+    }
+
+    /// Record where the run failed. Errors are reported from the innermost
+    /// step outwards, so the first one is kept.
+    pub(crate) fn error(&mut self, agent: &str, step_number: usize) {
+        if self.failure.is_none() {
+            self.failure = Some((agent.to_string(), step_number));
+        }
+    }
+}
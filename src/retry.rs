@@ -0,0 +1,157 @@
+use crate::StepError;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+type RetryOn = Arc<dyn Fn(&StepError) -> bool + Send + Sync>;
+
+/// How often and how patiently an agent is retried.
+///
+/// A policy covers both an agent's own `Outcome::Retry`/`Outcome::Wait`
+/// and errors it returns. By default only [`StepError::Transient`] errors
+/// are retried, and [`StepError::Cancelled`] never is. Attach one to an
+/// agent with [`crate::WorkflowBuilder::retry_policy`]; agents without one
+/// use the runner's `max_retries` with no backoff.
+///
+/// ```rust
+/// use agent_line::RetryPolicy;
+/// use std::time::Duration;
+///
+/// // Up to 5 retries, waiting about 1s, 2s, 4s, 8s, 8s.
+/// let policy = RetryPolicy::new(5)
+///     .with_backoff(Duration::from_secs(1), Duration::from_secs(8))
+///     .with_jitter(true);
+/// # let _ = policy;
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_retries: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: bool,
+    retry_on: RetryOn,
+}
+
+impl RetryPolicy {
+    /// Retry up to `max_retries` times in a row, immediately, on transient
+    /// errors.
+    pub fn new(max_retries: usize) -> Self {
+        Self {
+            max_retries,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            multiplier: 2.0,
+            jitter: false,
+            retry_on: Arc::new(|err| matches!(err, StepError::Transient(_))),
+        }
+    }
+
+    /// Wait `initial` before the first retry, doubling each time up to `max`.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Grow the backoff by `multiplier` per retry instead of doubling.
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Randomize each backoff to between half and all of its computed value,
+    /// so many runs retrying at once don't hit a service in lockstep.
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Choose which errors are retried. [`StepError::Cancelled`] is never
+    /// retried, whatever the predicate says.
+    pub fn retry_on(
+        mut self,
+        predicate: impl Fn(&StepError) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.retry_on = Arc::new(predicate);
+        self
+    }
+
+    /// The most consecutive retries allowed.
+    pub fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    /// Whether `err` should be retried, ignoring the retry budget.
+    pub(crate) fn retries_error(&self, err: &StepError) -> bool {
+        !matches!(err, StepError::Cancelled(_)) && (self.retry_on)(err)
+    }
+
+    /// How long to wait before retry number `retry` (starting at 1).
+    pub(crate) fn backoff(&self, retry: usize) -> Duration {
+        if self.initial_backoff.is_zero() {
+            return Duration::ZERO;
+        }
+        let exponent = retry.saturating_sub(1).min(64) as i32;
+        let secs = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = Duration::from_secs_f64(secs.min(self.max_backoff.as_secs_f64()));
+        if self.jitter {
+            delay.mul_f64(0.5 + 0.5 * unit_random())
+        } else {
+            delay
+        }
+    }
+}
+
+/// A random number in `[0, 1)`, good enough for jitter.
+fn unit_random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_retries_only_transient_errors() {
+        let policy = RetryPolicy::new(3);
+        assert!(policy.retries_error(&StepError::transient("rate limited")));
+        assert!(!policy.retries_error(&StepError::invalid("bad input")));
+        assert!(!policy.retries_error(&StepError::Cancelled("stop".into())));
+    }
+
+    #[test]
+    fn custom_predicate_never_retries_cancelled() {
+        let policy = RetryPolicy::new(3).retry_on(|_| true);
+        assert!(policy.retries_error(&StepError::other("anything")));
+        assert!(!policy.retries_error(&StepError::Cancelled("stop".into())));
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_max() {
+        let policy = RetryPolicy::new(5)
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(350));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+        assert_eq!(policy.backoff(50), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn no_backoff_by_default() {
+        assert_eq!(RetryPolicy::new(3).backoff(2), Duration::ZERO);
+    }
+
+    #[test]
+    fn jitter_stays_within_half_and_full_delay() {
+        let policy = RetryPolicy::new(5)
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .with_jitter(true);
+        for _ in 0..100 {
+            let delay = policy.backoff(1);
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+        }
+    }
+}
@@ -1,10 +1,32 @@
-use crate::{Ctx, Outcome, StepError, Workflow};
+use crate::cancel::Cancel;
+use crate::ctx::CaughtError;
+use crate::observer::{
+    BeforeStepEvent, CatchEvent, ErrorFn, RetryEvent, RouteEvent, RunEndEvent, RunStartEvent,
+    StepFn, WaitEvent,
+};
+use crate::replay::{RecordWriter, Tape};
+use crate::report::Trace;
+use crate::workflow::{Merge, Name, Node};
+use crate::{
+    Agent, CancellationToken, Checkpoint, CheckpointStore, Ctx, Outcome, RetryPolicy, RunObserver,
+    RunReport, StepError, StepResult, Workflow,
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::{Duration, Instant};
 
 /// Passed to the `on_step` hook after each successful agent step.
 pub struct StepEvent<'a> {
     /// Name of the agent that ran.
     pub agent: &'a str,
+    /// Which item the agent ran on, inside a map step.
+    pub item: Option<usize>,
     /// The outcome the agent returned.
     pub outcome: &'a Outcome,
     /// Wall-clock time for the step.
@@ -19,25 +41,210 @@ pub struct StepEvent<'a> {
 pub struct ErrorEvent<'a> {
     /// Name of the agent that errored.
     pub agent: &'a str,
+    /// Which item the agent ran on, inside a map step.
+    pub item: Option<usize>,
     /// The error that occurred.
     pub error: &'a StepError,
     /// Step number where the error happened.
     pub step_number: usize,
+    /// How many times the agent had already been retried in a row when the
+    /// error happened. Errors that get retried are not reported.
+    pub retries: usize,
+}
+
+/// How a run stopped without an error: finished, or paused by an agent.
+#[derive(Debug)]
+pub enum RunStatus<S> {
+    /// The workflow returned `Done` with this final state.
+    Done(S),
+    /// An agent returned [`Outcome::Pause`]. Continue with
+    /// [`Runner::resume_with`].
+    Paused(Paused<S>),
+}
+
+impl<S> RunStatus<S> {
+    /// The final state, or an error if the run paused.
+    pub fn into_done(self) -> Result<S, StepError> {
+        match self {
+            RunStatus::Done(state) => Ok(state),
+            RunStatus::Paused(paused) => Err(StepError::other(format!(
+                "workflow paused at '{}': {}",
+                paused.agent, paused.reason
+            ))),
+        }
+    }
+}
+
+/// A paused run, returned by [`Runner::start`] when an agent returns
+/// [`Outcome::Pause`]. Inspect or edit [`state`](Paused::state), collect
+/// input from a human, then pass it to [`Runner::resume_with`].
+#[derive(Debug)]
+pub struct Paused<S> {
+    /// The state the pausing agent returned.
+    pub state: S,
+    /// Why the agent paused.
+    pub reason: String,
+    /// Data the agent attached for whoever resumes the run.
+    pub payload: String,
+    agent: Name,
+    resume_at: Cow<'static, str>,
+    step_number: usize,
+}
+
+impl<S> Paused<S> {
+    /// Name of the agent that paused.
+    pub fn agent(&self) -> &str {
+        &self.agent
+    }
+
+    /// Step number of the pausing step.
+    pub fn step_number(&self) -> usize {
+        self.step_number
+    }
+
+    /// Resume at `step` instead of re-running the agent that paused.
+    pub fn resume_at(mut self, step: impl Into<Cow<'static, str>>) -> Self {
+        self.resume_at = step.into();
+        self
+    }
+}
+
+/// Where the runner goes after a step, decided by [`route`].
+pub(crate) enum Route {
+    /// Return the current state.
+    Done,
+    /// Run another agent; the retry counter has been reset.
+    Goto(Name),
+    /// Re-run the current agent immediately.
+    Rerun,
+    /// Sleep, then re-run the current agent.
+    Sleep(Duration),
+    /// Stop with an error the agent asked for.
+    Stop(StepError),
+    /// Stop with an error the runner raised, such as an exceeded limit or a
+    /// failing router (reported to `on_error`).
+    Raised(StepError),
+    /// Hand control back to the caller; the retry counter has been reset.
+    Pause { reason: String, payload: String },
+}
+
+/// Routing rules shared by every runner: turn an agent's [`Outcome`] into
+/// the next move, counting consecutive retries against `max_retries`.
+/// `step_named` looks up the targets of `Next`.
+pub(crate) fn route(
+    current: &str,
+    outcome: Outcome,
+    default_next: Option<Name>,
+    step_named: impl Fn(&str) -> Option<Name>,
+    retries: &mut usize,
+    max_retries: usize,
+) -> Route {
+    match outcome {
+        Outcome::Done => Route::Done,
+        Outcome::Fail(msg) => Route::Stop(StepError::other(msg)),
+        Outcome::Pause { reason, payload } => {
+            *retries = 0;
+            Route::Pause { reason, payload }
+        }
+        Outcome::Next(step) => match step_named(&step) {
+            Some(next) => {
+                *retries = 0;
+                Route::Goto(next)
+            }
+            None => Route::Stop(StepError::other(format!(
+                "step '{current}' returned Next to unknown step '{step}'"
+            ))),
+        },
+        Outcome::Continue => match default_next {
+            Some(next) => {
+                *retries = 0;
+                Route::Goto(next)
+            }
+            None => Route::Stop(StepError::other(format!(
+                "step '{current}' returned Continue but no default next step is configured"
+            ))),
+        },
+        Outcome::Retry(hint) => {
+            *retries += 1;
+            if *retries > max_retries {
+                return Route::Raised(StepError::other(format!(
+                    "step '{}' exceeded max retries ({}): {}",
+                    current, max_retries, hint.reason
+                )));
+            }
+            Route::Rerun
+        }
+        Outcome::Wait(dur) => {
+            *retries += 1;
+            if *retries > max_retries {
+                return Route::Raised(StepError::other(format!(
+                    "step '{}' exceeded max retries ({}) while waiting",
+                    current, max_retries
+                )));
+            }
+            Route::Sleep(dur)
+        }
+    }
+}
+
+/// Saves where a top-level run stands after each step: the next agent
+/// (`None` once done), its retry count, the step number, state and context.
+type SaveHook<'a, S> = &'a mut dyn FnMut(&Boundary<'_, S>) -> Result<(), StepError>;
+
+/// Where a top-level run stands between two steps, handed to the hook that
+/// saves checkpoints and recordings.
+pub(crate) struct Boundary<'a, S> {
+    /// The step that runs next, or `None` once the run has finished.
+    pub(crate) next: Option<&'a str>,
+    /// Consecutive retry count for `next`.
+    pub(crate) retries: usize,
+    /// Number of steps completed so far.
+    pub(crate) step_number: usize,
+    pub(crate) state: &'a S,
+    pub(crate) ctx: &'a Ctx,
+    /// The outcome of the step that just ran, if it returned one.
+    pub(crate) outcome: Option<&'a Outcome>,
+    /// The failure of the step that just ran, if it was caught.
+    pub(crate) error: Option<&'a StepError>,
 }
 
-type StepHook = Box<dyn FnMut(&StepEvent)>;
-type ErrorHook = Box<dyn FnMut(&ErrorEvent)>;
+/// What [`Runner::run_with_report`] collects on top of the hooks: the
+/// trace, and the state after the last top-level step that succeeded.
+struct Recording<S> {
+    trace: Trace,
+    last_state: Option<S>,
+}
+
+/// A checkpoint store plus the (de)serializers for the runner's state type,
+/// captured where `S: Serialize` is known.
+struct Checkpointing<S> {
+    store: Box<dyn CheckpointStore>,
+    run_id: String,
+    encode: fn(&S) -> serde_json::Result<serde_json::Value>,
+    decode: fn(serde_json::Value) -> serde_json::Result<S>,
+}
+
+/// Where to record runs, plus the serializer for the runner's state type.
+struct Recorder<S> {
+    path: PathBuf,
+    encode: fn(&S) -> serde_json::Result<serde_json::Value>,
+}
 
 /// Executes a [`Workflow`] step by step, handling retries, waits, and routing.
-pub struct Runner<S: Clone + 'static> {
+pub struct Runner<S: Clone + Send + 'static> {
     wf: Workflow<S>,
     max_steps: usize,
     max_retries: usize,
-    on_step: Option<StepHook>,
-    on_error: Option<ErrorHook>,
+    max_visits: Option<usize>,
+    observers: Vec<Box<dyn RunObserver>>,
+    checkpoints: Option<Checkpointing<S>>,
+    recorder: Option<Recorder<S>>,
+    cancellation: Option<CancellationToken>,
+    deadline: Option<Duration>,
+    step_timeout: Option<Duration>,
 }
 
-impl<S: Clone + 'static> Runner<S> {
+impl<S: Clone + Send + 'static> Runner<S> {
     /// Create a runner for the given workflow with default limits
     /// (max_steps: 10,000, max_retries: 3).
     pub fn new(wf: Workflow<S>) -> Self {
@@ -45,8 +252,13 @@ impl<S: Clone + 'static> Runner<S> {
             wf,
             max_steps: 10_000,
             max_retries: 3,
-            on_step: None,
-            on_error: None,
+            max_visits: None,
+            observers: Vec::new(),
+            checkpoints: None,
+            recorder: None,
+            cancellation: None,
+            deadline: None,
+            step_timeout: None,
         }
     }
 
@@ -62,19 +274,90 @@ impl<S: Clone + 'static> Runner<S> {
         self
     }
 
-    /// Register a callback that fires after each successful agent step.
-    pub fn on_step(mut self, cb: impl FnMut(&StepEvent) + 'static) -> Self {
-        self.on_step = Some(Box::new(cb));
+    /// Let a run enter any one step at most `n` times, unless the workflow
+    /// sets its own limit with [`WorkflowBuilder::max_visits`]. Unlike
+    /// `max_steps` this catches a loop early, at the step that loops. Off
+    /// by default. Counts start over when a run resumes from a checkpoint,
+    /// and each time a sub-workflow is entered.
+    ///
+    /// [`WorkflowBuilder::max_visits`]: crate::WorkflowBuilder::max_visits
+    pub fn with_max_visits(mut self, n: usize) -> Self {
+        self.max_visits = Some(n);
+        self
+    }
+
+    /// Fail any agent step that runs longer than `limit` with
+    /// [`StepError::Timeout`]. Agents with their own
+    /// [`WorkflowBuilder::timeout`](crate::WorkflowBuilder::timeout) use that
+    /// instead. Retry timed-out steps with a [`RetryPolicy`] that accepts
+    /// `StepError::Timeout`.
+    pub fn with_step_timeout(mut self, limit: Duration) -> Self {
+        self.step_timeout = Some(limit);
+        self
+    }
+
+    /// Stop runs when `token` is cancelled. The runner checks it before every
+    /// step and wakes from `Outcome::Wait` sleeps as soon as it fires; the
+    /// run then fails with [`StepError::Cancelled`].
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
         self
     }
 
-    /// Register a callback that fires when an agent errors or a limit is exceeded.
-    pub fn on_error(mut self, cb: impl FnMut(&ErrorEvent) + 'static) -> Self {
-        self.on_error = Some(Box::new(cb));
+    /// Fail a run with [`StepError::Cancelled`] once it has taken longer
+    /// than `limit`. Each call to [`run`](Self::run), [`start`](Self::start)
+    /// or a resume method gets the full `limit`. A step already running is
+    /// not interrupted, but agents can poll [`Ctx::is_cancelled`].
+    pub fn with_deadline(mut self, limit: Duration) -> Self {
+        self.deadline = Some(limit);
+        self
+    }
+
+    /// Register a callback that fires after each successful agent step.
+    /// Callbacks add up: registering another keeps the earlier ones.
+    pub fn on_step(self, cb: impl FnMut(&StepEvent) + Send + 'static) -> Self {
+        self.with_observer(StepFn(cb))
+    }
+
+    /// Register a callback that fires when an agent errors or a limit is
+    /// exceeded and the run gives up. Failures inside parallel steps and
+    /// sub-workflows are reported once, under the step that contains them;
+    /// see [`RunObserver::on_inner_error`] for the inner ones. Callbacks add
+    /// up: registering another keeps the earlier ones.
+    pub fn on_error(self, cb: impl FnMut(&ErrorEvent) + Send + 'static) -> Self {
+        self.with_observer(ErrorFn(cb))
+    }
+
+    /// Attach a [`RunObserver`] for the full run lifecycle. Any number can
+    /// be attached, alongside `on_step` and `on_error` callbacks; they are
+    /// notified in the order they were added.
+    pub fn with_observer(mut self, observer: impl RunObserver + 'static) -> Self {
+        self.observers.push(Box::new(observer));
         self
     }
 
-    /// Set both hooks to print step transitions and errors to stderr.
+    /// Report runs as they go, after any hooks and observers already
+    /// attached.
+    ///
+    /// With the `tracing` feature this attaches an observer that reports
+    /// runs, steps, routes and errors as `tracing` spans and events; runners
+    /// without it emit none. Without the feature it prints each finished
+    /// step and each error to stderr through an `on_step` and an `on_error`
+    /// callback.
+    #[cfg(feature = "tracing")]
+    pub fn with_tracing(self) -> Self {
+        self.with_observer(crate::spans::TracingObserver::default())
+    }
+
+    /// Report runs as they go, after any hooks and observers already
+    /// attached.
+    ///
+    /// With the `tracing` feature this attaches an observer that reports
+    /// runs, steps, routes and errors as `tracing` spans and events; runners
+    /// without it emit none. Without the feature it prints each finished
+    /// step and each error to stderr through an `on_step` and an `on_error`
+    /// callback.
+    #[cfg(not(feature = "tracing"))]
     pub fn with_tracing(self) -> Self {
         self.on_step(|e| {
             eprintln!(
@@ -92,126 +375,1285 @@ impl<S: Clone + 'static> Runner<S> {
 
     /// Run the workflow to completion, returning the final state or an error.
     /// Can be called multiple times on the same runner.
-    pub fn run(&mut self, mut state: S, ctx: &mut Ctx) -> Result<S, StepError> {
-        let mut current = self.wf.start();
-        let mut retries: usize = 0;
-        let mut step_number: usize = 0;
-
-        for _ in 0..self.max_steps {
-            step_number += 1;
-
-            let agent = self
-                .wf
-                .agent_mut(current)
-                .ok_or_else(|| StepError::other(format!("unknown step: {current}")))?;
-
-            let start = Instant::now();
-            let result = agent.run(state.clone(), ctx);
-            let duration = start.elapsed();
-
-            match result {
-                Err(err) => {
-                    if let Some(cb) = &mut self.on_error {
-                        cb(&ErrorEvent {
-                            agent: current,
-                            error: &err,
-                            step_number,
-                        });
-                    }
+    ///
+    /// With [`with_checkpoints`](Self::with_checkpoints), each call starts
+    /// the configured run over and replaces its earlier checkpoints.
+    ///
+    /// An agent returning [`Outcome::Pause`] makes `run` fail; use
+    /// [`start`](Self::start) for workflows that pause.
+    pub fn run(&mut self, state: S, ctx: &mut Ctx) -> Result<S, StepError> {
+        self.start(state, ctx).and_then(RunStatus::into_done)
+    }
+
+    /// Like [`run`](Self::run), but an agent returning [`Outcome::Pause`]
+    /// stops the run with a [`Paused`] handle instead of an error.
+    pub fn start(&mut self, state: S, ctx: &mut Ctx) -> Result<RunStatus<S>, StepError> {
+        let run_id = self.checkpoints.as_ref().map(|c| c.run_id.clone());
+        let start = self.wf.start().clone();
+        self.drive(state, ctx, start, 0, 0, run_id.as_deref(), None)
+    }
+
+    /// Like [`run`](Self::run), but return a [`RunReport`] instead of a bare
+    /// result: the final or last good state, every step taken, the total
+    /// time, and where and why the run failed. Hooks still fire as usual.
+    ///
+    /// A run that pauses is reported as failed at the pausing step, with the
+    /// state that agent returned.
+    pub fn run_with_report(&mut self, state: S, ctx: &mut Ctx) -> RunReport<S> {
+        let run_id = self.checkpoints.as_ref().map(|c| c.run_id.clone());
+        let start = self.wf.start().clone();
+        let began = Instant::now();
+        let mut recording = Recording {
+            trace: Trace::default(),
+            last_state: None,
+        };
+        let initial = state.clone();
+        let result = self.drive(
+            state,
+            ctx,
+            start,
+            0,
+            0,
+            run_id.as_deref(),
+            Some(&mut recording),
+        );
+
+        let Recording { trace, last_state } = recording;
+        let mut report = RunReport {
+            state: last_state.unwrap_or(initial),
+            steps: trace.steps,
+            duration: began.elapsed(),
+            failed_agent: None,
+            failed_step: None,
+            error: None,
+        };
+        match result {
+            Ok(RunStatus::Done(state)) => report.state = state,
+            Ok(RunStatus::Paused(paused)) => {
+                report.failed_agent = Some(paused.agent.to_string());
+                report.failed_step = Some(paused.step_number);
+                report.error = RunStatus::Paused(paused).into_done().err();
+            }
+            Err(err) => {
+                // Other routing errors aren't reported to `on_error`; the
+                // step that caused them is the last one recorded.
+                let failed = trace.failure.or_else(|| {
+                    let last = report.steps.last()?;
+                    Some((last.agent.clone(), last.step_number))
+                });
+                if let Some((agent, step)) = failed {
+                    report.failed_agent = Some(agent);
+                    report.failed_step = Some(step);
+                }
+                report.error = Some(err);
+            }
+        }
+        report
+    }
+
+    /// Continue a paused run. The agent that paused runs again (or the step
+    /// chosen with [`Paused::resume_at`]), with `input` available through
+    /// [`Ctx::resume_input`] for that one step.
+    pub fn resume_with(
+        &mut self,
+        paused: Paused<S>,
+        input: impl Into<String>,
+        ctx: &mut Ctx,
+    ) -> Result<RunStatus<S>, StepError> {
+        let current = self
+            .wf
+            .step_named(&paused.resume_at)
+            .cloned()
+            .ok_or_else(|| {
+                StepError::invalid(format!(
+                    "cannot resume at unknown step: {}",
+                    paused.resume_at
+                ))
+            })?;
+        ctx.set_resume_input(Some(input.into()));
+        let run_id = self.checkpoints.as_ref().map(|c| c.run_id.clone());
+        self.drive(
+            paused.state,
+            ctx,
+            current,
+            0,
+            paused.step_number,
+            run_id.as_deref(),
+            None,
+        )
+    }
+
+    pub(crate) fn workflow_name(&self) -> &str {
+        self.wf.name()
+    }
+
+    /// Run from recorded step `agent` with `tape` answering LLM requests.
+    /// Nothing is recorded or checkpointed.
+    pub(crate) fn replay(
+        &mut self,
+        state: S,
+        ctx: &mut Ctx,
+        agent: &str,
+        retries: usize,
+        step_number: usize,
+        tape: &Tape,
+    ) -> Result<RunStatus<S>, StepError> {
+        let current = self
+            .wf
+            .step_named(agent)
+            .cloned()
+            .ok_or_else(|| StepError::invalid(format!("unknown step in recording: {agent}")))?;
+        let recorder = self.recorder.take();
+        let _tape = tape.attach();
+        let result = self.drive(state, ctx, current, retries, step_number, None, None);
+        self.recorder = recorder;
+        result
+    }
+
+    /// Run from `current`, checkpointing under `run_id` if given and
+    /// collecting a trace into `recording` if given.
+    #[allow(clippy::too_many_arguments)]
+    fn drive(
+        &mut self,
+        state: S,
+        ctx: &mut Ctx,
+        current: Name,
+        retries: usize,
+        step_number: usize,
+        run_id: Option<&str>,
+        recording: Option<&mut Recording<S>>,
+    ) -> Result<RunStatus<S>, StepError> {
+        let (trace, mut last_state) = match recording {
+            Some(r) => (Some(&mut r.trace), Some(&mut r.last_state)),
+            None => (None, None),
+        };
+        let began = Instant::now();
+        let workflow = self.wf.name().to_string();
+        let workflow = workflow.as_str();
+        let mut writer = match &self.recorder {
+            Some(recorder) => {
+                let tape = Tape::recording();
+                let writer =
+                    RecordWriter::open(&recorder.path, workflow, ctx, step_number == 0, tape)?;
+                Some(writer)
+            }
+            None => None,
+        };
+        let _tape = writer.as_ref().map(|w| w.tape().attach());
+        for observer in &mut self.observers {
+            observer.on_run_start(&RunStartEvent {
+                workflow,
+                agent: &current,
+                step_number,
+            });
+        }
+
+        let mut exec = Exec {
+            observers: &mut self.observers,
+            trace,
+            max_steps: self.max_steps,
+            max_retries: self.max_retries,
+            max_visits: self.max_visits,
+            step_timeout: self.step_timeout,
+            step_number,
+            depth: 0,
+        };
+        let recording = last_state.is_some() || writer.is_some();
+        let checkpoints = &mut self.checkpoints;
+        let recorder = &self.recorder;
+        let mut save = |at: &Boundary<'_, S>| {
+            if let Some(last) = last_state.as_mut() {
+                **last = Some(at.state.clone());
+            }
+            if let (Some(writer), Some(recorder)) = (writer.as_mut(), recorder) {
+                let state = (recorder.encode)(at.state)
+                    .map_err(|e| StepError::other(format!("failed to serialize state: {e}")))?;
+                writer.boundary(at, state)?;
+            }
+            match (checkpoints.as_mut(), run_id) {
+                (Some(cp), Some(run_id)) => cp.store.save(&Checkpoint {
+                    run_id: run_id.to_string(),
+                    workflow: workflow.to_string(),
+                    agent: at.next.map(str::to_string),
+                    retries: at.retries,
+                    step_number: at.step_number,
+                    state: (cp.encode)(at.state)
+                        .map_err(|e| StepError::other(format!("failed to serialize state: {e}")))?,
+                    ctx: at.ctx.clone(),
+                }),
+                _ => Ok(()),
+            }
+        };
+        let save: Option<SaveHook<S>> = if run_id.is_some() || recording {
+            Some(&mut save)
+        } else {
+            None
+        };
+
+        ctx.set_cancel(Cancel::new(self.cancellation.clone(), self.deadline));
+        let result = execute_from(
+            &mut self.wf,
+            state,
+            ctx,
+            &mut exec,
+            "",
+            current,
+            retries,
+            save,
+        );
+        ctx.set_cancel(Cancel::default());
+        if let (Some(writer), Err(err)) = (writer.as_mut(), &result) {
+            // The run already failed; a broken run file must not hide why.
+            let _ = writer.fail(exec.step_number, ctx, err);
+        }
+
+        let end = RunEndEvent {
+            workflow,
+            step_number: exec.step_number,
+            duration: began.elapsed(),
+            error: result.as_ref().err(),
+            paused: matches!(result, Ok(RunStatus::Paused(_))),
+        };
+        exec.notify(|o| o.on_run_end(&end));
+        result
+    }
+}
+
+impl<S: Clone + Send + 'static + Serialize + DeserializeOwned> Runner<S> {
+    /// Save a [`Checkpoint`] to `store` under `run_id` before the first step
+    /// and after every step, so a crashed run can be picked up again with
+    /// [`resume`](Self::resume).
+    ///
+    /// Steps inside parallel steps and sub-workflows are not checkpointed
+    /// individually: those nodes re-run from their start on resume.
+    pub fn with_checkpoints(
+        mut self,
+        store: impl CheckpointStore + 'static,
+        run_id: impl Into<String>,
+    ) -> Self {
+        self.checkpoints = Some(Checkpointing {
+            store: Box::new(store),
+            run_id: run_id.into(),
+            encode: |state| serde_json::to_value(state),
+            decode: serde_json::from_value,
+        });
+        self
+    }
+
+    /// Record every top-level step to the run file at `path`: the state it
+    /// started with and handed on, its outcome or error, what it changed in
+    /// the context, and the LLM requests and tool calls it made. Load the
+    /// file with [`RunRecording::load`](crate::RunRecording::load) to
+    /// inspect a run, or replay it from any step with a
+    /// [`Replayer`](crate::Replayer).
+    ///
+    /// The file holds one JSON object per line. Each run started from the
+    /// beginning replaces it; resumed runs append to it.
+    pub fn with_recording(mut self, path: impl Into<PathBuf>) -> Self {
+        self.recorder = Some(Recorder {
+            path: path.into(),
+            encode: |state| serde_json::to_value(state),
+        });
+        self
+    }
+
+    /// Continue run `run_id` from its last checkpoint, restoring the saved
+    /// state, context, step number and retry count. `ctx` is replaced with
+    /// the saved context. Further checkpoints are saved under `run_id`.
+    ///
+    /// A run that had already finished returns its final state without
+    /// running anything. A pending `Wait` is not slept again, and a run
+    /// saved while paused re-runs the agent that paused.
+    pub fn resume(&mut self, run_id: &str, ctx: &mut Ctx) -> Result<RunStatus<S>, StepError> {
+        let Some(cp) = self.checkpoints.as_ref() else {
+            return Err(StepError::invalid(
+                "resume needs a checkpoint store, see Runner::with_checkpoints",
+            ));
+        };
+        let checkpoint = cp
+            .store
+            .load(run_id)?
+            .ok_or_else(|| StepError::invalid(format!("no checkpoint for run '{run_id}'")))?;
+        if checkpoint.workflow != self.wf.name() {
+            return Err(StepError::invalid(format!(
+                "run '{run_id}' belongs to workflow '{}', not '{}'",
+                checkpoint.workflow,
+                self.wf.name()
+            )));
+        }
+        let state = (cp.decode)(checkpoint.state)
+            .map_err(|e| StepError::invalid(format!("checkpoint state for run '{run_id}': {e}")))?;
+        *ctx = checkpoint.ctx;
+
+        let Some(agent) = checkpoint.agent else {
+            return Ok(RunStatus::Done(state));
+        };
+        let current =
+            self.wf.step_named(&agent).cloned().ok_or_else(|| {
+                StepError::invalid(format!("unknown step in checkpoint: {agent}"))
+            })?;
+        self.drive(
+            state,
+            ctx,
+            current,
+            checkpoint.retries,
+            checkpoint.step_number,
+            Some(run_id),
+            None,
+        )
+    }
+}
+
+/// Hooks, limits and the step counter for one run. Shared by every
+/// workflow the run enters, so nested steps number and report as one.
+pub(crate) struct Exec<'a> {
+    observers: &'a mut [Box<dyn RunObserver>],
+    trace: Option<&'a mut Trace>,
+    max_steps: usize,
+    max_retries: usize,
+    max_visits: Option<usize>,
+    step_timeout: Option<Duration>,
+    step_number: usize,
+    /// How many sub-workflows deep the current step is.
+    depth: usize,
+}
+
+impl Exec<'_> {
+    fn report_step(&mut self, event: &StepEvent) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.step(event);
+        }
+        self.notify(|o| o.after_step(event));
+    }
+
+    fn report_error(&mut self, agent: &str, error: &StepError, step_number: usize, retries: usize) {
+        if self.depth > 0 {
+            return self.report_inner_error(agent, None, error, step_number, retries);
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.error(agent, step_number);
+        }
+        let event = ErrorEvent {
+            agent,
+            item: None,
+            error,
+            step_number,
+            retries,
+        };
+        self.notify(|o| o.on_error(&event));
+    }
+
+    /// A failure inside a parallel step or sub-workflow. The step that
+    /// contains it fails next and is reported on its own, so this one only
+    /// reaches `on_inner_error`.
+    fn report_inner_error(
+        &mut self,
+        agent: &str,
+        item: Option<usize>,
+        error: &StepError,
+        step_number: usize,
+        retries: usize,
+    ) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.error(agent, step_number);
+        }
+        let event = ErrorEvent {
+            agent,
+            item,
+            error,
+            step_number,
+            retries,
+        };
+        self.notify(|o| o.on_inner_error(&event));
+    }
+
+    fn report_catch(&mut self, event: &CatchEvent) {
+        // The run goes on, so an earlier failure is no longer where it
+        // stopped.
+        if let Some(trace) = self.trace.as_mut() {
+            trace.failure = None;
+        }
+        self.notify(|o| o.on_catch(event));
+    }
+
+    fn notify(&mut self, mut f: impl FnMut(&mut dyn RunObserver)) {
+        for observer in self.observers.iter_mut() {
+            f(observer.as_mut());
+        }
+    }
+}
+
+/// Name a step as hooks see it: `parent/child` inside nested workflows
+/// and parallel steps.
+fn step_path<'a>(prefix: &str, name: &'a str) -> Cow<'a, str> {
+    if prefix.is_empty() {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("{prefix}/{name}"))
+    }
+}
+
+/// Drive `wf` from its start step until it finishes. `prefix` is the path
+/// of the step that embeds it, empty for the top-level workflow.
+pub(crate) fn execute<S: Clone + Send + 'static>(
+    wf: &mut Workflow<S>,
+    state: S,
+    ctx: &mut Ctx,
+    exec: &mut Exec<'_>,
+    prefix: &str,
+) -> Result<S, StepError> {
+    let start = wf.start().clone();
+    exec.depth += 1;
+    let status = execute_from(wf, state, ctx, exec, prefix, start, 0, None);
+    exec.depth -= 1;
+    match status? {
+        RunStatus::Done(state) => Ok(state),
+        RunStatus::Paused(paused) => {
+            let path = step_path(prefix, &paused.agent);
+            let err = StepError::other(format!(
+                "step '{path}' paused inside a sub-workflow; only top-level steps can pause"
+            ));
+            exec.report_inner_error(&path, None, &err, paused.step_number, 0);
+            Err(err)
+        }
+    }
+}
+
+/// [`execute`] from any step, calling `save` before the first step and
+/// after each one.
+#[allow(clippy::too_many_arguments)]
+fn execute_from<S: Clone + Send + 'static>(
+    wf: &mut Workflow<S>,
+    mut state: S,
+    ctx: &mut Ctx,
+    exec: &mut Exec<'_>,
+    prefix: &str,
+    mut current: Name,
+    mut retries: usize,
+    mut save: Option<SaveHook<'_, S>>,
+) -> Result<RunStatus<S>, StepError> {
+    if let Some(save) = save.as_mut() {
+        save(&Boundary {
+            next: Some(&current),
+            retries,
+            step_number: exec.step_number,
+            state: &state,
+            ctx,
+            outcome: None,
+            error: None,
+        })?;
+    }
+
+    let mut visits = HashMap::from([(current.clone(), 1)]);
+    while exec.step_number < exec.max_steps {
+        let path = step_path(prefix, &current);
+        let timeout = wf.timeout(&current).or(exec.step_timeout);
+        if let Some(limit) = timeout
+            && let Err(err) = wf.reclaim(&current, limit)
+        {
+            exec.report_error(&path, &err, exec.step_number, retries);
+            return Err(err);
+        }
+
+        // Parallel steps and sub-workflows retry their own agents, so the
+        // node as a whole only retries errors under an explicit policy.
+        // A map step's policy is for its items.
+        let explicit = wf.retry_policy(&current).cloned();
+        let retry_errors = match wf.node_mut(&current) {
+            Some(Node::Agent(_)) => true,
+            Some(Node::Map(_)) => false,
+            _ => explicit.is_some(),
+        };
+        let policy = explicit.unwrap_or_else(|| RetryPolicy::new(exec.max_retries));
+
+        if let Err(err) = ctx.cancel().check() {
+            exec.report_error(&path, &err, exec.step_number, retries);
+            return Err(err);
+        }
+
+        let before = BeforeStepEvent {
+            agent: &path,
+            item: None,
+            completed_steps: exec.step_number,
+            retries,
+        };
+        exec.notify(|o| o.before_step(&before));
+
+        // Each kind of node claims its own step numbers: nested and branch
+        // steps are numbered first, then the node itself.
+        let start = Instant::now();
+        let result = match wf.node_mut(&current) {
+            Some(Node::Agent(agent)) if timeout.is_none() => {
+                exec.step_number += 1;
+                agent.run(state.clone(), ctx)
+            }
+            Some(Node::Agent(_)) => {
+                exec.step_number += 1;
+                run_agent_timed(
+                    wf,
+                    &current,
+                    state.clone(),
+                    ctx,
+                    timeout.unwrap_or_default(),
+                )
+            }
+            Some(Node::Parallel(parallel)) => {
+                let branches = parallel.branches.clone();
+                let merge = Arc::clone(&parallel.merge);
+                run_parallel(wf, &path, &branches, &merge, &state, ctx, exec)
+            }
+            Some(Node::Workflow(nested)) => {
+                let result = nested.run(&state, ctx, exec, &path);
+                exec.step_number += 1;
+                result.map(|next| (next, Outcome::Continue))
+            }
+            // Like a parallel step, a map step numbers itself after its items.
+            Some(Node::Map(map)) => map
+                .run(&state, ctx, exec, &path, &policy, timeout)
+                .map(|next| (next, Outcome::Continue)),
+            None => return Err(wf.missing_agent(&current)),
+        };
+        let duration = start.elapsed();
+        ctx.set_resume_input(None);
+        let step_number = exec.step_number;
+
+        let (next_state, outcome) = match result {
+            Ok(step) => step,
+            Err(err)
+                if retry_errors && policy.retries_error(&err) && retries < policy.max_retries() =>
+            {
+                retries += 1;
+                let delay = policy.backoff(retries);
+                let retry = RetryEvent {
+                    agent: &path,
+                    step_number,
+                    retry: retries,
+                    delay,
+                    error: Some(&err),
+                };
+                exec.notify(|o| o.on_retry(&retry));
+                if let Err(err) = ctx.cancel().sleep(delay) {
+                    exec.report_error(&path, &err, step_number, retries);
                     return Err(err);
                 }
-                Ok((next_state, outcome)) => {
-                    if let Some(cb) = &mut self.on_step {
-                        cb(&StepEvent {
-                            agent: current,
-                            outcome: &outcome,
-                            duration,
-                            step_number,
-                            retries,
-                        });
-                    }
+                continue;
+            }
+            Err(err) => match wf.error_handler(&current, &err).cloned() {
+                Some(handler) => {
+                    let to = step_path(prefix, &handler);
+                    catch(
+                        exec,
+                        ctx,
+                        &mut save,
+                        &path,
+                        &to,
+                        &handler,
+                        err,
+                        step_number,
+                        &state,
+                    )?;
+                    retries = 0;
+                    current = handler;
+                    continue;
+                }
+                None => {
+                    exec.report_error(&path, &err, step_number, retries);
+                    return Err(err);
+                }
+            },
+        };
+
+        exec.report_step(&StepEvent {
+            agent: &path,
+            item: None,
+            outcome: &outcome,
+            duration,
+            step_number,
+            retries,
+        });
+
+        state = next_state;
+
+        // Declared edges only apply to `Continue`.
+        let default_next = match outcome {
+            Outcome::Continue => wf.next_after(&current, &state),
+            _ => Ok(None),
+        };
+
+        let step_retries = retries;
+        let returned = save.is_some().then(|| outcome.clone());
+        let route = match default_next {
+            Ok(default_next) => route(
+                &current,
+                outcome,
+                default_next,
+                |name| wf.step_named(name).cloned(),
+                &mut retries,
+                policy.max_retries(),
+            ),
+            // A failing router goes to the error handlers like any failure.
+            Err(err) => Route::Raised(err),
+        };
+        let route = match route {
+            Route::Goto(next) => enter(wf, &mut visits, exec.max_visits, next),
+            route => route,
+        };
+
+        let resume_at = match route {
+            Route::Done => Some(None),
+            Route::Goto(ref next) => Some(Some(&**next)),
+            Route::Rerun | Route::Sleep(_) | Route::Pause { .. } => Some(Some(&*current)),
+            Route::Stop(_) | Route::Raised(_) => None,
+        };
+        if let (Some(save), Some(next)) = (save.as_mut(), resume_at)
+            && let Err(err) = save(&Boundary {
+                next,
+                retries,
+                step_number,
+                state: &state,
+                ctx,
+                outcome: returned.as_ref(),
+                error: None,
+            })
+        {
+            exec.report_error(&path, &err, step_number, step_retries);
+            return Err(err);
+        }
 
-                    state = next_state;
-
-                    match outcome {
-                        Outcome::Done => return Ok(state),
-                        Outcome::Fail(msg) => return Err(StepError::other(msg)),
-                        Outcome::Next(step) => {
-                            current = step;
-                            retries = 0;
-                            continue;
-                        }
-                        Outcome::Continue => {
-                            if let Some(next) = self.wf.default_next(current) {
-                                current = next;
-                                retries = 0;
-                                continue;
-                            }
-                            return Err(StepError::other(format!(
-                                "step '{current}' returned Continue but no default next step is configured"
-                            )));
-                        }
-                        Outcome::Retry(hint) => {
-                            retries += 1;
-                            if retries > self.max_retries {
-                                let err = StepError::other(format!(
-                                    "step '{}' exceeded max retries ({}): {}",
-                                    current, self.max_retries, hint.reason
-                                ));
-                                if let Some(cb) = &mut self.on_error {
-                                    cb(&ErrorEvent {
-                                        agent: current,
-                                        error: &err,
-                                        step_number,
-                                    });
-                                }
-                                return Err(err);
-                            }
-                            continue;
-                        }
-                        Outcome::Wait(dur) => {
-                            retries += 1;
-                            if retries > self.max_retries {
-                                let err = StepError::other(format!(
-                                    "step '{}' exceeded max retries ({}) while waiting",
-                                    current, self.max_retries
-                                ));
-                                if let Some(cb) = &mut self.on_error {
-                                    cb(&ErrorEvent {
-                                        agent: current,
-                                        error: &err,
-                                        step_number,
-                                    });
-                                }
-                                return Err(err);
-                            }
-                            std::thread::sleep(dur);
-                            continue;
-                        }
+        let raised = matches!(route, Route::Raised(_));
+        match route {
+            Route::Done => return Ok(RunStatus::Done(state)),
+            Route::Goto(next) => {
+                let to = step_path(prefix, &next);
+                let event = RouteEvent {
+                    from: &path,
+                    to: &to,
+                    step_number,
+                };
+                exec.notify(|o| o.on_route(&event));
+                current = next;
+            }
+            Route::Rerun => {
+                let delay = policy.backoff(retries);
+                let retry = RetryEvent {
+                    agent: &path,
+                    step_number,
+                    retry: retries,
+                    delay,
+                    error: None,
+                };
+                exec.notify(|o| o.on_retry(&retry));
+                if let Err(err) = ctx.cancel().sleep(delay) {
+                    exec.report_error(&path, &err, step_number, retries);
+                    return Err(err);
+                }
+            }
+            Route::Sleep(dur) => {
+                let wait = WaitEvent {
+                    agent: &path,
+                    step_number,
+                    duration: dur,
+                };
+                exec.notify(|o| o.on_wait(&wait));
+                if let Err(err) = ctx.cancel().sleep(dur) {
+                    exec.report_error(&path, &err, step_number, retries);
+                    return Err(err);
+                }
+            }
+            Route::Pause { reason, payload } => {
+                return Ok(RunStatus::Paused(Paused {
+                    state,
+                    reason,
+                    payload,
+                    resume_at: Cow::Owned(current.to_string()),
+                    agent: current,
+                    step_number,
+                }));
+            }
+            Route::Stop(err) | Route::Raised(err) => {
+                let Some(handler) = wf.error_handler(&current, &err).cloned() else {
+                    // Only the runner's own errors are reported; a `Fail` was
+                    // the agent's own call and is already in its step event.
+                    if raised {
+                        exec.report_error(&path, &err, step_number, step_retries);
                     }
+                    return Err(err);
+                };
+                let to = step_path(prefix, &handler);
+                catch(
+                    exec,
+                    ctx,
+                    &mut save,
+                    &path,
+                    &to,
+                    &handler,
+                    err,
+                    step_number,
+                    &state,
+                )?;
+                retries = 0;
+                current = handler;
+            }
+        }
+    }
+
+    let err = StepError::other(format!(
+        "max_steps exceeded (possible infinite loop) in workflow {}",
+        wf.name()
+    ));
+    exec.report_error(&step_path(prefix, &current), &err, exec.step_number, 0);
+    Err(err)
+}
+
+/// Count a visit to `next`, turning the move into one to its loop exit,
+/// or into a [`StepError::LoopLimit`], once it has had all it may. Exits
+/// are held to their own limits, and can't lead back to a step already
+/// out of visits.
+fn enter<S: Clone + Send + 'static>(
+    wf: &Workflow<S>,
+    visits: &mut HashMap<Name, usize>,
+    default_limit: Option<usize>,
+    next: Name,
+) -> Route {
+    let mut step = next;
+    let mut spent = Vec::new();
+    loop {
+        let count = visits.entry(step.clone()).or_default();
+        let Some(limit) = wf.max_visits(&step).or(default_limit) else {
+            *count += 1;
+            return Route::Goto(step);
+        };
+        if *count < limit {
+            *count += 1;
+            return Route::Goto(step);
+        }
+        spent.push(step.clone());
+        match wf.loop_exit(&step) {
+            Some(exit) if !spent.contains(exit) => step = exit.clone(),
+            _ => {
+                return Route::Raised(StepError::LoopLimit(format!(
+                    "step '{step}' was entered more than {limit} times"
+                )));
+            }
+        }
+    }
+}
+
+/// Hand a failure at `path` over to the error handler `to`: report it,
+/// leave it in [`Ctx::caught`] and save the new position.
+#[allow(clippy::too_many_arguments)]
+fn catch<S>(
+    exec: &mut Exec<'_>,
+    ctx: &mut Ctx,
+    save: &mut Option<SaveHook<'_, S>>,
+    path: &str,
+    to: &str,
+    handler: &str,
+    error: StepError,
+    step_number: usize,
+    state: &S,
+) -> Result<(), StepError> {
+    exec.report_catch(&CatchEvent {
+        agent: path,
+        handler: to,
+        step_number,
+        error: &error,
+    });
+    ctx.set_caught(CaughtError {
+        agent: path.to_string(),
+        step_number,
+        error,
+    });
+    if let Some(save) = save.as_mut()
+        && let Err(err) = save(&Boundary {
+            next: Some(handler),
+            retries: 0,
+            step_number,
+            state,
+            ctx,
+            outcome: None,
+            error: ctx.caught().map(|caught| &caught.error),
+        })
+    {
+        exec.report_error(path, &err, step_number, 0);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Run every branch of a parallel step on its own thread, report each
+/// branch run as a step named `parallel/branch`, then merge.
+fn run_parallel<S: Clone + Send + 'static>(
+    wf: &mut Workflow<S>,
+    path: &str,
+    branches: &[Name],
+    merge: &Merge<S>,
+    state: &S,
+    ctx: &mut Ctx,
+    exec: &mut Exec<'_>,
+) -> Result<(S, Outcome), StepError> {
+    let mut agents = Vec::with_capacity(branches.len());
+    for branch in branches {
+        if let Some(limit) = wf.timeout(branch).or(exec.step_timeout)
+            && let Err(err) = wf.reclaim(branch, limit)
+        {
+            for (branch, agent) in agents {
+                wf.restore_agent(branch, agent);
+            }
+            return Err(err);
+        }
+        match wf.take_agent(branch) {
+            Some(agent) => agents.push((branch, agent)),
+            None => {
+                for (branch, agent) in agents {
+                    wf.restore_agent(branch, agent);
                 }
+                return Err(wf.missing_agent(branch));
             }
         }
+    }
 
-        let err = StepError::other(format!(
-            "max_steps exceeded (possible infinite loop) in workflow {}",
-            self.wf.name()
-        ));
-        if let Some(cb) = &mut self.on_error {
-            cb(&ErrorEvent {
-                agent: current,
-                error: &err,
-                step_number,
+    // Branch threads trace and record LLM calls under the parallel step.
+    #[cfg(feature = "otel")]
+    let otel_cx = opentelemetry::Context::current();
+    #[cfg(feature = "tracing")]
+    let span = tracing::Span::current();
+    let tape = Tape::current();
+    for branch in branches {
+        let before = BeforeStepEvent {
+            agent: &step_path(path, branch),
+            item: None,
+            completed_steps: exec.step_number,
+            retries: 0,
+        };
+        exec.notify(|o| o.before_step(&before));
+    }
+
+    let runs: Vec<BranchRun<S>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = agents
+            .into_iter()
+            .map(|(branch, agent)| {
+                let state = state.clone();
+                let fork = ctx.clone();
+                let policy = wf
+                    .retry_policy(branch)
+                    .cloned()
+                    .unwrap_or_else(|| RetryPolicy::new(exec.max_retries));
+                let timeout = wf.timeout(branch).or(exec.step_timeout);
+                #[cfg(feature = "otel")]
+                let otel_cx = otel_cx.clone();
+                #[cfg(feature = "tracing")]
+                let span = span.clone();
+                let tape = tape.clone();
+                let handle = scope.spawn(move || {
+                    #[cfg(feature = "otel")]
+                    let _otel = otel_cx.attach();
+                    #[cfg(feature = "tracing")]
+                    let _span = span.entered();
+                    let _tape = tape.as_ref().map(Tape::attach);
+                    run_branch(branch, agent, state, fork, policy, timeout)
+                });
+                (branch, handle)
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|(branch, handle)| {
+                handle.join().unwrap_or_else(|_| {
+                    BranchRun::failed(StepError::other(format!(
+                        "parallel branch '{branch}' panicked"
+                    )))
+                })
+            })
+            .collect()
+    });
+
+    // Branch runs are numbered in branch order, then the merge takes the
+    // next number.
+    let base = ctx.clone();
+    let mut next_number = exec.step_number + 1;
+    let mut results = Vec::with_capacity(runs.len());
+    let mut failure = None;
+    for (branch, mut run) in branches.iter().zip(runs) {
+        match (run.agent.take(), run.stray.take()) {
+            (Some(agent), _) => wf.restore_agent(branch, agent),
+            (None, Some(running)) => wf.park(branch, running),
+            // The branch panicked and took its agent with it.
+            (None, None) => wf.replace_agent(branch),
+        }
+        match join_branch(
+            exec,
+            ctx,
+            &base,
+            &step_path(path, branch),
+            None,
+            run,
+            &mut next_number,
+        ) {
+            Ok(branch_state) => results.push(branch_state),
+            Err(err) => {
+                failure.get_or_insert(err);
+            }
+        }
+    }
+
+    exec.step_number = next_number;
+    if let Some(err) = failure {
+        return Err(err);
+    }
+
+    Ok((merge(state.clone(), results), Outcome::Continue))
+}
+
+/// Run each item through its agent, at most `concurrency` at a time, each
+/// under `policy` and `timeout`, and return the results in item order. Item
+/// runs are reported as steps named `path/agent` with the item's index,
+/// numbered in item order.
+pub(crate) fn run_map<T: Clone + Send + 'static>(
+    items: Vec<(Box<dyn Agent<T>>, T)>,
+    concurrency: usize,
+    policy: &RetryPolicy,
+    timeout: Option<Duration>,
+    ctx: &mut Ctx,
+    exec: &mut Exec<'_>,
+    path: &str,
+) -> Result<Vec<T>, StepError> {
+    let runs: Vec<_> = items
+        .into_iter()
+        .map(|(agent, item)| {
+            let name = step_path(path, agent.name()).into_owned();
+            (name, agent, item, ctx.clone())
+        })
+        .collect();
+    let names: Vec<String> = runs.iter().map(|(name, ..)| name.clone()).collect();
+    for (i, name) in names.iter().enumerate() {
+        let before = BeforeStepEvent {
+            agent: name,
+            item: Some(i),
+            completed_steps: exec.step_number,
+            retries: 0,
+        };
+        exec.notify(|o| o.before_step(&before));
+    }
+
+    // Item threads trace and record LLM calls under the map step.
+    #[cfg(feature = "otel")]
+    let otel_cx = opentelemetry::Context::current();
+    #[cfg(feature = "tracing")]
+    let span = tracing::Span::current();
+    let tape = Tape::current();
+    let workers = concurrency.min(runs.len());
+    let queue = Mutex::new(runs.into_iter().enumerate());
+    let mut finished: Vec<Option<BranchRun<T>>> = names.iter().map(|_| None).collect();
+
+    std::thread::scope(|scope| {
+        let (done, results) = mpsc::channel();
+        for _ in 0..workers {
+            let done = done.clone();
+            let queue = &queue;
+            #[cfg(feature = "otel")]
+            let otel_cx = otel_cx.clone();
+            #[cfg(feature = "tracing")]
+            let span = span.clone();
+            let tape = tape.clone();
+            scope.spawn(move || {
+                #[cfg(feature = "otel")]
+                let _otel = otel_cx.attach();
+                #[cfg(feature = "tracing")]
+                let _span = span.entered();
+                let _tape = tape.as_ref().map(Tape::attach);
+                loop {
+                    let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                    let Some((i, (name, agent, item, fork))) = next else {
+                        break;
+                    };
+                    // Errors name the item, though events carry it apart.
+                    let name = format!("{name}[{i}]");
+                    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+                        run_branch(&name, agent, item, fork, policy.clone(), timeout)
+                    }))
+                    .unwrap_or_else(|_| BranchRun::failed(panicked(&name)));
+                    if done.send((i, run)).is_err() {
+                        break;
+                    }
+                }
             });
         }
-        Err(err)
+        drop(done);
+        for (i, run) in results {
+            finished[i] = Some(run);
+        }
+    });
+
+    let base = ctx.clone();
+    let mut next_number = exec.step_number + 1;
+    let mut results = Vec::with_capacity(names.len());
+    let mut failure = None;
+    for (i, (name, run)) in names.iter().zip(finished).enumerate() {
+        let run = run.unwrap_or_else(|| BranchRun::failed(panicked(&format!("{name}[{i}]"))));
+        match join_branch(exec, ctx, &base, name, Some(i), run, &mut next_number) {
+            Ok(result) => results.push(result),
+            Err(err) => {
+                failure.get_or_insert(err);
+            }
+        }
+    }
+
+    exec.step_number = next_number;
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(results),
+    }
+}
+
+/// Fold a finished branch back into the run: its context changes, then its
+/// steps and any failure, reported as `path` (and `item`, for a map item)
+/// and numbered from `next_number`. Returns the branch's final state.
+fn join_branch<S>(
+    exec: &mut Exec<'_>,
+    ctx: &mut Ctx,
+    base: &Ctx,
+    path: &str,
+    item: Option<usize>,
+    run: BranchRun<S>,
+    next_number: &mut usize,
+) -> Result<S, StepError> {
+    if let Some(fork) = run.ctx {
+        ctx.join(base, fork);
+    }
+    for step in &run.steps {
+        exec.report_step(&StepEvent {
+            agent: path,
+            item,
+            outcome: &step.outcome,
+            duration: step.duration,
+            step_number: *next_number,
+            retries: step.retries,
+        });
+        *next_number += 1;
+    }
+    if let Err(err) = &run.result {
+        exec.report_inner_error(path, item, err, *next_number, run.retries);
+        *next_number += 1;
+    }
+    run.result
+}
+
+/// One agent execution inside a parallel branch.
+struct BranchStep {
+    outcome: Outcome,
+    duration: Duration,
+    retries: usize,
+}
+
+/// Everything a branch thread hands back to the runner.
+struct BranchRun<S> {
+    agent: Option<Box<dyn Agent<S>>>,
+    ctx: Option<Ctx>,
+    steps: Vec<BranchStep>,
+    retries: usize,
+    result: Result<S, StepError>,
+    /// The agent, if it timed out and is still running.
+    stray: Option<Receiver<Finished<S>>>,
+}
+
+impl<S> BranchRun<S> {
+    /// A branch whose thread died before it could report back.
+    fn failed(err: StepError) -> Self {
+        Self {
+            agent: None,
+            ctx: None,
+            steps: Vec::new(),
+            retries: 0,
+            result: Err(err),
+            stray: None,
+        }
+    }
+}
+
+fn run_branch<S: Clone + Send + 'static>(
+    branch: &str,
+    agent: Box<dyn Agent<S>>,
+    mut state: S,
+    mut ctx: Ctx,
+    policy: RetryPolicy,
+    timeout: Option<Duration>,
+) -> BranchRun<S> {
+    let mut steps = Vec::new();
+    let mut retries = 0;
+    let max_retries = policy.max_retries();
+    let mut agent = Some(agent);
+    let mut stray = None;
+
+    let result = loop {
+        let start = Instant::now();
+        let Some(mut current) = agent.take() else {
+            break Err(panicked(branch));
+        };
+        let result = match timeout {
+            None => {
+                let result = current.run(state.clone(), &mut ctx);
+                agent = Some(current);
+                result
+            }
+            Some(limit) => match run_timed(current, state.clone(), &mut ctx, limit) {
+                Timed::Finished(current, result) => {
+                    agent = Some(current);
+                    result
+                }
+                Timed::Late(running) => {
+                    stray = Some(running);
+                    break Err(timed_out(branch, limit));
+                }
+                Timed::Panicked => break Err(panicked(branch)),
+            },
+        };
+        let (next_state, outcome) = match result {
+            Ok(step) => step,
+            Err(err) if policy.retries_error(&err) && retries < max_retries => {
+                retries += 1;
+                if let Err(err) = ctx.cancel().sleep(policy.backoff(retries)) {
+                    break Err(err);
+                }
+                continue;
+            }
+            Err(err) => break Err(err),
+        };
+        steps.push(BranchStep {
+            outcome: outcome.clone(),
+            duration: start.elapsed(),
+            retries,
+        });
+        state = next_state;
+
+        match outcome {
+            Outcome::Continue | Outcome::Done => break Ok(state),
+            Outcome::Next(step) => {
+                break Err(StepError::other(format!(
+                    "step '{branch}' runs in parallel and cannot route to '{step}'"
+                )));
+            }
+            Outcome::Fail(msg) => break Err(StepError::other(msg)),
+            Outcome::Pause { .. } => {
+                break Err(StepError::other(format!(
+                    "step '{branch}' runs in parallel and cannot pause"
+                )));
+            }
+            Outcome::Retry(_) | Outcome::Wait(_) => {
+                if retries >= max_retries {
+                    break Err(StepError::other(format!(
+                        "step '{branch}' exceeded max retries ({max_retries})"
+                    )));
+                }
+                retries += 1;
+                let waited = match outcome {
+                    Outcome::Wait(dur) => ctx.cancel().sleep(dur),
+                    _ => ctx.cancel().sleep(policy.backoff(retries)),
+                };
+                if let Err(err) = waited {
+                    break Err(err);
+                }
+            }
+        }
+    };
+
+    BranchRun {
+        agent,
+        ctx: Some(ctx),
+        steps,
+        retries,
+        result,
+        stray,
+    }
+}
+
+/// What a timed agent hands back from its worker thread.
+pub(crate) type Finished<S> = (Box<dyn Agent<S>>, Ctx, StepResult<S>);
+
+enum Timed<S> {
+    /// The agent finished in time; `ctx` has its changes.
+    Finished(Box<dyn Agent<S>>, StepResult<S>),
+    /// Still running; the agent comes back on this channel when done.
+    Late(Receiver<Finished<S>>),
+    Panicked,
+}
+
+/// Run `agent` on a worker thread against a copy of `ctx`, waiting at most
+/// `limit` for it.
+fn run_timed<S: Send + 'static>(
+    mut agent: Box<dyn Agent<S>>,
+    state: S,
+    ctx: &mut Ctx,
+    limit: Duration,
+) -> Timed<S> {
+    let (done, running) = mpsc::channel();
+    let mut fork = ctx.clone();
+    #[cfg(feature = "otel")]
+    let otel_cx = opentelemetry::Context::current();
+    #[cfg(feature = "tracing")]
+    let span = tracing::Span::current();
+    let tape = Tape::current();
+    std::thread::spawn(move || {
+        #[cfg(feature = "otel")]
+        let _otel = otel_cx.attach();
+        #[cfg(feature = "tracing")]
+        let _span = span.entered();
+        let _tape = tape.as_ref().map(Tape::attach);
+        let result = agent.run(state, &mut fork);
+        let _ = done.send((agent, fork, result));
+    });
+
+    match running.recv_timeout(limit) {
+        Ok((agent, fork, result)) => {
+            *ctx = fork;
+            Timed::Finished(agent, result)
+        }
+        Err(RecvTimeoutError::Timeout) => Timed::Late(running),
+        Err(RecvTimeoutError::Disconnected) => Timed::Panicked,
+    }
+}
+
+/// Run agent step `name` under a timeout, parking it in `wf` if it
+/// doesn't finish in time.
+fn run_agent_timed<S: Clone + Send + 'static>(
+    wf: &mut Workflow<S>,
+    name: &Name,
+    state: S,
+    ctx: &mut Ctx,
+    limit: Duration,
+) -> StepResult<S> {
+    let Some(agent) = wf.take_agent(name) else {
+        return Err(wf.missing_agent(name));
+    };
+    match run_timed(agent, state, ctx, limit) {
+        Timed::Finished(agent, result) => {
+            wf.restore_agent(name, agent);
+            result
+        }
+        Timed::Late(running) => {
+            wf.park(name, running);
+            Err(timed_out(name, limit))
+        }
+        Timed::Panicked => {
+            wf.replace_agent(name);
+            Err(panicked(name))
+        }
     }
 }
 
+fn timed_out(name: &str, limit: Duration) -> StepError {
+    StepError::Timeout(format!("step '{name}' took longer than {limit:?}"))
+}
+
+fn panicked(name: &str) -> StepError {
+    StepError::other(format!("step '{name}' panicked"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Agent, Outcome, RetryHint, StepResult, Workflow};
+    use crate::{Agent, CancellationToken, Outcome, RetryHint, RetryPolicy, StepResult, Workflow};
     use std::time::Duration;
 
     #[derive(Clone)]
@@ -464,7 +1906,7 @@ mod tests {
             "next_agent"
         }
         fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
-            Ok((S(state.0 + 1), Outcome::Next("done_agent")))
+            Ok((S(state.0 + 1), Outcome::next("done_agent")))
         }
     }
 
@@ -482,21 +1924,67 @@ mod tests {
         assert_eq!(result.0, 1);
     }
 
-    // --- Outcome::Fail ---
+    /// Hands each state on to the next reviewer by a name built at runtime.
+    struct Reviewer {
+        persona: String,
+        last: usize,
+    }
 
-    struct FailOutcomeAgent;
-    impl Agent<S> for FailOutcomeAgent {
-        fn name(&self) -> &'static str {
-            "fail_outcome"
+    impl Agent<S> for Reviewer {
+        fn name(&self) -> &str {
+            &self.persona
         }
-        fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
-            Ok((state, Outcome::Fail("reason".into())))
+        fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+            ctx.log(self.persona.clone());
+            match state.0 as usize {
+                n if n == self.last => Ok((state, Outcome::Done)),
+                n => Ok((S(state.0 + 1), Outcome::next(format!("reviewer-{}", n + 1)))),
+            }
         }
     }
 
     #[test]
-    fn fail_outcome_returns_step_error() {
-        let wf = Workflow::builder("test")
+    fn next_routes_to_steps_named_at_runtime() {
+        let mut builder = Workflow::builder(String::from("reviews"));
+        for i in 0..3 {
+            builder = builder.register(Reviewer {
+                persona: format!("reviewer-{i}"),
+                last: 2,
+            });
+        }
+        let mut ctx = Ctx::new();
+        let result = Runner::new(builder.build().unwrap())
+            .run(S(0), &mut ctx)
+            .unwrap();
+        assert_eq!(result.0, 2);
+        assert_eq!(ctx.logs(), ["reviewer-0", "reviewer-1", "reviewer-2"]);
+
+        let wf = Workflow::builder("lost")
+            .register(Reviewer {
+                persona: "reviewer-0".into(),
+                last: 5,
+            })
+            .build()
+            .unwrap();
+        let err = Runner::new(wf).run(S(0), &mut Ctx::new()).err().unwrap();
+        assert!(err.to_string().contains("unknown step 'reviewer-1'"));
+    }
+
+    // --- Outcome::Fail ---
+
+    struct FailOutcomeAgent;
+    impl Agent<S> for FailOutcomeAgent {
+        fn name(&self) -> &'static str {
+            "fail_outcome"
+        }
+        fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            Ok((state, Outcome::Fail("reason".into())))
+        }
+    }
+
+    #[test]
+    fn fail_outcome_returns_step_error() {
+        let wf = Workflow::builder("test")
             .register(FailOutcomeAgent)
             .build()
             .unwrap();
@@ -601,4 +2089,1758 @@ mod tests {
             .unwrap();
         assert_eq!(done_event.1, 0);
     }
+
+    // --- parallel steps ---
+
+    struct AddN(&'static str, u32);
+    impl Agent<S> for AddN {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+            ctx.log(self.0);
+            ctx.set(self.0, "ran");
+            Ok((S(state.0 + self.1), Outcome::Continue))
+        }
+    }
+
+    fn sum_deltas(base: S, branches: Vec<S>) -> S {
+        let delta: u32 = branches.iter().map(|b| b.0 - base.0).sum();
+        S(base.0 + delta)
+    }
+
+    #[test]
+    fn parallel_merges_branches_and_continues() {
+        let wf = Workflow::builder("test")
+            .register(AddN("one", 1))
+            .register(AddN("ten", 10))
+            .register(DoneAgent)
+            .parallel("fan", ["one", "ten"], sum_deltas)
+            .start_at("fan")
+            .then("done_agent")
+            .build()
+            .unwrap();
+
+        let mut ctx = Ctx::new();
+        let result = Runner::new(wf).run(S(5), &mut ctx).unwrap();
+        assert_eq!(result.0, 16);
+        assert_eq!(ctx.logs(), &["one", "ten"]);
+        assert_eq!(ctx.get("one"), Some("ran"));
+        assert_eq!(ctx.get("ten"), Some("ran"));
+    }
+
+    #[test]
+    fn parallel_reports_branch_steps_with_paths() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+
+        let wf = Workflow::builder("test")
+            .register(AddN("one", 1))
+            .register(AddN("ten", 10))
+            .register(DoneAgent)
+            .parallel("fan", ["one", "ten"], sum_deltas)
+            .start_at("fan")
+            .then("done_agent")
+            .build()
+            .unwrap();
+
+        let mut runner = Runner::new(wf).on_step(move |e| {
+            events_clone
+                .lock()
+                .unwrap()
+                .push((e.step_number, e.agent.to_string()));
+        });
+        runner.run(S(0), &mut Ctx::new()).unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![
+                (1, "fan/one".to_string()),
+                (2, "fan/ten".to_string()),
+                (3, "fan".to_string()),
+                (4, "done_agent".to_string()),
+            ]
+        );
+    }
+
+    struct Handshake {
+        name: &'static str,
+        tx: std::sync::mpsc::Sender<()>,
+        rx: std::sync::mpsc::Receiver<()>,
+    }
+    impl Agent<S> for Handshake {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+        fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            // Only succeeds if the other branch is running at the same time.
+            self.tx.send(()).unwrap();
+            self.rx
+                .recv_timeout(Duration::from_secs(5))
+                .map_err(|_| StepError::other("branches did not run concurrently"))?;
+            Ok((state, Outcome::Done))
+        }
+    }
+
+    #[test]
+    fn parallel_branches_run_concurrently() {
+        let (tx_a, rx_b) = std::sync::mpsc::channel();
+        let (tx_b, rx_a) = std::sync::mpsc::channel();
+
+        let wf = Workflow::builder("test")
+            .register(Handshake {
+                name: "a",
+                tx: tx_a,
+                rx: rx_a,
+            })
+            .register(Handshake {
+                name: "b",
+                tx: tx_b,
+                rx: rx_b,
+            })
+            .register(DoneAgent)
+            .parallel("fan", ["a", "b"], |s, _| s)
+            .start_at("fan")
+            .then("done_agent")
+            .build()
+            .unwrap();
+
+        assert!(Runner::new(wf).run(S(0), &mut Ctx::new()).is_ok());
+    }
+
+    #[test]
+    fn parallel_branch_error_is_reported_and_agents_restored() {
+        use std::sync::{Arc, Mutex};
+
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let errors_clone = Arc::clone(&errors);
+
+        let wf = Workflow::builder("test")
+            .register(AddN("one", 1))
+            .register(FailingAgent)
+            .parallel("fan", ["one", "failing_agent"], sum_deltas)
+            .start_at("fan")
+            .build()
+            .unwrap();
+
+        let mut runner = Runner::new(wf).on_error(move |e| {
+            errors_clone.lock().unwrap().push(e.agent.to_string());
+        });
+
+        let mut ctx = Ctx::new();
+        assert!(runner.run(S(0), &mut ctx).is_err());
+        // Agents are back in place, so a second run fails the same way.
+        assert!(runner.run(S(0), &mut ctx).is_err());
+
+        // The branch failure is inner; only the parallel step gives up.
+        assert_eq!(*errors.lock().unwrap(), ["fan", "fan"]);
+    }
+
+    #[test]
+    fn parallel_branch_cannot_route_with_next() {
+        let wf = Workflow::builder("test")
+            .register(NextAgent)
+            .register(DoneAgent)
+            .parallel("fan", ["next_agent"], |s, _| s)
+            .start_at("fan")
+            .build()
+            .unwrap();
+
+        let err = Runner::new(wf).run(S(0), &mut Ctx::new()).err().unwrap();
+        assert!(err.to_string().contains("cannot route"));
+    }
+
+    // --- declarative edges ---
+
+    #[derive(Clone)]
+    struct Flag(bool);
+
+    struct Step(&'static str);
+    impl Agent<Flag> for Step {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn run(&mut self, state: Flag, ctx: &mut Ctx) -> StepResult<Flag> {
+            ctx.log(self.0);
+            if self.0.starts_with("end") {
+                Ok((state, Outcome::Done))
+            } else {
+                Ok((state, Outcome::Continue))
+            }
+        }
+    }
+
+    fn flag_workflow() -> Workflow<Flag> {
+        Workflow::builder("test")
+            .register(Step("check"))
+            .register(Step("end_yes"))
+            .register(Step("end_no"))
+            .start_at("check")
+            .when(|f: &Flag| f.0, "end_yes")
+            .then("end_no")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn when_edge_routes_continue() {
+        let mut runner = Runner::new(flag_workflow());
+
+        let mut ctx = Ctx::new();
+        runner.run(Flag(true), &mut ctx).unwrap();
+        assert_eq!(ctx.logs(), &["check", "end_yes"]);
+
+        let mut ctx = Ctx::new();
+        runner.run(Flag(false), &mut ctx).unwrap();
+        assert_eq!(ctx.logs(), &["check", "end_no"]);
+    }
+
+    #[test]
+    fn branch_routes_by_state() {
+        let wf = Workflow::builder("test")
+            .register(Step("check"))
+            .register(Step("end_yes"))
+            .register(Step("end_no"))
+            .branch(
+                "check",
+                |f: &Flag| if f.0 { "end_yes" } else { "end_no" },
+                ["end_yes", "end_no"],
+            )
+            .build()
+            .unwrap();
+
+        let mut ctx = Ctx::new();
+        Runner::new(wf).run(Flag(false), &mut ctx).unwrap();
+        assert_eq!(ctx.logs(), &["check", "end_no"]);
+    }
+
+    #[test]
+    fn branch_outside_targets_is_an_error() {
+        let wf = Workflow::builder("test")
+            .register(Step("check"))
+            .register(Step("end_yes"))
+            .register(Step("end_no"))
+            .branch("check", |_: &Flag| "end_no", ["end_yes"])
+            .build()
+            .unwrap();
+
+        let err = Runner::new(wf)
+            .run(Flag(false), &mut Ctx::new())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("not one of its targets"));
+    }
+
+    #[test]
+    fn failing_branch_is_caught() {
+        let wf = Workflow::builder("test")
+            .register(Step("check"))
+            .register(Step("end_yes"))
+            .register(Step("end_no"))
+            .branch("check", |_: &Flag| "end_no", ["end_yes"])
+            .catch("end_no")
+            .build()
+            .unwrap();
+
+        let mut ctx = Ctx::new();
+        Runner::new(wf).run(Flag(false), &mut ctx).unwrap();
+        assert_eq!(ctx.logs(), &["check", "end_no"]);
+        let caught = ctx.caught().unwrap();
+        assert_eq!(caught.agent, "check");
+        assert!(caught.error.to_string().contains("not one of its targets"));
+    }
+
+    // --- sub-workflows ---
+
+    #[derive(Clone)]
+    struct Outer {
+        n: u32,
+        label: String,
+    }
+
+    struct Label;
+    impl Agent<Outer> for Label {
+        fn name(&self) -> &'static str {
+            "label"
+        }
+        fn run(&mut self, mut state: Outer, _ctx: &mut Ctx) -> StepResult<Outer> {
+            state.label = format!("n={}", state.n);
+            Ok((state, Outcome::Done))
+        }
+    }
+
+    fn inner_workflow() -> Workflow<S> {
+        Workflow::builder("inner")
+            .register(NextAgent)
+            .register(DoneAgent)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn subworkflow_runs_inline_with_nested_paths() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+
+        let wf = Workflow::builder("outer")
+            .subworkflow(inner_workflow())
+            .register(NextAgent)
+            .register(DoneAgent)
+            .start_at("inner")
+            .then("next_agent")
+            .build()
+            .unwrap();
+
+        let mut runner = Runner::new(wf).on_step(move |e| {
+            events_clone
+                .lock()
+                .unwrap()
+                .push((e.step_number, e.agent.to_string()));
+        });
+        let result = runner.run(S(0), &mut Ctx::new()).unwrap();
+        assert_eq!(result.0, 2);
+
+        let events = events.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![
+                (1, "inner/next_agent".to_string()),
+                (2, "inner/done_agent".to_string()),
+                (3, "inner".to_string()),
+                (4, "next_agent".to_string()),
+                (5, "done_agent".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn subworkflow_mapped_converts_state_both_ways() {
+        let wf = Workflow::builder("outer")
+            .subworkflow_mapped(
+                inner_workflow(),
+                |outer: &Outer| S(outer.n),
+                |mut outer, inner| {
+                    outer.n = inner.0;
+                    outer
+                },
+            )
+            .register(Label)
+            .start_at("inner")
+            .then("label")
+            .build()
+            .unwrap();
+
+        let result = Runner::new(wf)
+            .run(
+                Outer {
+                    n: 41,
+                    label: String::new(),
+                },
+                &mut Ctx::new(),
+            )
+            .unwrap();
+        assert_eq!(result.label, "n=42");
+    }
+
+    #[test]
+    fn subworkflow_error_reports_inner_and_outer_paths() {
+        let (observer, events) = recorder();
+        let inner = Workflow::builder("inner")
+            .register(FailingAgent)
+            .build()
+            .unwrap();
+        let wf = Workflow::builder("outer")
+            .subworkflow(inner)
+            .build()
+            .unwrap();
+
+        let mut runner = Runner::new(wf).with_observer(observer);
+        assert!(runner.run(S(0), &mut Ctx::new()).is_err());
+        let events = events.lock().unwrap();
+        let errors: Vec<_> = events.iter().filter(|e| e.contains("error ")).collect();
+        assert_eq!(errors, ["inner error inner/failing_agent", "error inner"]);
+    }
+
+    #[test]
+    fn subworkflow_shares_max_steps() {
+        let inner = Workflow::builder("inner")
+            .register(AlwaysContinue)
+            .register(DoneAgent)
+            .start_at("always_continue")
+            .then("done_agent")
+            .build()
+            .unwrap();
+        let wf = Workflow::builder("outer")
+            .subworkflow(inner)
+            .build()
+            .unwrap();
+
+        let err = Runner::new(wf)
+            .with_max_steps(1)
+            .run(S(0), &mut Ctx::new())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("max_steps exceeded"));
+    }
+
+    // --- checkpoints ---
+
+    #[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Doc {
+        bumps: u32,
+        report: String,
+    }
+
+    struct Bump;
+    impl Agent<Doc> for Bump {
+        fn name(&self) -> &'static str {
+            "bump"
+        }
+        fn run(&mut self, mut doc: Doc, ctx: &mut Ctx) -> StepResult<Doc> {
+            doc.bumps += 1;
+            ctx.set("bumped", doc.bumps.to_string());
+            ctx.log("bumped");
+            Ok((doc, Outcome::Continue))
+        }
+    }
+
+    struct Report {
+        crash: bool,
+    }
+    impl Agent<Doc> for Report {
+        fn name(&self) -> &'static str {
+            "report"
+        }
+        fn run(&mut self, mut doc: Doc, _ctx: &mut Ctx) -> StepResult<Doc> {
+            if self.crash {
+                return Err(StepError::other("crashed"));
+            }
+            doc.report = format!("bumped {} time(s)", doc.bumps);
+            Ok((doc, Outcome::Done))
+        }
+    }
+
+    fn doc_workflow(crash: bool) -> Workflow<Doc> {
+        Workflow::builder("doc")
+            .register(Bump)
+            .register(Report { crash })
+            .start_at("bump")
+            .then("report")
+            .build()
+            .unwrap()
+    }
+
+    fn checkpoint_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("agent-line-runner-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn resume_continues_from_failed_step() {
+        use crate::{CheckpointStore, FileCheckpointStore};
+        use std::sync::{Arc, Mutex};
+
+        let dir = checkpoint_dir("resume");
+        let empty = Doc {
+            bumps: 0,
+            report: String::new(),
+        };
+
+        let mut crashing = Runner::new(doc_workflow(true))
+            .with_checkpoints(FileCheckpointStore::new(&dir), "run-1");
+        let mut ctx = Ctx::new();
+        assert!(crashing.run(empty, &mut ctx).is_err());
+
+        let saved = FileCheckpointStore::new(&dir)
+            .load("run-1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(saved.agent.as_deref(), Some("report"));
+        assert_eq!(saved.step_number, 1);
+
+        let steps = Arc::new(Mutex::new(Vec::new()));
+        let steps_clone = Arc::clone(&steps);
+        let mut fixed = Runner::new(doc_workflow(false))
+            .with_checkpoints(FileCheckpointStore::new(&dir), "other")
+            .on_step(move |e| {
+                steps_clone
+                    .lock()
+                    .unwrap()
+                    .push((e.step_number, e.agent.to_string()));
+            });
+        let mut ctx = Ctx::new();
+        let doc = fixed
+            .resume("run-1", &mut ctx)
+            .unwrap()
+            .into_done()
+            .unwrap();
+
+        // `bump` is not re-run, and its context survives the restart.
+        assert_eq!(doc.bumps, 1);
+        assert_eq!(doc.report, "bumped 1 time(s)");
+        assert_eq!(ctx.get("bumped"), Some("1"));
+        assert_eq!(ctx.logs(), ["bumped"]);
+        assert_eq!(*steps.lock().unwrap(), vec![(2, "report".to_string())]);
+
+        let saved = FileCheckpointStore::new(&dir)
+            .load("run-1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(saved.agent, None);
+        assert_eq!(saved.step_number, 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resume_finished_run_returns_final_state() {
+        use crate::FileCheckpointStore;
+
+        let dir = checkpoint_dir("finished");
+        let mut runner =
+            Runner::new(doc_workflow(false)).with_checkpoints(FileCheckpointStore::new(&dir), "r");
+        let mut ctx = Ctx::new();
+        let done = runner
+            .run(
+                Doc {
+                    bumps: 0,
+                    report: String::new(),
+                },
+                &mut ctx,
+            )
+            .unwrap();
+
+        let mut ctx = Ctx::new();
+        let resumed = runner.resume("r", &mut ctx).unwrap().into_done().unwrap();
+        assert_eq!(resumed, done);
+        assert_eq!(ctx.logs(), ["bumped"]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resume_rejects_unknown_run_and_other_workflow() {
+        use crate::FileCheckpointStore;
+
+        let dir = checkpoint_dir("reject");
+        let mut ctx = Ctx::new();
+
+        let mut bare = Runner::new(doc_workflow(false));
+        assert!(matches!(
+            bare.resume("r", &mut ctx),
+            Err(StepError::Invalid(_))
+        ));
+
+        let mut runner =
+            Runner::new(doc_workflow(false)).with_checkpoints(FileCheckpointStore::new(&dir), "r");
+        let err = runner.resume("missing", &mut ctx).unwrap_err();
+        assert!(err.to_string().contains("no checkpoint for run 'missing'"));
+
+        runner
+            .run(
+                Doc {
+                    bumps: 0,
+                    report: String::new(),
+                },
+                &mut ctx,
+            )
+            .unwrap();
+        let renamed = Workflow::builder("renamed")
+            .register(Bump)
+            .register(Report { crash: false })
+            .build()
+            .unwrap();
+        let mut other = Runner::new(renamed).with_checkpoints(FileCheckpointStore::new(&dir), "r");
+        let err = other.resume("r", &mut ctx).unwrap_err();
+        assert!(err.to_string().contains("belongs to workflow 'doc'"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // --- pause and resume ---
+
+    /// Pauses for approval, then finishes only if the input was "yes".
+    struct Approver {
+        seen_input: Arc<std::sync::Mutex<Vec<Option<String>>>>,
+    }
+    impl Agent<S> for Approver {
+        fn name(&self) -> &'static str {
+            "approver"
+        }
+        fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+            self.seen_input
+                .lock()
+                .unwrap()
+                .push(ctx.resume_input().map(str::to_string));
+            match ctx.resume_input() {
+                None => Ok((state, Outcome::pause("approve write?", "diff"))),
+                Some("yes") => Ok((S(state.0 + 1), Outcome::Continue)),
+                Some(_) => Ok((state, Outcome::Fail("rejected".into()))),
+            }
+        }
+    }
+
+    fn approval_workflow(seen: &Arc<std::sync::Mutex<Vec<Option<String>>>>) -> Workflow<S> {
+        Workflow::builder("approval")
+            .register(Approver {
+                seen_input: Arc::clone(seen),
+            })
+            .register(DoneAgent)
+            .start_at("approver")
+            .then("done_agent")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn pause_returns_handle_and_resume_passes_input_once() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let steps = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let steps_clone = Arc::clone(&steps);
+        let mut runner = Runner::new(approval_workflow(&seen)).on_step(move |e| {
+            steps_clone
+                .lock()
+                .unwrap()
+                .push((e.step_number, e.agent.to_string()));
+        });
+        let mut ctx = Ctx::new();
+
+        let paused = match runner.start(S(1), &mut ctx).unwrap() {
+            RunStatus::Paused(paused) => paused,
+            RunStatus::Done(_) => panic!("expected a pause"),
+        };
+        assert_eq!(paused.agent(), "approver");
+        assert_eq!(paused.reason, "approve write?");
+        assert_eq!(paused.payload, "diff");
+        assert_eq!(paused.step_number(), 1);
+
+        let done = runner
+            .resume_with(paused, "yes", &mut ctx)
+            .unwrap()
+            .into_done()
+            .unwrap();
+        assert_eq!(done.0, 2);
+        assert_eq!(ctx.resume_input(), None);
+        assert_eq!(*seen.lock().unwrap(), vec![None, Some("yes".to_string())]);
+        assert_eq!(
+            *steps.lock().unwrap(),
+            vec![
+                (1, "approver".to_string()),
+                (2, "approver".to_string()),
+                (3, "done_agent".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn resume_at_skips_the_pausing_agent() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut runner = Runner::new(approval_workflow(&seen));
+        let mut ctx = Ctx::new();
+
+        let RunStatus::Paused(paused) = runner.start(S(1), &mut ctx).unwrap() else {
+            panic!("expected a pause");
+        };
+        let status = runner
+            .resume_with(paused.resume_at("done_agent"), "", &mut ctx)
+            .unwrap();
+        assert!(matches!(status, RunStatus::Done(S(1))));
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn resume_at_unknown_step_is_rejected() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut runner = Runner::new(approval_workflow(&seen));
+        let mut ctx = Ctx::new();
+
+        let RunStatus::Paused(paused) = runner.start(S(1), &mut ctx).unwrap() else {
+            panic!("expected a pause");
+        };
+        let err = runner
+            .resume_with(paused.resume_at("nope"), "", &mut ctx)
+            .err()
+            .unwrap();
+        assert!(matches!(err, StepError::Invalid(_)));
+    }
+
+    #[test]
+    fn run_fails_when_an_agent_pauses() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut runner = Runner::new(approval_workflow(&seen));
+        let mut ctx = Ctx::new();
+
+        let err = runner.run(S(1), &mut ctx).err().unwrap();
+        assert!(err.to_string().contains("paused at 'approver'"));
+    }
+
+    #[test]
+    fn pause_inside_subworkflow_is_an_error() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let wf = Workflow::builder("outer")
+            .subworkflow(approval_workflow(&seen))
+            .build()
+            .unwrap();
+        let mut ctx = Ctx::new();
+
+        let err = Runner::new(wf).start(S(1), &mut ctx).err().unwrap();
+        assert!(err.to_string().contains("'approval/approver' paused"));
+    }
+
+    // --- cancellation and deadlines ---
+
+    struct Sleeper;
+    impl Agent<S> for Sleeper {
+        fn name(&self) -> &'static str {
+            "sleeper"
+        }
+        fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            Ok((S(state.0 + 1), Outcome::Wait(Duration::from_secs(10))))
+        }
+    }
+
+    #[test]
+    fn cancel_wakes_a_waiting_run() {
+        let token = CancellationToken::new();
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let errors_clone = Arc::clone(&errors);
+        let wf = Workflow::builder("test").register(Sleeper).build().unwrap();
+        let mut runner = Runner::new(wf)
+            .with_cancellation(token.clone())
+            .on_error(move |e| errors_clone.lock().unwrap().push(e.agent.to_string()));
+
+        let start = Instant::now();
+        let err = std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                token.cancel();
+            });
+            runner.run(S(0), &mut Ctx::new()).err().unwrap()
+        });
+
+        assert!(matches!(err, StepError::Cancelled(_)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(*errors.lock().unwrap(), vec!["sleeper".to_string()]);
+    }
+
+    #[test]
+    fn deadline_stops_a_long_run() {
+        let wf = Workflow::builder("test").register(Sleeper).build().unwrap();
+        let mut runner = Runner::new(wf).with_deadline(Duration::from_millis(20));
+        let mut ctx = Ctx::new();
+
+        let start = Instant::now();
+        let err = runner.run(S(0), &mut ctx).err().unwrap();
+        assert!(err.to_string().contains("deadline"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(!ctx.is_cancelled());
+    }
+
+    struct CancelSelf {
+        token: CancellationToken,
+        saw_cancel: bool,
+    }
+    impl Agent<S> for CancelSelf {
+        fn name(&self) -> &'static str {
+            "cancel_self"
+        }
+        fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+            self.token.cancel();
+            self.saw_cancel = ctx.is_cancelled();
+            assert!(self.saw_cancel);
+            Ok((state, Outcome::Continue))
+        }
+    }
+
+    #[test]
+    fn agents_see_cancellation_and_runner_stops_before_next_step() {
+        let token = CancellationToken::new();
+        let steps = Arc::new(std::sync::Mutex::new(0));
+        let steps_clone = Arc::clone(&steps);
+        let wf = Workflow::builder("test")
+            .register(CancelSelf {
+                token: token.clone(),
+                saw_cancel: false,
+            })
+            .register(DoneAgent)
+            .start_at("cancel_self")
+            .then("done_agent")
+            .build()
+            .unwrap();
+        let mut runner = Runner::new(wf)
+            .with_cancellation(token)
+            .on_step(move |_| *steps_clone.lock().unwrap() += 1);
+
+        let err = runner.run(S(0), &mut Ctx::new()).err().unwrap();
+        assert!(matches!(err, StepError::Cancelled(_)));
+        assert_eq!(*steps.lock().unwrap(), 1);
+    }
+
+    // --- retry policies ---
+
+    /// Fails with the given error `failures` times, then finishes.
+    struct Flaky {
+        failures: usize,
+        calls: Arc<std::sync::Mutex<usize>>,
+        error: fn() -> StepError,
+    }
+    impl Agent<S> for Flaky {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+        fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            let mut calls = self.calls.lock().unwrap();
+            *calls += 1;
+            if *calls <= self.failures {
+                Err((self.error)())
+            } else {
+                Ok((state, Outcome::Done))
+            }
+        }
+    }
+
+    fn flaky(failures: usize, error: fn() -> StepError) -> (Flaky, Arc<std::sync::Mutex<usize>>) {
+        let calls = Arc::new(std::sync::Mutex::new(0));
+        let agent = Flaky {
+            failures,
+            calls: Arc::clone(&calls),
+            error,
+        };
+        (agent, calls)
+    }
+
+    #[test]
+    fn transient_errors_are_retried_automatically() {
+        let (agent, calls) = flaky(2, || StepError::transient("rate limited"));
+        let wf = Workflow::builder("test").register(agent).build().unwrap();
+
+        Runner::new(wf).run(S(0), &mut Ctx::new()).unwrap();
+        assert_eq!(*calls.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn other_errors_are_not_retried_by_default() {
+        let (agent, calls) = flaky(1, || StepError::invalid("bad"));
+        let wf = Workflow::builder("test").register(agent).build().unwrap();
+
+        assert!(Runner::new(wf).run(S(0), &mut Ctx::new()).is_err());
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn error_event_reports_retries_when_giving_up() {
+        let (agent, calls) = flaky(usize::MAX, || StepError::transient("down"));
+        let wf = Workflow::builder("test").register(agent).build().unwrap();
+        let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reported_clone = Arc::clone(&reported);
+        let mut runner = Runner::new(wf)
+            .with_max_retries(2)
+            .on_error(move |e| reported_clone.lock().unwrap().push(e.retries));
+
+        let err = runner.run(S(0), &mut Ctx::new()).err().unwrap();
+        assert!(matches!(err, StepError::Transient(_)));
+        assert_eq!(*calls.lock().unwrap(), 3);
+        assert_eq!(*reported.lock().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn per_agent_policy_overrides_runner_retries_and_backs_off() {
+        let (agent, calls) = flaky(2, || StepError::other("flaky disk"));
+        let wf = Workflow::builder("test")
+            .register(agent)
+            .retry_policy(
+                "flaky",
+                RetryPolicy::new(2)
+                    .with_backoff(Duration::from_millis(10), Duration::from_secs(1))
+                    .retry_on(|e| matches!(e, StepError::Other(_))),
+            )
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        Runner::new(wf)
+            .with_max_retries(0)
+            .run(S(0), &mut Ctx::new())
+            .unwrap();
+        assert_eq!(*calls.lock().unwrap(), 3);
+        // 10ms before the first retry, 20ms before the second.
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn per_agent_policy_limits_retry_outcomes() {
+        let wf = Workflow::builder("test")
+            .register(RetryAgent {
+                attempts: 0,
+                succeed_on: 3,
+            })
+            .retry_policy("retry_agent", RetryPolicy::new(1))
+            .build()
+            .unwrap();
+
+        let err = Runner::new(wf).run(S(0), &mut Ctx::new()).err().unwrap();
+        assert!(err.to_string().contains("exceeded max retries (1)"));
+    }
+
+    #[test]
+    fn parallel_branches_retry_transient_errors() {
+        let (agent, calls) = flaky(1, || StepError::transient("blip"));
+        let wf = Workflow::builder("test")
+            .register(agent)
+            .register(DoneAgent)
+            .parallel("fan", ["flaky"], |state, _| state)
+            .start_at("fan")
+            .then("done_agent")
+            .build()
+            .unwrap();
+
+        Runner::new(wf).run(S(0), &mut Ctx::new()).unwrap();
+        assert_eq!(*calls.lock().unwrap(), 2);
+    }
+
+    // --- step timeouts ---
+
+    /// Sleeps for the next duration in `naps` (or not at all once they run
+    /// out), then records itself in ctx and finishes.
+    struct Napper {
+        naps: Vec<Duration>,
+    }
+    impl Agent<S> for Napper {
+        fn name(&self) -> &'static str {
+            "napper"
+        }
+        fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+            if !self.naps.is_empty() {
+                std::thread::sleep(self.naps.remove(0));
+            }
+            ctx.set("napped", "yes");
+            Ok((S(state.0 + 1), Outcome::Continue))
+        }
+    }
+
+    #[test]
+    fn slow_step_times_out() {
+        let wf = Workflow::builder("test")
+            .register(Napper {
+                naps: vec![Duration::from_secs(10)],
+            })
+            .build()
+            .unwrap();
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let errors_clone = Arc::clone(&errors);
+        let mut runner = Runner::new(wf)
+            .with_step_timeout(Duration::from_millis(20))
+            .on_error(move |e| errors_clone.lock().unwrap().push(e.agent.to_string()));
+        let mut ctx = Ctx::new();
+
+        let start = Instant::now();
+        let err = runner.run(S(0), &mut ctx).err().unwrap();
+        assert!(matches!(err, StepError::Timeout(_)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(*errors.lock().unwrap(), vec!["napper".to_string()]);
+        assert_eq!(ctx.get("napped"), None);
+    }
+
+    #[test]
+    fn timed_step_in_time_keeps_ctx_changes() {
+        let wf = Workflow::builder("test")
+            .register(Napper { naps: vec![] })
+            .register(DoneAgent)
+            .start_at("napper")
+            .then("done_agent")
+            .timeout("napper", Duration::from_secs(5))
+            .build()
+            .unwrap();
+        let mut ctx = Ctx::new();
+
+        let result = Runner::new(wf).run(S(0), &mut ctx).unwrap();
+        assert_eq!(result.0, 1);
+        assert_eq!(ctx.get("napped"), Some("yes"));
+    }
+
+    #[test]
+    fn timed_out_step_is_retried_once_the_agent_returns() {
+        let wf = Workflow::builder("test")
+            .register(Napper {
+                naps: vec![Duration::from_millis(50)],
+            })
+            .register(DoneAgent)
+            .start_at("napper")
+            .then("done_agent")
+            .timeout("napper", Duration::from_millis(20))
+            .retry_policy(
+                "napper",
+                RetryPolicy::new(1)
+                    .with_backoff(Duration::from_millis(100), Duration::from_millis(100))
+                    .retry_on(|e| matches!(e, StepError::Timeout(_))),
+            )
+            .build()
+            .unwrap();
+        let mut ctx = Ctx::new();
+
+        let result = Runner::new(wf).run(S(0), &mut ctx).unwrap();
+        assert_eq!(result.0, 1);
+    }
+
+    #[test]
+    fn parallel_branch_times_out() {
+        let wf = Workflow::builder("test")
+            .register(Napper {
+                naps: vec![Duration::from_secs(10)],
+            })
+            .register(DoneAgent)
+            .parallel("fan", ["napper"], |state, _| state)
+            .start_at("fan")
+            .then("done_agent")
+            .timeout("napper", Duration::from_millis(20))
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        let err = Runner::new(wf).run(S(0), &mut Ctx::new()).err().unwrap();
+        assert!(matches!(err, StepError::Timeout(_)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    struct PanicOnce(Arc<std::sync::atomic::AtomicUsize>);
+    impl Agent<S> for PanicOnce {
+        fn name(&self) -> &'static str {
+            "panic_once"
+        }
+        fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            if self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                panic!("first call");
+            }
+            Ok((state, Outcome::Done))
+        }
+    }
+
+    #[test]
+    fn timed_agent_that_panics_is_rebuilt_from_its_factory() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let factory_calls = Arc::clone(&calls);
+        let wf = Workflow::builder("test")
+            .register_with(move || PanicOnce(Arc::clone(&factory_calls)))
+            .timeout("panic_once", Duration::from_secs(5))
+            .build()
+            .unwrap();
+        let mut runner = Runner::new(wf);
+
+        let err = runner.run(S(0), &mut Ctx::new()).err().unwrap();
+        assert!(err.to_string().contains("'panic_once' panicked"));
+        assert!(runner.run(S(0), &mut Ctx::new()).is_ok());
+    }
+
+    #[test]
+    fn timed_agent_that_panics_without_a_factory_stays_failed() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let wf = Workflow::builder("test")
+            .register(PanicOnce(calls))
+            .timeout("panic_once", Duration::from_secs(5))
+            .build()
+            .unwrap();
+        let mut runner = Runner::new(wf);
+
+        let err = runner.run(S(0), &mut Ctx::new()).err().unwrap();
+        assert!(err.to_string().contains("'panic_once' panicked"));
+        for _ in 0..2 {
+            let err = runner.run(S(0), &mut Ctx::new()).err().unwrap();
+            assert!(err.to_string().contains("not registered with a factory"));
+        }
+    }
+
+    // --- map steps ---
+
+    struct Square;
+    impl Agent<u32> for Square {
+        fn name(&self) -> &'static str {
+            "square"
+        }
+        fn run(&mut self, item: u32, ctx: &mut Ctx) -> StepResult<u32> {
+            if item == 0 {
+                return Err(StepError::other("zero"));
+            }
+            ctx.set(format!("item{item}"), "ran");
+            Ok((item * item, Outcome::Continue))
+        }
+    }
+
+    fn squares(items: fn(&S) -> Vec<u32>, concurrency: usize) -> Workflow<S> {
+        Workflow::builder("test")
+            .register(DoneAgent)
+            .map(
+                "squares",
+                items,
+                || Square,
+                |_, results| S(results.iter().sum()),
+                concurrency,
+            )
+            .start_at("squares")
+            .then("done_agent")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn map_runs_each_item_and_reduces_in_order() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        let mut ctx = Ctx::new();
+        let mut runner = Runner::new(squares(|s| (1..=s.0).collect(), 2)).on_step(move |e| {
+            events_clone
+                .lock()
+                .unwrap()
+                .push((e.step_number, e.agent.to_string(), e.item));
+        });
+
+        let result = runner.run(S(3), &mut ctx).unwrap();
+        assert_eq!(result.0, 1 + 4 + 9);
+        assert_eq!(ctx.get("item1"), Some("ran"));
+        assert_eq!(ctx.get("item3"), Some("ran"));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (1, "squares/square".to_string(), Some(0)),
+                (2, "squares/square".to_string(), Some(1)),
+                (3, "squares/square".to_string(), Some(2)),
+                (4, "squares".to_string(), None),
+                (5, "done_agent".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn map_over_empty_collection_reduces_nothing() {
+        let result = Runner::new(squares(|_| Vec::new(), 4))
+            .run(S(7), &mut Ctx::new())
+            .unwrap();
+        assert_eq!(result.0, 0);
+    }
+
+    #[test]
+    fn map_item_failure_fails_the_step() {
+        let err = Runner::new(squares(|_| vec![2, 0, 3], 1))
+            .run(S(0), &mut Ctx::new())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("zero"));
+
+        let wf = Workflow::builder("test")
+            .register(DoneAgent)
+            .map(
+                "squares",
+                |_: &S| vec![2, 0, 3],
+                || Square,
+                |_, results| S(results.iter().sum()),
+                1,
+            )
+            .start_at("squares")
+            .on_error("squares", "done_agent")
+            .build()
+            .unwrap();
+        let result = Runner::new(wf).run(S(5), &mut Ctx::new()).unwrap();
+        assert_eq!(result.0, 5);
+    }
+
+    #[test]
+    fn map_items_take_the_step_retry_policy_and_timeout() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&runs);
+        let wf = Workflow::builder("test")
+            .map(
+                "squares",
+                |_: &S| vec![2, 0, 3],
+                || Square,
+                |_, results| S(results.iter().sum()),
+                1,
+            )
+            .retry_policy(
+                "squares",
+                RetryPolicy::new(2).retry_on(|e| e.to_string().contains("zero")),
+            )
+            .timeout("squares", Duration::from_secs(5))
+            .item_layer(
+                "squares",
+                crate::middleware_fn(move |item: u32, ctx: &mut Ctx, next| {
+                    counted.fetch_add(1, Ordering::SeqCst);
+                    next.run(item, ctx)
+                }),
+            )
+            .build()
+            .unwrap();
+        let (observer, events) = recorder();
+        let err = Runner::new(wf)
+            .with_observer(observer)
+            .run(S(0), &mut Ctx::new())
+            .err()
+            .unwrap();
+
+        assert!(err.to_string().contains("zero"));
+        // Only the zero item is retried, and the step is not run again.
+        assert_eq!(runs.load(Ordering::SeqCst), 5);
+        let events = events.lock().unwrap();
+        assert!(events.contains(&"inner error squares/square #1".to_string()));
+        assert!(events.contains(&"error squares".to_string()));
+    }
+
+    // --- run reports ---
+
+    #[test]
+    fn report_of_finished_run() {
+        let mut runner = Runner::new(doc_workflow(false));
+        let report = runner.run_with_report(Doc::default(), &mut Ctx::new());
+
+        assert!(report.is_ok());
+        assert_eq!(report.state.report, "bumped 1 time(s)");
+        let agents: Vec<_> = report.steps.iter().map(|s| s.agent.as_str()).collect();
+        assert_eq!(agents, ["bump", "report"]);
+        assert!(matches!(report.steps[1].outcome, Outcome::Done));
+        assert_eq!(report.steps[1].step_number, 2);
+        assert!(report.failed_agent.is_none() && report.failed_step.is_none());
+        assert_eq!(report.into_result().unwrap().bumps, 1);
+    }
+
+    #[test]
+    fn report_keeps_last_good_state_on_error() {
+        let mut runner = Runner::new(doc_workflow(true));
+        let report = runner.run_with_report(Doc::default(), &mut Ctx::new());
+
+        assert!(!report.is_ok());
+        assert_eq!(report.state.bumps, 1);
+        assert_eq!(report.steps.len(), 1);
+        assert_eq!(report.failed_agent.as_deref(), Some("report"));
+        assert_eq!(report.failed_step, Some(2));
+        assert!(report.error.unwrap().to_string().contains("crashed"));
+    }
+
+    #[test]
+    fn report_names_the_step_that_returned_fail() {
+        let wf = Workflow::builder("test")
+            .register(AlwaysContinue)
+            .register(FailOutcomeAgent)
+            .start_at("always_continue")
+            .then("fail_outcome")
+            .build()
+            .unwrap();
+        let report = Runner::new(wf).run_with_report(S(0), &mut Ctx::new());
+
+        assert_eq!(report.failed_agent.as_deref(), Some("fail_outcome"));
+        assert_eq!(report.failed_step, Some(2));
+        assert!(report.error.is_some());
+    }
+
+    // --- observers ---
+
+    use crate::{
+        BeforeStepEvent, RetryEvent, RouteEvent, RunEndEvent, RunObserver, RunStartEvent, WaitEvent,
+    };
+    use std::sync::Mutex;
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl RunObserver for Recorder {
+        fn on_run_start(&mut self, e: &RunStartEvent) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("start {} at {}", e.workflow, e.agent));
+        }
+        fn before_step(&mut self, e: &BeforeStepEvent) {
+            self.0.lock().unwrap().push(format!("before {}", e.agent));
+        }
+        fn after_step(&mut self, e: &StepEvent) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("after {} #{}", e.agent, e.step_number));
+        }
+        fn on_error(&mut self, e: &ErrorEvent) {
+            self.0.lock().unwrap().push(format!("error {}", e.agent));
+        }
+        fn on_inner_error(&mut self, e: &ErrorEvent) {
+            let item = e.item.map(|i| format!(" #{i}")).unwrap_or_default();
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("inner error {}{item}", e.agent));
+        }
+        fn on_retry(&mut self, e: &RetryEvent) {
+            let kind = if e.error.is_some() {
+                "error"
+            } else {
+                "outcome"
+            };
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("retry {} {} ({kind})", e.agent, e.retry));
+        }
+        fn on_wait(&mut self, e: &WaitEvent) {
+            self.0.lock().unwrap().push(format!("wait {}", e.agent));
+        }
+        fn on_catch(&mut self, e: &CatchEvent) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("catch {} -> {}", e.agent, e.handler));
+        }
+        fn on_route(&mut self, e: &RouteEvent) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("route {} -> {}", e.from, e.to));
+        }
+        fn on_run_end(&mut self, e: &RunEndEvent) {
+            let status = if e.error.is_some() { "failed" } else { "ok" };
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("end {status} after {}", e.step_number));
+        }
+    }
+
+    fn recorder() -> (Recorder, Arc<Mutex<Vec<String>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        (Recorder(Arc::clone(&events)), events)
+    }
+
+    #[test]
+    fn observer_sees_the_whole_lifecycle() {
+        let wf = Workflow::builder("test")
+            .register(WaitOnce { waited: false })
+            .build()
+            .unwrap();
+        let (observer, events) = recorder();
+        let mut runner = Runner::new(wf).with_observer(observer);
+        runner.run(S(0), &mut Ctx::new()).unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "start test at wait_once",
+                "before wait_once",
+                "after wait_once #1",
+                "wait wait_once",
+                "before wait_once",
+                "after wait_once #2",
+                "end ok after 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn observer_sees_routes_and_retries() {
+        let (flaky, _) = flaky(1, || StepError::transient("blip"));
+        let wf = Workflow::builder("test")
+            .register(AlwaysContinue)
+            .register(flaky)
+            .start_at("always_continue")
+            .then("flaky")
+            .build()
+            .unwrap();
+        let (observer, events) = recorder();
+        let mut runner = Runner::new(wf).with_observer(observer);
+        runner.run(S(0), &mut Ctx::new()).unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "start test at always_continue",
+                "before always_continue",
+                "after always_continue #1",
+                "route always_continue -> flaky",
+                "before flaky",
+                "retry flaky 1 (error)",
+                "before flaky",
+                "after flaky #3",
+                "end ok after 3",
+            ]
+        );
+
+        let wf = Workflow::builder("test")
+            .register(RetryAgent {
+                attempts: 0,
+                succeed_on: 2,
+            })
+            .build()
+            .unwrap();
+        let (observer, events) = recorder();
+        let mut runner = Runner::new(wf).with_observer(observer);
+        runner.run(S(0), &mut Ctx::new()).unwrap();
+        assert!(
+            events
+                .lock()
+                .unwrap()
+                .contains(&"retry retry_agent 1 (outcome)".to_string())
+        );
+    }
+
+    #[test]
+    fn several_observers_and_hooks_all_fire() {
+        let wf = Workflow::builder("test")
+            .register(FailingAgent)
+            .build()
+            .unwrap();
+        let (first, first_events) = recorder();
+        let (second, second_events) = recorder();
+        let steps = Arc::new(Mutex::new(0));
+        let (a, b) = (Arc::clone(&steps), Arc::clone(&steps));
+        let mut runner = Runner::new(wf)
+            .with_observer(first)
+            .with_observer(second)
+            .on_error(move |_| *a.lock().unwrap() += 1)
+            .on_error(move |_| *b.lock().unwrap() += 1)
+            .with_max_retries(0);
+        assert!(runner.run(S(0), &mut Ctx::new()).is_err());
+
+        assert_eq!(*steps.lock().unwrap(), 2);
+        assert_eq!(
+            *first_events.lock().unwrap(),
+            *second_events.lock().unwrap()
+        );
+        assert_eq!(
+            first_events.lock().unwrap().last().unwrap(),
+            "end failed after 1"
+        );
+    }
+
+    // --- error handlers ---
+
+    /// Records the failure it was handed and finishes the run.
+    struct Restore(&'static str);
+    impl Agent<S> for Restore {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+            let caught = ctx.caught().unwrap();
+            let line = format!("{} caught {}: {}", self.0, caught.agent, caught.error);
+            ctx.log(line);
+            Ok((S(state.0 + 100), Outcome::Done))
+        }
+    }
+
+    #[test]
+    fn on_error_routes_an_error_to_its_handler() {
+        let wf = Workflow::builder("test")
+            .register(AddN("one", 1))
+            .register(FailingAgent)
+            .register(Restore("restore"))
+            .start_at("one")
+            .then("failing_agent")
+            .on_error("failing_agent", "restore")
+            .retry_policy("failing_agent", RetryPolicy::new(0))
+            .build()
+            .unwrap();
+        let (observer, events) = recorder();
+        let mut ctx = Ctx::new();
+        let state = Runner::new(wf)
+            .with_observer(observer)
+            .run(S(0), &mut ctx)
+            .unwrap();
+
+        // The handler gets the state the failed step started with.
+        assert_eq!(state.0, 101);
+        assert_eq!(
+            ctx.logs().last().unwrap(),
+            "restore caught failing_agent: transient: boom"
+        );
+        assert_eq!(ctx.caught().unwrap().step_number, 2);
+        let events = events.lock().unwrap();
+        assert!(events.contains(&"catch failing_agent -> restore".to_string()));
+        assert!(!events.iter().any(|e| e.starts_with("error")));
+        assert_eq!(events.last().unwrap(), "end ok after 3");
+    }
+
+    #[test]
+    fn caught_parallel_failure_never_reaches_on_error() {
+        let wf = Workflow::builder("test")
+            .register(AddN("one", 1))
+            .register(FailingAgent)
+            .register(Restore("restore"))
+            .parallel("fan", ["one", "failing_agent"], sum_deltas)
+            .start_at("fan")
+            .on_error("fan", "restore")
+            .retry_policy("failing_agent", RetryPolicy::new(0))
+            .build()
+            .unwrap();
+        let (observer, events) = recorder();
+        let report = Runner::new(wf)
+            .with_observer(observer)
+            .run_with_report(S(0), &mut Ctx::new());
+
+        assert!(report.is_ok());
+        assert_eq!(report.failed_agent, None);
+        let events = events.lock().unwrap();
+        assert!(events.contains(&"inner error fan/failing_agent".to_string()));
+        assert_eq!(events.iter().filter(|e| e.starts_with("catch")).count(), 1);
+        assert!(events.contains(&"catch fan -> restore".to_string()));
+        assert!(!events.iter().any(|e| e.starts_with("error")));
+    }
+
+    #[test]
+    fn catch_handles_fail_outcomes_and_retry_limits() {
+        let wf = Workflow::builder("test")
+            .register(FailOutcomeAgent)
+            .register(Restore("restore"))
+            .catch("restore")
+            .build()
+            .unwrap();
+        let mut ctx = Ctx::new();
+        Runner::new(wf).run(S(0), &mut ctx).unwrap();
+        assert_eq!(ctx.caught().unwrap().error.to_string(), "reason");
+
+        let wf = Workflow::builder("test")
+            .register(AlwaysRetry)
+            .register(Restore("restore"))
+            .catch("restore")
+            .build()
+            .unwrap();
+        let mut ctx = Ctx::new();
+        Runner::new(wf)
+            .with_max_retries(1)
+            .run(S(0), &mut ctx)
+            .unwrap();
+        let caught = ctx.take_caught().unwrap();
+        assert_eq!(caught.agent, "always_retry");
+        assert!(caught.error.to_string().contains("exceeded max retries"));
+        assert!(ctx.caught().is_none());
+    }
+
+    #[test]
+    fn on_error_takes_precedence_over_catch() {
+        let wf = Workflow::builder("test")
+            .register(FailOutcomeAgent)
+            .register(Restore("restore"))
+            .register(Restore("fallback"))
+            .on_error("fail_outcome", "restore")
+            .catch("fallback")
+            .build()
+            .unwrap();
+        let mut ctx = Ctx::new();
+        Runner::new(wf).run(S(0), &mut ctx).unwrap();
+        assert!(ctx.logs()[0].starts_with("restore caught"));
+    }
+
+    #[test]
+    fn a_failing_catch_handler_ends_the_run() {
+        let wf = Workflow::builder("test")
+            .register(FailOutcomeAgent)
+            .register(FailingAgent)
+            .on_error("fail_outcome", "failing_agent")
+            .catch("failing_agent")
+            .retry_policy("failing_agent", RetryPolicy::new(0))
+            .build()
+            .unwrap();
+        let report = Runner::new(wf).run_with_report(S(0), &mut Ctx::new());
+
+        assert_eq!(report.error.unwrap().to_string(), "transient: boom");
+        assert_eq!(report.failed_agent.as_deref(), Some("failing_agent"));
+        assert_eq!(report.failed_step, Some(2));
+    }
+
+    #[test]
+    fn cancellation_is_not_handled() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let wf = Workflow::builder("test")
+            .register(DoneAgent)
+            .register(Restore("restore"))
+            .catch("restore")
+            .build()
+            .unwrap();
+        let err = Runner::new(wf)
+            .with_cancellation(token)
+            .run(S(0), &mut Ctx::new())
+            .err()
+            .unwrap();
+        assert!(matches!(err, StepError::Cancelled(_)));
+    }
+
+    #[test]
+    fn inner_failures_reach_the_parent_handler() {
+        let inner = Workflow::builder("inner")
+            .register(FailOutcomeAgent)
+            .build()
+            .unwrap();
+        let wf = Workflow::builder("outer")
+            .subworkflow(inner)
+            .register(Restore("restore"))
+            .on_error("inner", "restore")
+            .build()
+            .unwrap();
+        let report = Runner::new(wf).run_with_report(S(0), &mut Ctx::new());
+
+        assert!(report.is_ok());
+        assert_eq!(report.state.0, 100);
+        assert_eq!(report.failed_agent, None);
+    }
+
+    #[test]
+    fn runners_can_move_to_another_thread() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let wf = Workflow::builder("send")
+            .register(RetryAgent {
+                attempts: 0,
+                succeed_on: 1,
+            })
+            .build()
+            .unwrap();
+        let runner = Runner::new(wf).on_step(|_| {}).on_error(|_| {});
+        assert_send(&runner);
+        let result = std::thread::spawn(move || {
+            let mut runner = runner;
+            runner.run(S(0), &mut Ctx::new()).map(|s| s.0)
+        });
+        assert!(result.join().unwrap().is_ok());
+    }
+
+    /// Hands the state to `to` with `Next`, counting up, until it reaches
+    /// `stop_at`.
+    struct PingPong {
+        name: &'static str,
+        to: &'static str,
+        stop_at: u32,
+    }
+
+    impl Agent<S> for PingPong {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+        fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+            ctx.log(self.name);
+            if state.0 >= self.stop_at {
+                return Ok((state, Outcome::Done));
+            }
+            Ok((S(state.0 + 1), Outcome::next(self.to)))
+        }
+    }
+
+    struct Publish;
+
+    impl Agent<S> for Publish {
+        fn name(&self) -> &'static str {
+            "publish"
+        }
+        fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+            ctx.log(format!("published after {}", state.0));
+            Ok((state, Outcome::Done))
+        }
+    }
+
+    fn writer_editor() -> crate::WorkflowBuilder<S> {
+        Workflow::builder("review")
+            .register(PingPong {
+                name: "writer",
+                to: "editor",
+                stop_at: 100,
+            })
+            .register(PingPong {
+                name: "editor",
+                to: "writer",
+                stop_at: 100,
+            })
+            .register(Publish)
+    }
+
+    #[test]
+    fn max_visits_stops_a_next_cycle() {
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&errors);
+        let wf = writer_editor().max_visits("editor", 2).build().unwrap();
+        let mut runner = Runner::new(wf).on_error(move |e| {
+            seen.lock().unwrap().push(e.agent.to_string());
+        });
+        let mut ctx = Ctx::new();
+        let err = runner.run(S(0), &mut ctx).err().unwrap();
+
+        assert!(matches!(err, StepError::LoopLimit(_)));
+        assert!(err.to_string().contains("'editor'"));
+        assert_eq!(
+            ctx.logs(),
+            ["writer", "editor", "writer", "editor", "writer"]
+        );
+        assert_eq!(*errors.lock().unwrap(), ["writer"]);
+    }
+
+    #[test]
+    fn loop_exit_takes_over_from_a_step_out_of_visits() {
+        let wf = writer_editor()
+            .max_visits("editor", 2)
+            .loop_exit("editor", "publish")
+            .build()
+            .unwrap();
+        assert!(wf.validate().is_empty());
+        let mut ctx = Ctx::new();
+        let state = Runner::new(wf).run(S(0), &mut ctx).unwrap();
+
+        assert_eq!(state.0, 5);
+        assert_eq!(ctx.logs().last().unwrap(), "published after 5");
+    }
+
+    #[test]
+    fn loop_exits_are_held_to_their_own_limits() {
+        let wf = writer_editor()
+            .max_visits("writer", 2)
+            .loop_exit("writer", "editor")
+            .max_visits("editor", 2)
+            .loop_exit("editor", "writer")
+            .build()
+            .unwrap();
+        let mut ctx = Ctx::new();
+        let err = Runner::new(wf).run(S(0), &mut ctx).err().unwrap();
+
+        assert!(matches!(err, StepError::LoopLimit(_)));
+        assert!(err.to_string().contains("'editor'"));
+        assert_eq!(ctx.logs(), ["writer", "editor", "writer", "editor"]);
+    }
+
+    #[test]
+    fn runner_visit_limit_applies_to_every_step_and_can_be_caught() {
+        let wf = writer_editor().catch("publish").build().unwrap();
+        let mut ctx = Ctx::new();
+        let state = Runner::new(wf)
+            .with_max_visits(3)
+            .run(S(0), &mut ctx)
+            .unwrap();
+        // writer 3 times, editor 3 times, then writer is out of visits.
+        assert_eq!(state.0, 6);
+        let caught = ctx.caught().unwrap();
+        assert_eq!(caught.agent, "editor");
+        assert!(matches!(caught.error, StepError::LoopLimit(_)));
+
+        // A workflow's own limit wins over the runner's.
+        let wf = writer_editor().max_visits("writer", 10).build().unwrap();
+        let err = Runner::new(wf)
+            .with_max_visits(3)
+            .run(S(0), &mut Ctx::new())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("'editor'"));
+    }
+
+    #[test]
+    fn retries_within_a_visit_do_not_count() {
+        let wf = Workflow::builder("retry")
+            .register(RetryAgent {
+                attempts: 0,
+                succeed_on: 3,
+            })
+            .max_visits("retry_agent", 1)
+            .build()
+            .unwrap();
+        assert!(Runner::new(wf).run(S(0), &mut Ctx::new()).is_ok());
+    }
 }
@@ -0,0 +1,497 @@
+//! `tracing` spans and events for runs and steps (the `tracing` feature).
+//!
+//! [`crate::Runner::with_tracing`] attaches a [`TracingObserver`] when the
+//! feature is on, so runs show up in whatever subscriber the application
+//! installed.
+
+use crate::{
+    BeforeStepEvent, CatchEvent, ErrorEvent, Provider, RetryEvent, RouteEvent, RunEndEvent,
+    RunObserver, RunStartEvent, StepError, StepEvent, WaitEvent,
+};
+use std::ops::Deref;
+use tracing::{Span, field};
+
+/// Opens a `workflow.run` span per run and an `agent.step` span per step.
+/// Step spans are entered while the step runs, so LLM and tool spans nest
+/// under them.
+#[derive(Default)]
+pub(crate) struct TracingObserver {
+    run: Option<Entered>,
+    workflow: String,
+    steps: Vec<(String, Option<usize>, Entered)>,
+}
+
+/// A span entered until dropped. Unlike `tracing`'s `EnteredSpan` it is
+/// `Send`, so the observer can be; runs and steps start and end on the
+/// run's thread, so it is always exited where it was entered.
+struct Entered(Span);
+
+impl Entered {
+    fn new(span: Span) -> Self {
+        span.with_subscriber(|(id, dispatch)| dispatch.enter(id));
+        Self(span)
+    }
+}
+
+impl Deref for Entered {
+    type Target = Span;
+
+    fn deref(&self) -> &Span {
+        &self.0
+    }
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        self.0.with_subscriber(|(id, dispatch)| dispatch.exit(id));
+    }
+}
+
+impl TracingObserver {
+    /// The innermost open step that `path` is nested in, else the run.
+    fn parent_of(&self, path: &str) -> Option<&Span> {
+        self.steps
+            .iter()
+            .rev()
+            .find(|(open, _, _)| {
+                path.strip_prefix(open.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .map(|(_, _, span)| &**span)
+            .or(self.run.as_deref())
+    }
+
+    /// Stop tracking the latest open step named `path`, for map item
+    /// `item` if it is one.
+    fn close(&mut self, path: &str, item: Option<usize>) -> Option<Entered> {
+        let pos = self
+            .steps
+            .iter()
+            .rposition(|(open, at, _)| open == path && *at == item)?;
+        Some(self.steps.remove(pos).2)
+    }
+}
+
+impl RunObserver for TracingObserver {
+    fn on_run_start(&mut self, event: &RunStartEvent) {
+        let span = tracing::info_span!(
+            "workflow.run",
+            workflow = event.workflow,
+            start = event.agent,
+            resumed_at = event.step_number,
+        );
+        self.workflow = event.workflow.to_string();
+        self.run = Some(Entered::new(span));
+    }
+
+    fn before_step(&mut self, event: &BeforeStepEvent) {
+        let span = tracing::info_span!(
+            parent: self.parent_of(event.agent).and_then(Span::id),
+            "agent.step",
+            workflow = %self.workflow,
+            agent = event.agent,
+            item = event.item,
+            retries = event.retries,
+            step_number = field::Empty,
+        );
+        self.steps
+            .push((event.agent.to_string(), event.item, Entered::new(span)));
+    }
+
+    fn after_step(&mut self, event: &StepEvent) {
+        if let Some(span) = self.close(event.agent, event.item) {
+            span.record("step_number", event.step_number);
+            tracing::info!(
+                parent: &*span,
+                workflow = %self.workflow,
+                agent = event.agent,
+                item = event.item,
+                step_number = event.step_number,
+                retries = event.retries,
+                outcome = ?event.outcome,
+                duration_ms = event.duration.as_millis() as u64,
+                "step finished"
+            );
+        }
+    }
+
+    fn on_error(&mut self, event: &ErrorEvent) {
+        let span = self.close(event.agent, event.item);
+        if let Some(span) = &span {
+            span.record("step_number", event.step_number);
+        }
+        tracing::error!(
+            parent: span.as_deref().and_then(Span::id),
+            workflow = %self.workflow,
+            agent = event.agent,
+            item = event.item,
+            step_number = event.step_number,
+            retries = event.retries,
+            error = %event.error,
+            "step failed"
+        );
+    }
+
+    fn on_inner_error(&mut self, event: &ErrorEvent) {
+        // The failed step inside a parallel step or sub-workflow still has
+        // a span of its own to close.
+        self.on_error(event);
+    }
+
+    fn on_catch(&mut self, event: &CatchEvent) {
+        // An `Err` never reaches `after_step`; close its span here.
+        let span = self.close(event.agent, None);
+        tracing::warn!(
+            parent: span.as_deref().and_then(Span::id),
+            workflow = %self.workflow,
+            agent = event.agent,
+            handler = event.handler,
+            step_number = event.step_number,
+            error = %event.error,
+            "step failed, running error handler"
+        );
+    }
+
+    fn on_retry(&mut self, event: &RetryEvent) {
+        // An errored attempt never reaches `after_step`; close its span here.
+        let span = match event.error {
+            Some(_) => self.close(event.agent, None),
+            None => None,
+        };
+        tracing::warn!(
+            parent: span.as_deref().and_then(Span::id),
+            workflow = %self.workflow,
+            agent = event.agent,
+            step_number = event.step_number,
+            retry = event.retry,
+            delay_ms = event.delay.as_millis() as u64,
+            error = event.error.map(field::display),
+            "retrying step"
+        );
+    }
+
+    fn on_wait(&mut self, event: &WaitEvent) {
+        tracing::info!(
+            workflow = %self.workflow,
+            agent = event.agent,
+            step_number = event.step_number,
+            wait_ms = event.duration.as_millis() as u64,
+            "step waiting"
+        );
+    }
+
+    fn on_route(&mut self, event: &RouteEvent) {
+        tracing::debug!(
+            workflow = %self.workflow,
+            from = event.from,
+            to = event.to,
+            step_number = event.step_number,
+            "routing"
+        );
+    }
+
+    fn on_run_end(&mut self, event: &RunEndEvent) {
+        // Drop any steps a failure left open, innermost first.
+        while self.steps.pop().is_some() {}
+        let duration_ms = event.duration.as_millis() as u64;
+        match event.error {
+            Some(err) => tracing::error!(
+                workflow = %self.workflow,
+                steps = event.step_number,
+                duration_ms,
+                error = %err,
+                "workflow failed"
+            ),
+            None => tracing::info!(
+                workflow = %self.workflow,
+                steps = event.step_number,
+                duration_ms,
+                paused = event.paused,
+                "workflow finished"
+            ),
+        }
+        self.run = None;
+    }
+}
+
+/// Run one LLM exchange inside an `llm.chat` span, recording the token usage
+/// the provider reports.
+pub(crate) fn chat_span(
+    provider: Provider,
+    model: &str,
+    max_tokens: u32,
+    call: impl FnOnce() -> Result<serde_json::Value, StepError>,
+) -> Result<serde_json::Value, StepError> {
+    let span = open_chat(provider, model, max_tokens);
+    let result = span.in_scope(call);
+    close_chat(&span, provider, model, &result);
+    result
+}
+
+/// The `llm.chat` span for one LLM exchange, not yet entered.
+pub(crate) fn open_chat(provider: Provider, model: &str, max_tokens: u32) -> Span {
+    tracing::info_span!(
+        "llm.chat",
+        provider = provider.name(),
+        model,
+        max_tokens,
+        input_tokens = field::Empty,
+        output_tokens = field::Empty,
+    )
+}
+
+/// Record the token usage, or the error, of the exchange in `span`.
+pub(crate) fn close_chat(
+    span: &Span,
+    provider: Provider,
+    model: &str,
+    result: &Result<serde_json::Value, StepError>,
+) {
+    span.in_scope(|| match result {
+        Ok(json) => {
+            let usage = provider.usage(json);
+            span.record("input_tokens", usage.input_tokens);
+            span.record("output_tokens", usage.output_tokens);
+        }
+        Err(err) => {
+            tracing::error!(provider = provider.name(), model, error = %err, "llm request failed")
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Agent, Ctx, Outcome, Runner, StepResult, Workflow};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::registry::{LookupSpan, Registry};
+
+    /// A span or event as a subscriber saw it: its name (or message), the
+    /// name of its parent span, and its fields.
+    #[derive(Debug, Clone, Default)]
+    struct Seen {
+        name: String,
+        parent: Option<String>,
+        fields: Vec<(String, String)>,
+    }
+
+    impl Seen {
+        fn field(&self, key: &str) -> Option<&str> {
+            self.fields
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    impl Visit for Seen {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let value = format!("{value:?}");
+            if field.name() == "message" {
+                self.name = value;
+            } else {
+                self.fields.push((field.name().to_string(), value));
+            }
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.record_debug(field, &format_args!("{value}"));
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Capture {
+        spans: Arc<Mutex<Vec<(Id, Seen)>>>,
+        events: Arc<Mutex<Vec<Seen>>>,
+    }
+
+    impl<S: tracing::Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            let mut seen = Seen {
+                name: span.name().to_string(),
+                parent: span.parent().map(|p| p.name().to_string()),
+                fields: Vec::new(),
+            };
+            attrs.record(&mut seen);
+            self.spans.lock().unwrap().push((id.clone(), seen));
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            let mut spans = self.spans.lock().unwrap();
+            if let Some((_, seen)) = spans.iter_mut().rev().find(|(s, _)| s == id) {
+                values.record(seen);
+            }
+        }
+
+        fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+            let mut seen = Seen {
+                parent: ctx.event_span(event).map(|s| s.name().to_string()),
+                ..Seen::default()
+            };
+            event.record(&mut seen);
+            self.events.lock().unwrap().push(seen);
+        }
+    }
+
+    struct Step(&'static str, bool);
+
+    impl Agent<u32> for Step {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn run(&mut self, state: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            if self.1 {
+                return Err(StepError::invalid("bad input"));
+            }
+            // Tools called from an agent nest under its step.
+            crate::tools::file_exists("Cargo.toml");
+            Ok((state + 1, Outcome::Continue))
+        }
+    }
+
+    struct Finish;
+
+    impl Agent<u32> for Finish {
+        fn name(&self) -> &'static str {
+            "finish"
+        }
+        fn run(&mut self, state: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            Ok((state, Outcome::Done))
+        }
+    }
+
+    fn capture(wf: Workflow<u32>) -> (Vec<Seen>, Vec<Seen>) {
+        let capture = Capture::default();
+        let subscriber = Registry::default().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let _ = Runner::new(wf).with_tracing().run(0, &mut Ctx::new());
+        });
+        let spans = capture.spans.lock().unwrap();
+        let events = capture.events.lock().unwrap();
+        (
+            spans.iter().map(|(_, s)| s.clone()).collect(),
+            events.clone(),
+        )
+    }
+
+    #[test]
+    fn runners_report_nothing_until_asked() {
+        let wf = Workflow::builder("quiet").register(Finish).build().unwrap();
+        let capture = Capture::default();
+        let subscriber = Registry::default().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            Runner::new(wf).run(0, &mut Ctx::new()).unwrap();
+        });
+        assert!(capture.spans.lock().unwrap().is_empty());
+        assert!(capture.events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn run_and_step_spans_carry_workflow_agent_and_step_number() {
+        let inner = Workflow::builder("review")
+            .register(Step("edit", false))
+            .register(Finish)
+            .start_at("edit")
+            .then("finish")
+            .build()
+            .unwrap();
+        let wf = Workflow::builder("pipeline")
+            .register(Step("draft", false))
+            .subworkflow(inner)
+            .start_at("draft")
+            .then("review")
+            .build()
+            .unwrap();
+
+        let (spans, _) = capture(wf);
+
+        let run = spans.iter().find(|s| s.name == "workflow.run").unwrap();
+        assert_eq!(run.field("workflow"), Some("pipeline"));
+
+        let step = |agent: &str| {
+            spans
+                .iter()
+                .find(|s| s.name == "agent.step" && s.field("agent") == Some(agent))
+                .unwrap()
+        };
+        let draft = step("draft");
+        assert_eq!(draft.parent.as_deref(), Some("workflow.run"));
+        assert_eq!(draft.field("workflow"), Some("pipeline"));
+        assert_eq!(draft.field("step_number"), Some("1"));
+        assert_eq!(step("review/edit").parent.as_deref(), Some("agent.step"));
+
+        let tool = spans.iter().find(|s| s.name == "tool.file_exists").unwrap();
+        assert_eq!(tool.parent.as_deref(), Some("agent.step"));
+        assert_eq!(tool.field("path"), Some("Cargo.toml"));
+    }
+
+    #[test]
+    fn map_items_share_a_name_and_close_their_own_spans() {
+        let wf = Workflow::builder("pipeline")
+            .map(
+                "each",
+                |_: &u32| vec![0, 0, 0],
+                || Step("count", false),
+                |_, items| items.iter().sum(),
+                2,
+            )
+            .build()
+            .unwrap();
+
+        let (spans, _) = capture(wf);
+
+        let items: Vec<_> = spans
+            .iter()
+            .filter(|s| s.field("agent") == Some("each/count"))
+            .map(|s| (s.field("item"), s.field("step_number")))
+            .collect();
+        assert_eq!(
+            items,
+            [
+                (Some("0"), Some("1")),
+                (Some("1"), Some("2")),
+                (Some("2"), Some("3")),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_step_emits_error_event() {
+        let wf = Workflow::builder("pipeline")
+            .register(Step("draft", true))
+            .start_at("draft")
+            .build()
+            .unwrap();
+
+        let (_, events) = capture(wf);
+
+        let failed = events.iter().find(|e| e.name == "step failed").unwrap();
+        assert_eq!(failed.parent.as_deref(), Some("agent.step"));
+        assert_eq!(failed.field("agent"), Some("draft"));
+        assert_eq!(failed.field("error"), Some("invalid: bad input"));
+        let end = events.iter().find(|e| e.name == "workflow failed").unwrap();
+        assert_eq!(end.parent.as_deref(), Some("workflow.run"));
+    }
+
+    #[test]
+    fn chat_span_records_model_and_usage() {
+        let capture = Capture::default();
+        let subscriber = Registry::default().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let json = serde_json::json!({"prompt_eval_count": 12, "eval_count": 34});
+            chat_span(Provider::Ollama, "llama3.1:8b", 512, || Ok(json)).unwrap();
+        });
+
+        let spans = capture.spans.lock().unwrap();
+        let (_, chat) = &spans[0];
+        assert_eq!(chat.name, "llm.chat");
+        assert_eq!(chat.field("provider"), Some("ollama"));
+        assert_eq!(chat.field("model"), Some("llama3.1:8b"));
+        assert_eq!(chat.field("input_tokens"), Some("12"));
+        assert_eq!(chat.field("output_tokens"), Some("34"));
+    }
+}
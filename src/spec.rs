@@ -0,0 +1,680 @@
+use crate::workflow::AgentFactory;
+use crate::{Agent, RetryPolicy, Workflow, WorkflowError};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+type Condition<S> = Arc<dyn Fn(&S) -> bool + Send + Sync>;
+type Router<S> = Arc<dyn Fn(&S) -> Cow<'static, str> + Send + Sync>;
+
+// ---------------------------------------------------------------------------
+// SpecError
+// ---------------------------------------------------------------------------
+
+/// Errors returned by [`Workflow::from_spec`].
+#[derive(Debug)]
+pub enum SpecError {
+    /// The spec file could not be read.
+    Io(std::io::Error),
+    /// The file extension isn't a format this build can read.
+    UnsupportedFormat(String),
+    /// The spec isn't valid JSON, TOML or YAML, or doesn't match the
+    /// expected layout.
+    Parse(String),
+    /// A step names an agent that isn't in the [`AgentRegistry`].
+    UnknownAgent(String),
+    /// A chain, `when`, `branch`, error handler or `start` names a step the
+    /// spec doesn't list.
+    UnknownStep(String),
+    /// A `when` names a condition that isn't in the [`AgentRegistry`].
+    UnknownCondition(String),
+    /// A `branch` names a router that isn't in the [`AgentRegistry`].
+    UnknownRouter(String),
+    /// A factory built an agent whose name doesn't match the one it was
+    /// registered under.
+    NameMismatch {
+        /// The name the factory was registered under.
+        registered: String,
+        /// The name the built agent reports.
+        built: String,
+    },
+    /// The workflow failed [`crate::WorkflowBuilder::build`]'s checks.
+    Workflow(WorkflowError),
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read spec: {e}"),
+            Self::UnsupportedFormat(ext) => write!(f, "unsupported spec format: {ext}"),
+            Self::Parse(msg) => write!(f, "invalid spec: {msg}"),
+            Self::UnknownAgent(name) => write!(f, "agent not in registry: {name}"),
+            Self::UnknownStep(name) => write!(f, "unknown step: {name}"),
+            Self::UnknownCondition(name) => write!(f, "condition not in registry: {name}"),
+            Self::UnknownRouter(name) => write!(f, "router not in registry: {name}"),
+            Self::NameMismatch { registered, built } => {
+                write!(f, "agent registered as '{registered}' is named '{built}'")
+            }
+            Self::Workflow(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+impl From<std::io::Error> for SpecError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<WorkflowError> for SpecError {
+    fn from(e: WorkflowError) -> Self {
+        Self::Workflow(e)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// AgentRegistry
+// ---------------------------------------------------------------------------
+
+/// The agents, conditions and routers a spec file can refer to by name.
+///
+/// Code can't live in a config file, so everything a spec wires together is
+/// registered here first. Agents are registered as factories, so one
+/// registry can load any number of workflows.
+///
+/// ```rust
+/// # use agent_line::{Agent, AgentRegistry, Ctx, Outcome, StepResult};
+/// # #[derive(Clone)] struct Doc { passed: bool }
+/// # struct Coder;
+/// # impl Agent<Doc> for Coder {
+/// #     fn name(&self) -> &'static str { "coder" }
+/// #     fn run(&mut self, d: Doc, _: &mut Ctx) -> StepResult<Doc> { Ok((d, Outcome::Done)) }
+/// # }
+/// let registry = AgentRegistry::new()
+///     .agent("coder", || Coder)
+///     .condition("passed", |d: &Doc| d.passed);
+/// # let _ = registry;
+/// ```
+pub struct AgentRegistry<S> {
+    agents: HashMap<String, AgentFactory<S>>,
+    conditions: HashMap<String, Condition<S>>,
+    routers: HashMap<String, Router<S>>,
+}
+
+impl<S: Clone + Send + 'static> AgentRegistry<S> {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self {
+            agents: HashMap::new(),
+            conditions: HashMap::new(),
+            routers: HashMap::new(),
+        }
+    }
+
+    /// Make the agent built by `factory` available to specs as `name`.
+    /// `name` must match the agent's [`Agent::name`].
+    pub fn agent<A: Agent<S>>(
+        mut self,
+        name: impl Into<String>,
+        factory: impl Fn() -> A + Send + Sync + 'static,
+    ) -> Self {
+        self.agents
+            .insert(name.into(), Arc::new(move || Box::new(factory())));
+        self
+    }
+
+    /// Make `predicate` available to a spec's `when` edges as `name`.
+    pub fn condition(
+        mut self,
+        name: impl Into<String>,
+        predicate: impl Fn(&S) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.conditions.insert(name.into(), Arc::new(predicate));
+        self
+    }
+
+    /// Make `router` available to a spec's `branch` edges as `name`.
+    pub fn router<R: Into<Cow<'static, str>>>(
+        mut self,
+        name: impl Into<String>,
+        router: impl Fn(&S) -> R + Send + Sync + 'static,
+    ) -> Self {
+        self.routers
+            .insert(name.into(), Arc::new(move |state| router(state).into()));
+        self
+    }
+}
+
+impl<S: Clone + Send + 'static> Default for AgentRegistry<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Spec format
+// ---------------------------------------------------------------------------
+
+/// The formats [`Workflow::from_spec_str`] can read. TOML and YAML need the
+/// `toml` and `yaml` features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecFormat {
+    /// JSON, read from `.json` files.
+    Json,
+    /// TOML, read from `.toml` files.
+    #[cfg(feature = "toml")]
+    Toml,
+    /// YAML, read from `.yaml` or `.yml` files.
+    #[cfg(feature = "yaml")]
+    Yaml,
+}
+
+impl SpecFormat {
+    /// Pick a format from a file extension.
+    fn from_path(path: &Path) -> Result<Self, SpecError> {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match ext {
+            "json" => Ok(Self::Json),
+            #[cfg(feature = "toml")]
+            "toml" => Ok(Self::Toml),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Ok(Self::Yaml),
+            _ => Err(SpecError::UnsupportedFormat(ext.to_string())),
+        }
+    }
+
+    fn parse(self, text: &str) -> Result<Spec, SpecError> {
+        match self {
+            Self::Json => serde_json::from_str(text).map_err(|e| SpecError::Parse(e.to_string())),
+            #[cfg(feature = "toml")]
+            Self::Toml => toml::from_str(text).map_err(|e| SpecError::Parse(e.to_string())),
+            #[cfg(feature = "yaml")]
+            Self::Yaml => serde_yaml::from_str(text).map_err(|e| SpecError::Parse(e.to_string())),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Spec {
+    name: String,
+    #[serde(default)]
+    start: Option<String>,
+    steps: Vec<StepEntry>,
+    #[serde(default)]
+    chains: Vec<Vec<String>>,
+    #[serde(default)]
+    when: Vec<WhenSpec>,
+    #[serde(default)]
+    branches: Vec<BranchSpec>,
+    #[serde(default)]
+    on_error: Vec<ErrorSpec>,
+    #[serde(default)]
+    catch: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StepEntry {
+    Name(String),
+    Full(StepSpec),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepSpec {
+    agent: String,
+    #[serde(default)]
+    retry: Option<RetrySpec>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RetrySpec {
+    max_retries: usize,
+    #[serde(default)]
+    initial_backoff_ms: u64,
+    #[serde(default)]
+    max_backoff_ms: u64,
+    #[serde(default)]
+    multiplier: Option<f64>,
+    #[serde(default)]
+    jitter: bool,
+}
+
+impl RetrySpec {
+    fn policy(&self) -> RetryPolicy {
+        let mut policy = RetryPolicy::new(self.max_retries)
+            .with_backoff(
+                Duration::from_millis(self.initial_backoff_ms),
+                Duration::from_millis(self.max_backoff_ms),
+            )
+            .with_jitter(self.jitter);
+        if let Some(multiplier) = self.multiplier {
+            policy = policy.with_multiplier(multiplier);
+        }
+        policy
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WhenSpec {
+    from: String,
+    condition: String,
+    to: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BranchSpec {
+    from: String,
+    router: String,
+    targets: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ErrorSpec {
+    from: String,
+    handler: String,
+}
+
+// ---------------------------------------------------------------------------
+// Loading
+// ---------------------------------------------------------------------------
+
+impl<S: Clone + Send + 'static> Workflow<S> {
+    /// Load a workflow from a spec file, building its agents from `registry`.
+    ///
+    /// The format is picked from the extension: `.json`, or `.toml` and
+    /// `.yaml`/`.yml` with the `toml` and `yaml` features. A spec lists the
+    /// steps in registration order (the first is the default start), `then`
+    /// chains, `when`/`branch` edges that refer to the registry's
+    /// conditions and routers, and `on_error`/`catch` handlers:
+    ///
+    /// ```json
+    /// {
+    ///   "name": "coding",
+    ///   "steps": [
+    ///     "planner",
+    ///     { "agent": "coder", "timeout_ms": 60000,
+    ///       "retry": { "max_retries": 3, "initial_backoff_ms": 500, "max_backoff_ms": 5000 } },
+    ///     "tester",
+    ///     "publisher",
+    ///     "restore"
+    ///   ],
+    ///   "chains": [["planner", "coder", "tester"]],
+    ///   "when": [{ "from": "tester", "condition": "passed", "to": "publisher" }],
+    ///   "on_error": [{ "from": "tester", "handler": "restore" }]
+    /// }
+    /// ```
+    ///
+    /// The result goes through the same checks as
+    /// [`WorkflowBuilder::build`](crate::WorkflowBuilder::build).
+    pub fn from_spec(
+        path: impl AsRef<Path>,
+        registry: &AgentRegistry<S>,
+    ) -> Result<Self, SpecError> {
+        let path = path.as_ref();
+        let format = SpecFormat::from_path(path)?;
+        let text = std::fs::read_to_string(path)?;
+        Self::from_spec_str(&text, format, registry)
+    }
+
+    /// Load a workflow from spec text in `format`. See
+    /// [`from_spec`](Self::from_spec).
+    pub fn from_spec_str(
+        text: &str,
+        format: SpecFormat,
+        registry: &AgentRegistry<S>,
+    ) -> Result<Self, SpecError> {
+        let spec = format.parse(text)?;
+        let mut builder = Workflow::builder(spec.name);
+
+        // Build every agent first; step names are the agents' own.
+        let mut steps = HashSet::new();
+        let mut settings = Vec::new();
+        for entry in spec.steps {
+            let step = match entry {
+                StepEntry::Name(agent) => StepSpec {
+                    agent,
+                    retry: None,
+                    timeout_ms: None,
+                },
+                StepEntry::Full(step) => step,
+            };
+            let factory = registry
+                .agents
+                .get(&step.agent)
+                .ok_or_else(|| SpecError::UnknownAgent(step.agent.clone()))?;
+            let agent = factory();
+            if agent.name() != step.agent {
+                return Err(SpecError::NameMismatch {
+                    built: agent.name().to_string(),
+                    registered: step.agent,
+                });
+            }
+            steps.insert(step.agent.clone());
+            builder = builder.register_factory(agent, Arc::clone(factory));
+            settings.push((step.agent, step.retry, step.timeout_ms));
+        }
+        let step = |name: &str| match steps.contains(name) {
+            true => Ok(name.to_string()),
+            false => Err(SpecError::UnknownStep(name.to_string())),
+        };
+
+        if let Some(start) = &spec.start {
+            builder = builder.start_at(step(start)?);
+        }
+        for chain in &spec.chains {
+            for pair in chain.windows(2) {
+                builder = builder.link(step(&pair[0])?.into(), step(&pair[1])?.into());
+            }
+        }
+        for when in &spec.when {
+            let condition = registry
+                .conditions
+                .get(&when.condition)
+                .cloned()
+                .ok_or_else(|| SpecError::UnknownCondition(when.condition.clone()))?;
+            builder = builder.when_from(
+                step(&when.from)?.into(),
+                move |s: &S| condition(s),
+                step(&when.to)?.into(),
+            );
+        }
+        for branch in &spec.branches {
+            let router = registry
+                .routers
+                .get(&branch.router)
+                .cloned()
+                .ok_or_else(|| SpecError::UnknownRouter(branch.router.clone()))?;
+            let targets = branch
+                .targets
+                .iter()
+                .map(|t| step(t))
+                .collect::<Result<Vec<_>, _>>()?;
+            builder = builder.branch(step(&branch.from)?, move |s: &S| router(s), targets);
+        }
+        for handler in &spec.on_error {
+            builder = builder.on_error(step(&handler.from)?, step(&handler.handler)?);
+        }
+        if let Some(catch) = &spec.catch {
+            builder = builder.catch(step(catch)?);
+        }
+        for (name, retry, timeout_ms) in settings {
+            if let Some(retry) = retry {
+                builder = builder.retry_policy(name.clone(), retry.policy());
+            }
+            if let Some(ms) = timeout_ms {
+                builder = builder.timeout(name, Duration::from_millis(ms));
+            }
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Ctx, Outcome, Runner, StepResult};
+
+    struct Add {
+        name: &'static str,
+        amount: u32,
+    }
+
+    impl Agent<u32> for Add {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+        fn run(&mut self, state: u32, ctx: &mut Ctx) -> StepResult<u32> {
+            ctx.log(self.name);
+            Ok((state + self.amount, Outcome::Continue))
+        }
+    }
+
+    struct Finish;
+
+    impl Agent<u32> for Finish {
+        fn name(&self) -> &'static str {
+            "finish"
+        }
+        fn run(&mut self, state: u32, ctx: &mut Ctx) -> StepResult<u32> {
+            ctx.log("finish");
+            Ok((state, Outcome::Done))
+        }
+    }
+
+    fn registry() -> AgentRegistry<u32> {
+        AgentRegistry::new()
+            .agent("triage", || Add {
+                name: "triage",
+                amount: 1,
+            })
+            .agent("triage_narrative", || Add {
+                name: "triage_narrative",
+                amount: 10,
+            })
+            .agent("big", || Add {
+                name: "big",
+                amount: 100,
+            })
+            .agent("finish", || Finish)
+            .condition("large", |n: &u32| *n >= 10)
+            .router(
+                "parity",
+                |n: &u32| if n.is_multiple_of(2) { "big" } else { "finish" },
+            )
+    }
+
+    fn run(wf: Workflow<u32>) -> (u32, Vec<String>) {
+        let mut ctx = Ctx::new();
+        let result = Runner::new(wf).run(0, &mut ctx).unwrap();
+        (result, ctx.logs().to_vec())
+    }
+
+    #[test]
+    fn loads_steps_and_chains() {
+        let spec = r#"{
+            "name": "incident",
+            "steps": ["triage", "triage_narrative", "finish"],
+            "chains": [["triage", "triage_narrative", "finish"]]
+        }"#;
+        let wf = Workflow::from_spec_str(spec, SpecFormat::Json, &registry()).unwrap();
+        assert_eq!(wf.name(), "incident");
+        assert_eq!(
+            run(wf),
+            (
+                11,
+                vec!["triage".into(), "triage_narrative".into(), "finish".into()]
+            )
+        );
+    }
+
+    #[test]
+    fn loaded_workflows_can_be_instantiated() {
+        let spec = r#"{
+            "name": "incident",
+            "steps": ["triage", "finish"],
+            "chains": [["triage", "finish"]]
+        }"#;
+        let wf = Workflow::from_spec_str(spec, SpecFormat::Json, &registry()).unwrap();
+        assert_eq!(run(wf.instantiate().unwrap()), run(wf));
+    }
+
+    #[test]
+    fn skipping_a_step_only_needs_the_spec_changed() {
+        let spec = r#"{
+            "name": "incident",
+            "steps": ["triage", "finish"],
+            "chains": [["triage", "finish"]]
+        }"#;
+        let wf = Workflow::from_spec_str(spec, SpecFormat::Json, &registry()).unwrap();
+        assert_eq!(run(wf).0, 1);
+    }
+
+    #[test]
+    fn loads_start_when_and_branch() {
+        let spec = r#"{
+            "name": "routed",
+            "start": "triage_narrative",
+            "steps": ["triage", "triage_narrative", "big", "finish"],
+            "when": [{ "from": "triage_narrative", "condition": "large", "to": "triage" }],
+            "branches": [{ "from": "triage", "router": "parity", "targets": ["big", "finish"] }],
+            "chains": [["big", "finish"]]
+        }"#;
+        let wf = Workflow::from_spec_str(spec, SpecFormat::Json, &registry()).unwrap();
+        // 10 (large) -> triage 11 (odd) -> finish
+        assert_eq!(run(wf).0, 11);
+    }
+
+    #[test]
+    fn loads_retry_policies_and_timeouts() {
+        let spec = r#"{
+            "name": "settings",
+            "steps": [
+                { "agent": "triage", "timeout_ms": 5000,
+                  "retry": { "max_retries": 4, "initial_backoff_ms": 10, "max_backoff_ms": 100 } },
+                "finish"
+            ],
+            "chains": [["triage", "finish"]]
+        }"#;
+        let wf = Workflow::from_spec_str(spec, SpecFormat::Json, &registry()).unwrap();
+        assert_eq!(wf.retry_policy("triage").unwrap().max_retries(), 4);
+        assert_eq!(wf.timeout("triage"), Some(Duration::from_secs(5)));
+        assert!(wf.retry_policy("finish").is_none());
+    }
+
+    #[test]
+    fn rejects_names_missing_from_registry_or_steps() {
+        let load = |spec: &str| Workflow::from_spec_str(spec, SpecFormat::Json, &registry());
+
+        let err = load(r#"{ "name": "x", "steps": ["nope"] }"#).err().unwrap();
+        assert!(matches!(err, SpecError::UnknownAgent(ref n) if n == "nope"));
+
+        let err = load(r#"{ "name": "x", "steps": ["triage"], "chains": [["triage", "big"]] }"#)
+            .err()
+            .unwrap();
+        assert!(matches!(err, SpecError::UnknownStep(ref n) if n == "big"));
+
+        let err = load(
+            r#"{ "name": "x", "steps": ["triage", "big"],
+                 "when": [{ "from": "triage", "condition": "nope", "to": "big" }] }"#,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(err, SpecError::UnknownCondition(_)));
+
+        let err = load(r#"{ "name": "x", "steps": ["triage"], "extra": 1 }"#)
+            .err()
+            .unwrap();
+        assert!(matches!(err, SpecError::Parse(_)));
+    }
+
+    #[test]
+    fn loads_error_handlers() {
+        // `triage` continues with nowhere to go, which its handler catches.
+        let spec = r#"{
+            "name": "incident",
+            "steps": ["triage", "big", "finish"],
+            "on_error": [{ "from": "triage", "handler": "finish" }],
+            "catch": "big"
+        }"#;
+        let wf = Workflow::from_spec_str(spec, SpecFormat::Json, &registry()).unwrap();
+        assert_eq!(run(wf), (1, vec!["triage".into(), "finish".into()]));
+
+        let spec = r#"{ "name": "x", "steps": ["triage"], "catch": "nope" }"#;
+        let err = Workflow::from_spec_str(spec, SpecFormat::Json, &registry())
+            .err()
+            .unwrap();
+        assert!(matches!(err, SpecError::UnknownStep(name) if name == "nope"));
+    }
+
+    #[test]
+    fn build_rules_still_apply() {
+        let err = Workflow::from_spec_str(
+            r#"{ "name": "x", "steps": ["triage", "triage"] }"#,
+            SpecFormat::Json,
+            &registry(),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            SpecError::Workflow(WorkflowError::DuplicateAgent(name)) if name == "triage"
+        ));
+    }
+
+    #[test]
+    fn factory_name_must_match_registration() {
+        let registry = AgentRegistry::new().agent("alias", || Finish);
+        let err = Workflow::from_spec_str(
+            r#"{ "name": "x", "steps": ["alias"] }"#,
+            SpecFormat::Json,
+            &registry,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            SpecError::NameMismatch { built, .. } if built == "finish"
+        ));
+    }
+
+    #[test]
+    fn from_spec_picks_format_from_extension() {
+        let dir = std::env::temp_dir().join(format!("agent-line-spec-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wf.json");
+        std::fs::write(&path, r#"{ "name": "file", "steps": ["finish"] }"#).unwrap();
+        assert_eq!(
+            Workflow::from_spec(&path, &registry()).unwrap().name(),
+            "file"
+        );
+
+        let err = Workflow::from_spec(dir.join("wf.ini"), &registry())
+            .err()
+            .unwrap();
+        assert!(matches!(err, SpecError::UnsupportedFormat(ref e) if e == "ini"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn loads_toml() {
+        let spec = r#"
+            name = "incident"
+            steps = ["triage", { agent = "finish", timeout_ms = 1000 }]
+            chains = [["triage", "finish"]]
+        "#;
+        let wf = Workflow::from_spec_str(spec, SpecFormat::Toml, &registry()).unwrap();
+        assert_eq!(run(wf).0, 1);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn loads_yaml() {
+        let spec = "
+name: incident
+steps:
+  - triage
+  - agent: finish
+    retry: { max_retries: 2 }
+chains:
+  - [triage, finish]
+";
+        let wf = Workflow::from_spec_str(spec, SpecFormat::Yaml, &registry()).unwrap();
+        assert_eq!(run(wf).0, 1);
+    }
+}
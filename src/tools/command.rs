@@ -1,8 +1,11 @@
 use std::process::Command;
 
+use serde::Serialize;
+
 use crate::agent::StepError;
 
 /// Output from a shell command.
+#[derive(Serialize)]
 pub struct CmdOutput {
     /// Whether the command exited with status 0.
     pub success: bool,
@@ -13,29 +16,51 @@ pub struct CmdOutput {
 }
 
 /// Run a shell command via `sh -c`.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "tool.run_cmd", skip_all, fields(command = cmd), err)
+)]
 pub fn run_cmd(cmd: &str) -> Result<CmdOutput, StepError> {
-    let output = Command::new("sh").arg("-c").arg(cmd).output()?;
+    crate::metrics::tool_invoked("run_cmd");
+    crate::replay::tool(
+        "run_cmd",
+        || serde_json::json!({ "command": cmd }),
+        || {
+            let output = Command::new("sh").arg("-c").arg(cmd).output()?;
 
-    Ok(CmdOutput {
-        success: output.status.success(),
-        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-    })
+            Ok(CmdOutput {
+                success: output.status.success(),
+                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            })
+        },
+    )
 }
 
 /// Run a shell command via `sh -c` in a specific directory.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "tool.run_cmd", skip_all, fields(dir = dir_name, command = cmd), err)
+)]
 pub fn run_cmd_in_dir(dir_name: &str, cmd: &str) -> Result<CmdOutput, StepError> {
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(cmd)
-        .current_dir(dir_name)
-        .output()?;
+    crate::metrics::tool_invoked("run_cmd_in_dir");
+    crate::replay::tool(
+        "run_cmd_in_dir",
+        || serde_json::json!({ "dir": dir_name, "command": cmd }),
+        || {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(cmd)
+                .current_dir(dir_name)
+                .output()?;
 
-    Ok(CmdOutput {
-        success: output.status.success(),
-        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-    })
+            Ok(CmdOutput {
+                success: output.status.success(),
+                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            })
+        },
+    )
 }
 
 #[cfg(test)]
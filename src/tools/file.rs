@@ -3,31 +3,91 @@ use std::fs::OpenOptions;
 use std::io::Write;
 
 /// Read an entire file into a string.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "tool.read_file",
+        level = "debug",
+        skip_all,
+        fields(path = path),
+        err,
+    )
+)]
 pub fn read_file(path: &str) -> Result<String, StepError> {
-    Ok(std::fs::read_to_string(path)?)
+    crate::metrics::tool_invoked("read_file");
+    crate::replay::tool(
+        "read_file",
+        || serde_json::json!({ "path": path }),
+        || Ok(std::fs::read_to_string(path)?),
+    )
 }
 /// Write content to a file, creating parent directories if needed.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "tool.write_file",
+        level = "debug",
+        skip_all,
+        fields(path = path, bytes = content.len()),
+        err,
+    )
+)]
 pub fn write_file(path: &str, content: &str) -> Result<(), StepError> {
-    if let Some(parent) = std::path::Path::new(path).parent() {
-        std::fs::create_dir_all(parent)?;
-    }
-    Ok(std::fs::write(path, content)?)
+    crate::metrics::tool_invoked("write_file");
+    crate::replay::tool(
+        "write_file",
+        || serde_json::json!({ "path": path, "content": content }),
+        || {
+            if let Some(parent) = std::path::Path::new(path).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            Ok(std::fs::write(path, content)?)
+        },
+    )
 }
 
 /// List entries in a directory.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "tool.list_dir", level = "debug", skip_all, fields(path = path), err)
+)]
 pub fn list_dir(path: &str) -> Result<Vec<String>, StepError> {
-    let mut entries = Vec::new();
-    for entry in std::fs::read_dir(path)? {
-        let entry = entry?;
-        entries.push(entry.path().display().to_string());
-    }
-    Ok(entries)
+    crate::metrics::tool_invoked("list_dir");
+    crate::replay::tool(
+        "list_dir",
+        || serde_json::json!({ "path": path }),
+        || {
+            let mut entries = Vec::new();
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?;
+                entries.push(entry.path().display().to_string());
+            }
+            Ok(entries)
+        },
+    )
 }
 /// Recursively find files matching a suffix pattern (e.g. `"*.rs"`).
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "tool.find_files",
+        level = "debug",
+        skip_all,
+        fields(path = path, pattern = pattern),
+        err,
+    )
+)]
 pub fn find_files(path: &str, pattern: &str) -> Result<Vec<String>, StepError> {
-    let mut results = Vec::new();
-    find_files_recursive(path, pattern, &mut results)?;
-    Ok(results)
+    crate::metrics::tool_invoked("find_files");
+    crate::replay::tool(
+        "find_files",
+        || serde_json::json!({ "path": path, "pattern": pattern }),
+        || {
+            let mut results = Vec::new();
+            find_files_recursive(path, pattern, &mut results)?;
+            Ok(results)
+        },
+    )
 }
 
 fn find_files_recursive(
@@ -51,31 +111,98 @@ fn find_files_recursive(
 }
 
 /// Append content to a file, creating it if it doesn't exist.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "tool.append_file",
+        level = "debug",
+        skip_all,
+        fields(path = file_path, bytes = content.len()),
+        err,
+    )
+)]
 pub fn append_file(file_path: &str, content: &str) -> Result<(), StepError> {
-    let mut file = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(file_path)?;
+    crate::metrics::tool_invoked("append_file");
+    crate::replay::tool(
+        "append_file",
+        || serde_json::json!({ "path": file_path, "content": content }),
+        || {
+            let mut file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(file_path)?;
 
-    file.write_all(content.as_bytes())?;
-    Ok(())
+            file.write_all(content.as_bytes())?;
+            Ok(())
+        },
+    )
 }
 
 /// Check if a file exists.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "tool.file_exists",
+        level = "debug",
+        skip_all,
+        fields(path = file_path),
+    )
+)]
 pub fn file_exists(file_path: &str) -> bool {
-    std::path::Path::new(file_path).exists()
+    crate::metrics::tool_invoked("file_exists");
+    let exists = std::path::Path::new(file_path).exists();
+    crate::replay::record_tool(
+        "file_exists",
+        || serde_json::json!({ "path": file_path }),
+        &Ok(exists),
+    );
+    exists
 }
 
 /// Delete a file.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "tool.delete_file",
+        level = "debug",
+        skip_all,
+        fields(path = file_path),
+        err,
+    )
+)]
 pub fn delete_file(file_path: &str) -> Result<(), StepError> {
-    std::fs::remove_file(file_path)?;
-    Ok(())
+    crate::metrics::tool_invoked("delete_file");
+    crate::replay::tool(
+        "delete_file",
+        || serde_json::json!({ "path": file_path }),
+        || {
+            std::fs::remove_file(file_path)?;
+            Ok(())
+        },
+    )
 }
 
 /// Create a directory and all parent directories.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "tool.create_dir",
+        level = "debug",
+        skip_all,
+        fields(path = name),
+        err,
+    )
+)]
 pub fn create_dir(name: &str) -> Result<(), StepError> {
-    std::fs::create_dir_all(name)?;
-    Ok(())
+    crate::metrics::tool_invoked("create_dir");
+    crate::replay::tool(
+        "create_dir",
+        || serde_json::json!({ "path": name }),
+        || {
+            std::fs::create_dir_all(name)?;
+            Ok(())
+        },
+    )
 }
 
 #[cfg(test)]
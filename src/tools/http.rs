@@ -4,57 +4,95 @@ use crate::agent::StepError;
 use ureq::{self, Agent};
 
 /// Send a GET request and return the response body as a string.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "tool.http_get", skip_all, fields(url = url), err)
+)]
 pub fn http_get(url: &str) -> Result<String, StepError> {
-    let config = Agent::config_builder()
-        .timeout_global(Some(Duration::from_secs(5)))
-        .build();
-
-    let agent: Agent = config.into();
-
-    let body: String = agent
-        .get(url)
-        .header("User-Agent", "agent-line")
-        .call()?
-        .body_mut()
-        .read_to_string()?;
-
-    Ok(body)
+    crate::metrics::tool_invoked("http_get");
+    crate::replay::tool(
+        "http_get",
+        || serde_json::json!({ "url": url }),
+        || {
+            let config = Agent::config_builder()
+                .timeout_global(Some(Duration::from_secs(5)))
+                .build();
+
+            let agent: Agent = config.into();
+
+            let body: String = agent
+                .get(url)
+                .header("User-Agent", "agent-line")
+                .call()?
+                .body_mut()
+                .read_to_string()?;
+
+            Ok(body)
+        },
+    )
 }
 
 /// Send a POST request with a string body and return the response body.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "tool.http_post",
+        skip_all,
+        fields(url = url, bytes = body.len()),
+        err,
+    )
+)]
 pub fn http_post(url: &str, body: &str) -> Result<String, StepError> {
-    let config = Agent::config_builder()
-        .timeout_global(Some(Duration::from_secs(5)))
-        .build();
-
-    let agent: Agent = config.into();
-
-    let response = agent
-        .post(url)
-        .header("User-Agent", "agent-line")
-        .send(body)?
-        .body_mut()
-        .read_to_string()?;
-
-    Ok(response)
+    crate::metrics::tool_invoked("http_post");
+    crate::replay::tool(
+        "http_post",
+        || serde_json::json!({ "url": url, "body": body }),
+        || {
+            let config = Agent::config_builder()
+                .timeout_global(Some(Duration::from_secs(5)))
+                .build();
+
+            let agent: Agent = config.into();
+
+            let response = agent
+                .post(url)
+                .header("User-Agent", "agent-line")
+                .send(body)?
+                .body_mut()
+                .read_to_string()?;
+
+            Ok(response)
+        },
+    )
 }
 
 /// Send a POST request with a JSON body and return the response body.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "tool.http_post", skip_all, fields(url = url), err)
+)]
 pub fn http_post_json(url: &str, body: &serde_json::Value) -> Result<String, StepError> {
-    let config = Agent::config_builder()
-        .timeout_global(Some(Duration::from_secs(5)))
-        .build();
-
-    let agent: Agent = config.into();
-
-    let response = agent
-        .post(url)
-        .header("User-Agent", "agent-line")
-        .send_json(body)?
-        .body_mut()
-        .read_to_string()?;
-
-    Ok(response)
+    crate::metrics::tool_invoked("http_post_json");
+    crate::replay::tool(
+        "http_post_json",
+        || serde_json::json!({ "url": url, "body": body }),
+        || {
+            let config = Agent::config_builder()
+                .timeout_global(Some(Duration::from_secs(5)))
+                .build();
+
+            let agent: Agent = config.into();
+
+            let response = agent
+                .post(url)
+                .header("User-Agent", "agent-line")
+                .send_json(body)?
+                .body_mut()
+                .read_to_string()?;
+
+            Ok(response)
+        },
+    )
 }
 
 #[cfg(test)]
@@ -1,20 +1,39 @@
-use crate::Agent;
-use std::collections::HashMap;
+use crate::diagram::{EdgeKind, Graph, GraphEdge, GraphNode, NodeKind};
+use crate::middleware::{Layered, Middleware};
+use crate::runner::{Exec, Finished, execute, run_map};
+use crate::{Agent, Ctx, RetryPolicy, Routes, StepError, StepResult};
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
 
 // ---------------------------------------------------------------------------
 // WorkflowError
 // ---------------------------------------------------------------------------
 
-/// Errors returned by [`WorkflowBuilder::build`].
+/// Errors returned by [`WorkflowBuilder::build`] and [`Workflow::instantiate`].
 #[derive(Debug)]
 pub enum WorkflowError {
     /// Two agents were registered with the same name.
-    DuplicateAgent(&'static str),
-    /// A `start_at` or `then` target does not match any registered agent.
-    UnknownStep(&'static str),
+    DuplicateAgent(String),
+    /// A `start_at`, `then`, `branch` or `when` target does not match any
+    /// registered agent.
+    UnknownStep(String),
     /// No agents were registered or no start step could be determined.
     MissingStart,
+    /// A parallel branch names a step that is not a plain agent.
+    InvalidBranch(String),
+    /// A timeout was set on a step that is not a plain agent or map step.
+    InvalidTimeout(String),
+    /// A layer was added to a step that is not a plain agent, or an item
+    /// layer to a step that is not a map step over the layer's type.
+    InvalidLayer(String),
+    /// A workflow was instantiated but this agent was registered as an
+    /// instance rather than with a factory.
+    MissingFactory(String),
 }
 
 impl fmt::Display for WorkflowError {
@@ -23,142 +42,1417 @@ impl fmt::Display for WorkflowError {
             Self::DuplicateAgent(name) => write!(f, "duplicate agent name: {name}"),
             Self::UnknownStep(name) => write!(f, "unknown step: {name}"),
             Self::MissingStart => write!(f, "workflow missing start step"),
+            Self::InvalidBranch(name) => {
+                write!(f, "parallel branch must be a registered agent: {name}")
+            }
+            Self::InvalidTimeout(name) => {
+                write!(
+                    f,
+                    "timeouts can only be set on agents and map steps: {name}"
+                )
+            }
+            Self::InvalidLayer(name) => {
+                write!(f, "layers can only be added to agents: {name}")
+            }
+            Self::MissingFactory(name) => {
+                write!(f, "agent was not registered with a factory: {name}")
+            }
         }
     }
 }
 
 impl std::error::Error for WorkflowError {}
 
+/// A problem in a workflow's graph found by [`Workflow::validate`].
+///
+/// The checks trust [`Agent::routes`] declarations. An agent that doesn't
+/// declare its routes is assumed to be able to go anywhere and to finish,
+/// so it never causes an issue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphIssue {
+    /// No path from the start step reaches this step.
+    Unreachable(String),
+    /// The step can return `Continue` but has no `then` step or `branch`
+    /// to continue to (a `when` alone may not match).
+    ContinueWithoutNext(String),
+    /// The step is reachable, but no path from it ever reaches a step that
+    /// can return `Done`, e.g. a cycle with no exit.
+    NoExit(String),
+}
+
+impl fmt::Display for GraphIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreachable(name) => write!(f, "step '{name}' is unreachable from the start"),
+            Self::ContinueWithoutNext(name) => write!(
+                f,
+                "step '{name}' can return Continue but may have no next step"
+            ),
+            Self::NoExit(name) => write!(f, "no path from step '{name}' ever returns Done"),
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // WorkflowBuilder
 // ---------------------------------------------------------------------------
 
 /// Step-by-step builder for a [`Workflow`]. Obtained via [`Workflow::builder`].
-pub struct WorkflowBuilder<S: Clone + 'static> {
-    name: &'static str,
-    start: Option<&'static str>,
-    chain_last: Option<&'static str>,
-    agents: HashMap<&'static str, Box<dyn Agent<S>>>,
-    default_next: HashMap<&'static str, &'static str>,
-    duplicate: Option<&'static str>,
+pub struct WorkflowBuilder<S: Clone + Send + 'static> {
+    name: Name,
+    nodes: HashMap<Name, Node<S>>,
+    factories: HashMap<Name, AgentFactory<S>>,
+    edges: Edges<S>,
+    retry_policies: HashMap<Name, RetryPolicy>,
+    timeouts: HashMap<Name, Duration>,
+    error_handlers: HashMap<Name, Name>,
+    catch: Option<Name>,
+    max_visits: HashMap<Name, usize>,
+    loop_exits: HashMap<Name, Name>,
+    layers: Vec<Arc<dyn Middleware<S>>>,
+    agent_layers: HashMap<Name, Vec<Arc<dyn Middleware<S>>>>,
+    item_layers: HashMap<Name, Vec<Box<dyn Any + Send + Sync>>>,
+    unanchored_edge: bool,
+    wiring: Wiring,
 }
 
-impl<S: Clone + 'static> WorkflowBuilder<S> {
+impl<S: Clone + Send + 'static> WorkflowBuilder<S> {
     /// Register an agent. The first agent registered becomes the default start step.
-    pub fn register<A: Agent<S>>(mut self, agent: A) -> Self {
-        let name = agent.name();
-        if self.agents.contains_key(name) {
-            self.duplicate = Some(name);
+    pub fn register<A: Agent<S>>(self, agent: A) -> Self {
+        self.register_boxed(Box::new(agent))
+    }
+
+    /// Register `agent` under `name` instead of its own [`Agent::name`],
+    /// e.g. to add several instances of one agent type, or agents named at
+    /// runtime from config. Use [`register_as_with`](Self::register_as_with)
+    /// for a workflow you [`instantiate`](Workflow::instantiate).
+    pub fn register_as<A: Agent<S>>(self, name: impl Into<Cow<'static, str>>, agent: A) -> Self {
+        let agent = Box::new(Renamed {
+            name: step_name(name),
+            agent: Box::new(agent),
+        });
+        self.register_boxed(agent)
+    }
+
+    /// Register the agent `factory` builds under `name`, like
+    /// [`register_as`](Self::register_as), keeping the factory for
+    /// [`Workflow::instantiate`] like [`register_with`](Self::register_with).
+    pub fn register_as_with<A: Agent<S>>(
+        self,
+        name: impl Into<Cow<'static, str>>,
+        factory: impl Fn() -> A + Send + Sync + 'static,
+    ) -> Self {
+        let name = step_name(name);
+        let factory: AgentFactory<S> = Arc::new(move || {
+            Box::new(Renamed {
+                name: name.clone(),
+                agent: Box::new(factory()),
+            })
+        });
+        let agent = factory();
+        self.register_factory(agent, factory)
+    }
+
+    pub(crate) fn register_boxed(self, agent: Box<dyn Agent<S>>) -> Self {
+        let name = Name::from(agent.name());
+        self.add_node(name, Node::Agent(agent))
+    }
+
+    /// Register the agent `factory` builds. The factory is called once now
+    /// and again by every [`Workflow::instantiate`], so each instance of
+    /// the workflow gets an agent of its own.
+    pub fn register_with<A: Agent<S>>(
+        self,
+        factory: impl Fn() -> A + Send + Sync + 'static,
+    ) -> Self {
+        let factory: AgentFactory<S> = Arc::new(move || Box::new(factory()));
+        let agent = factory();
+        self.register_factory(agent, factory)
+    }
+
+    /// Register `agent`, which `factory` built, keeping the factory for
+    /// [`Workflow::instantiate`].
+    pub(crate) fn register_factory(
+        mut self,
+        agent: Box<dyn Agent<S>>,
+        factory: AgentFactory<S>,
+    ) -> Self {
+        self.factories.insert(Name::from(agent.name()), factory);
+        self.register_boxed(agent)
+    }
+
+    /// Register a fan-out/fan-in step named `name`.
+    ///
+    /// When the runner reaches it, each agent in `branches` runs once on its
+    /// own thread against a clone of the current state. `merge` then receives
+    /// the original state and the branch results (in `branches` order) and
+    /// returns the state the workflow continues with, following the step's
+    /// default next (set via `.then()`).
+    ///
+    /// Branches must be registered agents. A branch finishes when its agent
+    /// returns [`crate::Outcome::Continue`] or [`crate::Outcome::Done`];
+    /// `Retry` and `Wait` re-run it within the branch, and `Next` is an error.
+    /// Each branch sees a copy of the [`crate::Ctx`]; new log lines and
+    /// changed keys are folded back in branch order after all branches finish.
+    pub fn parallel<B: Into<Cow<'static, str>>>(
+        self,
+        name: impl Into<Cow<'static, str>>,
+        branches: impl IntoIterator<Item = B>,
+        merge: impl Fn(S, Vec<S>) -> S + Send + Sync + 'static,
+    ) -> Self {
+        let node = Node::Parallel(Parallel {
+            branches: branches.into_iter().map(step_name).collect(),
+            merge: Arc::new(merge),
+        });
+        self.add_node(step_name(name), node)
+    }
+
+    /// Embed another workflow over the same state as a single step, named
+    /// after that workflow.
+    ///
+    /// When the runner reaches it, the inner workflow runs from its own start
+    /// step until it returns [`crate::Outcome::Done`]; the parent then
+    /// follows the step's edges as if it had returned `Continue`. Inner steps
+    /// share the parent's hooks, limits and step counter and are reported as
+    /// `workflow/agent`.
+    pub fn subworkflow(self, wf: Workflow<S>) -> Self {
+        self.subworkflow_mapped(wf, S::clone, |_, inner| inner)
+    }
+
+    /// Embed a workflow over a different state type `T` as a single step.
+    ///
+    /// `into` builds the inner state from the parent's, and `from` folds the
+    /// inner workflow's final state back into the parent's. Otherwise it
+    /// behaves like [`subworkflow`](Self::subworkflow).
+    pub fn subworkflow_mapped<T: Clone + Send + 'static>(
+        self,
+        wf: Workflow<T>,
+        into: impl Fn(&S) -> T + Send + Sync + 'static,
+        from: impl Fn(S, T) -> S + Send + Sync + 'static,
+    ) -> Self {
+        let name = wf.name.clone();
+        let node = Node::Workflow(Box::new(Embedded {
+            wf,
+            into: Arc::new(into),
+            from: Arc::new(from),
+        }));
+        self.add_node(name, node)
+    }
+
+    /// Register a step named `step` that runs an agent over each item of a
+    /// collection, in parallel.
+    ///
+    /// When the runner reaches it, `extract` pulls the items out of the
+    /// state and each item runs through a fresh agent from `agent_for_item`,
+    /// at most `concurrency` at a time. `reduce` then receives the state and
+    /// the results in item order, and returns the state the workflow
+    /// continues with, following the step's `.then()`.
+    ///
+    /// Items run like parallel branches: `Retry` and `Wait` re-run the
+    /// agent, `Next` and `Pause` are errors, and each item sees a copy of
+    /// the [`crate::Ctx`] that is folded back in item order. The step's
+    /// [`retry_policy`](Self::retry_policy) and [`timeout`](Self::timeout)
+    /// apply to each item, and [`item_layer`](Self::item_layer) wraps the
+    /// item agents. Item runs are reported as `step/agent`, with the item's
+    /// index in the event's `item` field. If any item fails, the step fails
+    /// with the first failure once every item is done.
+    pub fn map<T, A>(
+        self,
+        step: impl Into<Cow<'static, str>>,
+        extract: impl Fn(&S) -> Vec<T> + Send + Sync + 'static,
+        agent_for_item: impl Fn() -> A + Send + Sync + 'static,
+        reduce: impl Fn(S, Vec<T>) -> S + Send + Sync + 'static,
+        concurrency: usize,
+    ) -> Self
+    where
+        T: Clone + Send + 'static,
+        A: Agent<T>,
+    {
+        let node = Node::Map(Box::new(Mapped {
+            extract: Arc::new(extract),
+            agent_for_item: Arc::new(move || Box::new(agent_for_item())),
+            reduce: Arc::new(reduce),
+            concurrency: concurrency.max(1),
+            layers: Vec::new(),
+        }));
+        self.add_node(step_name(step), node)
+    }
+
+    /// Set which agent runs first (overrides the default).
+    pub fn start_at(mut self, step: impl Into<Cow<'static, str>>) -> Self {
+        self.wiring.start_at(step_name(step));
+        self
+    }
+
+    /// Chain the next step: current(chain_last) -> next
+    pub fn then(mut self, next: impl Into<Cow<'static, str>>) -> Self {
+        self.wiring.then(step_name(next));
+        self
+    }
+
+    /// Set `from`'s default next step without moving the chain.
+    pub(crate) fn link(mut self, from: Name, next: Name) -> Self {
+        self.wiring.default_next.insert(from, next);
+        self
+    }
+
+    /// Add a conditional edge from `from` without moving the chain.
+    pub(crate) fn when_from(
+        mut self,
+        from: Name,
+        predicate: impl Fn(&S) -> bool + Send + Sync + 'static,
+        to: Name,
+    ) -> Self {
+        self.edges
+            .entry(from)
+            .or_default()
+            .push(Edge::when(predicate, to));
+        self
+    }
+
+    /// Route out of `from` with a function of the state when `from` returns
+    /// [`crate::Outcome::Continue`].
+    ///
+    /// `router` must return one of `targets`; `build()` checks that every
+    /// target exists, and the runner fails the step if the router picks a
+    /// name outside the list. Takes precedence over `from`'s `.then()` step.
+    pub fn branch<R, T>(
+        mut self,
+        from: impl Into<Cow<'static, str>>,
+        router: impl Fn(&S) -> R + Send + Sync + 'static,
+        targets: impl IntoIterator<Item = T>,
+    ) -> Self
+    where
+        R: Into<Cow<'static, str>>,
+        T: Into<Cow<'static, str>>,
+    {
+        self.edges
+            .entry(step_name(from))
+            .or_default()
+            .push(Edge::branch(router, targets));
+        self
+    }
+
+    /// Add a conditional edge from the current chain step (the last
+    /// `start_at`/`then` target): when that step returns
+    /// [`crate::Outcome::Continue`] and `predicate` holds, go to `to`.
+    ///
+    /// Conditions are checked in the order they were added, before the
+    /// step's `.then()` default. Does not move the chain, so in
+    /// `.start_at("tester").when(passed, "publish").then("coder")` the
+    /// tester goes to `publish` when `passed` holds and to `coder` otherwise.
+    pub fn when(
+        mut self,
+        predicate: impl Fn(&S) -> bool + Send + Sync + 'static,
+        to: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        match self.wiring.chain_last() {
+            Some(from) => self.when_from(from, predicate, step_name(to)),
+            None => {
+                self.unanchored_edge = true;
+                self
+            }
         }
-        self.agents.insert(name, Box::new(agent));
+    }
+
+    /// Retry `step` according to `policy` instead of the runner's
+    /// `max_retries`. On a parallel step or sub-workflow, the policy retries
+    /// the whole step when it fails; on a map step, it retries each item.
+    pub fn retry_policy(mut self, step: impl Into<Cow<'static, str>>, policy: RetryPolicy) -> Self {
+        self.retry_policies.insert(step_name(step), policy);
+        self
+    }
+
+    /// Fail agent `step` with [`StepError::Timeout`] if one run takes longer
+    /// than `limit`, overriding the runner's
+    /// [`with_step_timeout`](crate::Runner::with_step_timeout). Also applies
+    /// when the agent runs as a parallel branch, and to each item of a map
+    /// step.
+    ///
+    /// Timed agents run on a worker thread. One that times out is left to
+    /// finish there; before the step runs again, the runner waits up to
+    /// `limit` for it to come back.
+    pub fn timeout(mut self, step: impl Into<Cow<'static, str>>, limit: Duration) -> Self {
+        self.timeouts.insert(step_name(step), limit);
+        self
+    }
+
+    /// Route failures of `from` to `handler` instead of ending the run.
+    ///
+    /// A failure is an `Err` left over once retries are used up, an
+    /// [`crate::Outcome::Fail`], or a step running past its retry limit.
+    /// The handler runs next with the state the failed step started with
+    /// (or returned, for `Fail`) and finds the failure in
+    /// [`Ctx::caught`]; from there it routes like any other step, so it can
+    /// finish the run, fail it, or continue to a fallback. Cancellation is
+    /// never handled. Takes precedence over [`catch`](Self::catch).
+    pub fn on_error(
+        mut self,
+        from: impl Into<Cow<'static, str>>,
+        handler: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.error_handlers
+            .insert(step_name(from), step_name(handler));
+        self
+    }
+
+    /// Route failures of every step without its own
+    /// [`on_error`](Self::on_error) handler to `handler`. A failure of the
+    /// catch handler itself ends the run.
+    pub fn catch(mut self, handler: impl Into<Cow<'static, str>>) -> Self {
+        self.catch = Some(step_name(handler));
+        self
+    }
 
+    /// Let the run enter `step` at most `n` times, so a cycle through it,
+    /// like writer and editor handing a draft back and forth, can't go on
+    /// forever. Retries and waits within one visit don't count.
+    ///
+    /// The visit after the last fails the run with
+    /// [`StepError::LoopLimit`], which `on_error` and `catch` handlers see
+    /// like any other failure, unless [`loop_exit`](Self::loop_exit) names
+    /// a step to go to instead. Overrides
+    /// [`crate::Runner::with_max_visits`].
+    pub fn max_visits(mut self, step: impl Into<Cow<'static, str>>, n: usize) -> Self {
+        self.max_visits.insert(step_name(step), n);
+        self
+    }
+
+    /// Go to `exit` instead of failing once `step` has used up its visits.
+    pub fn loop_exit(
+        mut self,
+        step: impl Into<Cow<'static, str>>,
+        exit: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.loop_exits.insert(step_name(step), step_name(exit));
+        self
+    }
+
+    /// Wrap every agent, including parallel branches, in `layer`.
+    ///
+    /// Layers added first run outermost, and workflow-wide layers run
+    /// outside those added with [`layer_for`](Self::layer_for). Parallel
+    /// steps and sub-workflows are not wrapped as a whole; a sub-workflow's
+    /// agents take the layers of the workflow they were built in. Map item
+    /// agents are wrapped too when their items are of type `S`.
+    pub fn layer(mut self, layer: impl Middleware<S>) -> Self {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    /// Wrap agent `step` in `layer`, inside any workflow-wide layers.
+    pub fn layer_for(
+        mut self,
+        step: impl Into<Cow<'static, str>>,
+        layer: impl Middleware<S>,
+    ) -> Self {
+        self.agent_layers
+            .entry(step_name(step))
+            .or_default()
+            .push(Arc::new(layer));
+        self
+    }
+
+    /// Wrap the item agents of map step `step` in `layer`, inside any
+    /// workflow-wide layers. `T` must be the map's item type, or
+    /// [`build`](Self::build) fails with [`WorkflowError::InvalidLayer`].
+    pub fn item_layer<T: 'static>(
+        mut self,
+        step: impl Into<Cow<'static, str>>,
+        layer: impl Middleware<T>,
+    ) -> Self {
+        let layer: Arc<dyn Middleware<T>> = Arc::new(layer);
+        self.item_layers
+            .entry(step_name(step))
+            .or_default()
+            .push(Box::new(layer));
+        self
+    }
+
+    /// Validate and build the workflow. Returns an error if agents are
+    /// missing, duplicated, or if routing targets don't exist.
+    pub fn build(self) -> Result<Workflow<S>, WorkflowError> {
+        let mut nodes = self.nodes;
+        let start = self.wiring.validate(|name| nodes.contains_key(name))?;
+
+        // A `when` with no chain step to hang it on.
+        if self.unanchored_edge {
+            return Err(WorkflowError::MissingStart);
+        }
+
+        check_edges(&self.edges, |name| nodes.contains_key(name))?;
+
+        for step in self.retry_policies.keys() {
+            if !nodes.contains_key(step) {
+                return Err(WorkflowError::UnknownStep(step.to_string()));
+            }
+        }
+        let handlers = self.error_handlers.iter().flat_map(|(from, to)| [from, to]);
+        for step in handlers.chain(&self.catch) {
+            if !nodes.contains_key(step) {
+                return Err(WorkflowError::UnknownStep(step.to_string()));
+            }
+        }
+        let exits = self.loop_exits.iter().flat_map(|(from, to)| [from, to]);
+        for step in exits.chain(self.max_visits.keys()) {
+            if !nodes.contains_key(step) {
+                return Err(WorkflowError::UnknownStep(step.to_string()));
+            }
+        }
+        for step in self.timeouts.keys() {
+            match nodes.get(step) {
+                Some(Node::Agent(_) | Node::Map(_)) => {}
+                Some(_) => return Err(WorkflowError::InvalidTimeout(step.to_string())),
+                None => return Err(WorkflowError::UnknownStep(step.to_string())),
+            }
+        }
+
+        for step in self.agent_layers.keys() {
+            match nodes.get(step) {
+                Some(Node::Agent(_)) => {}
+                Some(_) => return Err(WorkflowError::InvalidLayer(step.to_string())),
+                None => return Err(WorkflowError::UnknownStep(step.to_string())),
+            }
+        }
+
+        // Map items take workflow-wide layers of their type, then their own.
+        for node in nodes.values_mut() {
+            if let Node::Map(map) = node {
+                for layer in &self.layers {
+                    map.add_layer(layer);
+                }
+            }
+        }
+        for (step, layers) in self.item_layers {
+            let Some(node) = nodes.get_mut(&step) else {
+                return Err(WorkflowError::UnknownStep(step.to_string()));
+            };
+            let Node::Map(map) = node else {
+                return Err(WorkflowError::InvalidLayer(step.to_string()));
+            };
+            for layer in layers {
+                if !map.add_layer(&*layer) {
+                    return Err(WorkflowError::InvalidLayer(step.to_string()));
+                }
+            }
+        }
+
+        // Validate every declared `Next` target exists.
+        for node in nodes.values() {
+            if let Node::Agent(agent) = node
+                && let Some(routes) = agent.routes()
+            {
+                for target in routes.next_steps() {
+                    if !nodes.contains_key(target.as_ref()) {
+                        return Err(WorkflowError::UnknownStep(target.to_string()));
+                    }
+                }
+            }
+        }
+
+        // Validate every parallel branch is a registered agent.
+        for node in nodes.values() {
+            if let Node::Parallel(parallel) = node {
+                for branch in &parallel.branches {
+                    match nodes.get(branch) {
+                        Some(Node::Agent(_)) => {}
+                        Some(_) => return Err(WorkflowError::InvalidBranch(branch.to_string())),
+                        None => return Err(WorkflowError::UnknownStep(branch.to_string())),
+                    }
+                }
+            }
+        }
+
+        // Wrap agents in their layers, workflow-wide ones outermost, and
+        // have their factories do the same.
+        let mut agent_layers = self.agent_layers;
+        let mut factories = self.factories;
+        let nodes = nodes
+            .into_iter()
+            .map(|(name, node)| match node {
+                Node::Agent(agent) => {
+                    let layers: Vec<_> = self
+                        .layers
+                        .iter()
+                        .cloned()
+                        .chain(agent_layers.remove(&name).into_iter().flatten())
+                        .collect();
+                    if layers.is_empty() {
+                        return (name, Node::Agent(agent));
+                    }
+                    if let Some(factory) = factories.remove(&name) {
+                        let step = name.clone();
+                        let layers = layers.clone();
+                        let layered: AgentFactory<S> = Arc::new(move || {
+                            Box::new(Layered {
+                                name: step.clone(),
+                                agent: factory(),
+                                layers: layers.clone(),
+                            })
+                        });
+                        factories.insert(name.clone(), layered);
+                    }
+                    let agent: Box<dyn Agent<S>> = Box::new(Layered {
+                        name: name.clone(),
+                        agent,
+                        layers,
+                    });
+                    (name, Node::Agent(agent))
+                }
+                node => (name, node),
+            })
+            .collect();
+
+        Ok(Workflow {
+            name: self.name,
+            start,
+            nodes,
+            factories,
+            edges: self.edges,
+            default_next: self.wiring.default_next,
+            retry_policies: self.retry_policies,
+            timeouts: self.timeouts,
+            error_handlers: self.error_handlers,
+            catch: self.catch,
+            max_visits: self.max_visits,
+            loop_exits: self.loop_exits,
+            stray: HashMap::new(),
+            lost: HashSet::new(),
+        })
+    }
+
+    fn add_node(mut self, name: Name, node: Node<S>) -> Self {
+        if self.nodes.contains_key(&name) {
+            self.wiring.duplicate = Some(name.clone());
+        }
+        self.nodes.insert(name.clone(), node);
+        self.wiring.registered(name);
+        self
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Nodes
+// ---------------------------------------------------------------------------
+
+/// A registered step: a single agent, a fan-out over several, a whole
+/// embedded workflow, or an agent mapped over a collection.
+pub(crate) enum Node<S> {
+    Agent(Box<dyn Agent<S>>),
+    Parallel(Parallel<S>),
+    Workflow(Box<dyn Nested<S>>),
+    Map(Box<dyn Mapping<S>>),
+}
+
+/// Builds a fresh agent for each [`Workflow::instantiate`].
+pub(crate) type AgentFactory<S> = Arc<dyn Fn() -> Box<dyn Agent<S>> + Send + Sync>;
+
+pub(crate) type Merge<S> = Arc<dyn Fn(S, Vec<S>) -> S + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct Parallel<S> {
+    pub(crate) branches: Vec<Name>,
+    pub(crate) merge: Merge<S>,
+}
+
+/// A step of a workflow over `S` that runs a workflow of its own.
+pub(crate) trait Nested<S>: Send {
+    fn run(
+        &mut self,
+        state: &S,
+        ctx: &mut Ctx,
+        exec: &mut Exec<'_>,
+        path: &str,
+    ) -> Result<S, StepError>;
+
+    /// A copy with fresh instances of its agents.
+    fn instantiate(&self) -> Result<Box<dyn Nested<S>>, WorkflowError>;
+}
+
+type IntoInner<S, T> = Arc<dyn Fn(&S) -> T + Send + Sync>;
+type FromInner<S, T> = Arc<dyn Fn(S, T) -> S + Send + Sync>;
+
+struct Embedded<S, T: Clone + Send + 'static> {
+    wf: Workflow<T>,
+    into: IntoInner<S, T>,
+    from: FromInner<S, T>,
+}
+
+impl<S, T> Nested<S> for Embedded<S, T>
+where
+    S: Clone + Send + 'static,
+    T: Clone + Send + 'static,
+{
+    fn run(
+        &mut self,
+        state: &S,
+        ctx: &mut Ctx,
+        exec: &mut Exec<'_>,
+        path: &str,
+    ) -> Result<S, StepError> {
+        let inner = execute(&mut self.wf, (self.into)(state), ctx, exec, path)?;
+        Ok((self.from)(state.clone(), inner))
+    }
+
+    fn instantiate(&self) -> Result<Box<dyn Nested<S>>, WorkflowError> {
+        Ok(Box::new(Embedded {
+            wf: self.wf.instantiate()?,
+            into: Arc::clone(&self.into),
+            from: Arc::clone(&self.from),
+        }))
+    }
+}
+
+type Extract<S, T> = Arc<dyn Fn(&S) -> Vec<T> + Send + Sync>;
+type Reduce<S, T> = Arc<dyn Fn(S, Vec<T>) -> S + Send + Sync>;
+
+/// A step of a workflow over `S` that runs an agent over each item of a
+/// collection in the state.
+pub(crate) trait Mapping<S>: Send {
+    /// Run every item, each under `policy` and `timeout`.
+    fn run(
+        &mut self,
+        state: &S,
+        ctx: &mut Ctx,
+        exec: &mut Exec<'_>,
+        path: &str,
+        policy: &RetryPolicy,
+        timeout: Option<Duration>,
+    ) -> Result<S, StepError>;
+
+    /// Wrap item agents in `layer`, an `Arc<dyn Middleware<T>>` over the
+    /// item type. Returns `false`, adding nothing, for any other type.
+    fn add_layer(&mut self, layer: &dyn Any) -> bool;
+
+    /// A copy that builds its item agents the same way.
+    fn instantiate(&self) -> Box<dyn Mapping<S>>;
+}
+
+/// An agent run over each item of a collection in the state.
+struct Mapped<S, T> {
+    extract: Extract<S, T>,
+    agent_for_item: AgentFactory<T>,
+    reduce: Reduce<S, T>,
+    concurrency: usize,
+    layers: Vec<Arc<dyn Middleware<T>>>,
+}
+
+impl<S, T: 'static> Mapped<S, T> {
+    /// A fresh item agent, wrapped in the item layers.
+    fn item_agent(&self) -> Box<dyn Agent<T>> {
+        let agent = (self.agent_for_item)();
+        if self.layers.is_empty() {
+            return agent;
+        }
+        Box::new(Layered {
+            name: step_name(agent.name().to_string()),
+            agent,
+            layers: self.layers.clone(),
+        })
+    }
+}
+
+impl<S, T> Mapping<S> for Mapped<S, T>
+where
+    S: Clone + Send + 'static,
+    T: Clone + Send + 'static,
+{
+    fn run(
+        &mut self,
+        state: &S,
+        ctx: &mut Ctx,
+        exec: &mut Exec<'_>,
+        path: &str,
+        policy: &RetryPolicy,
+        timeout: Option<Duration>,
+    ) -> Result<S, StepError> {
+        let items = (self.extract)(state)
+            .into_iter()
+            .map(|item| (self.item_agent(), item))
+            .collect();
+        let results = run_map(items, self.concurrency, policy, timeout, ctx, exec, path)?;
+        Ok((self.reduce)(state.clone(), results))
+    }
+
+    fn add_layer(&mut self, layer: &dyn Any) -> bool {
+        match layer.downcast_ref::<Arc<dyn Middleware<T>>>() {
+            Some(layer) => {
+                self.layers.push(Arc::clone(layer));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn instantiate(&self) -> Box<dyn Mapping<S>> {
+        Box::new(Mapped {
+            extract: Arc::clone(&self.extract),
+            agent_for_item: Arc::clone(&self.agent_for_item),
+            reduce: Arc::clone(&self.reduce),
+            concurrency: self.concurrency,
+            layers: self.layers.clone(),
+        })
+    }
+}
+
+/// An agent registered under a name other than its own, by
+/// [`WorkflowBuilder::register_as`].
+struct Renamed<S> {
+    name: Name,
+    agent: Box<dyn Agent<S>>,
+}
+
+impl<S: 'static> Agent<S> for Renamed<S> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&mut self, state: S, ctx: &mut Ctx) -> StepResult<S> {
+        self.agent.run(state, ctx)
+    }
+
+    fn routes(&self) -> Option<Routes> {
+        self.agent.routes()
+    }
+}
+
+type Predicate<S> = Arc<dyn Fn(&S) -> bool + Send + Sync>;
+type Router<S> = Arc<dyn Fn(&S) -> Cow<'static, str> + Send + Sync>;
+
+/// A declared routing edge, consulted when a step returns `Continue`.
+#[derive(Clone)]
+pub(crate) enum Edge<S> {
+    When {
+        predicate: Predicate<S>,
+        to: Name,
+    },
+    Branch {
+        router: Router<S>,
+        targets: Vec<Name>,
+    },
+}
+
+impl<S> Edge<S> {
+    pub(crate) fn when(predicate: impl Fn(&S) -> bool + Send + Sync + 'static, to: Name) -> Self {
+        Edge::When {
+            predicate: Arc::new(predicate),
+            to,
+        }
+    }
+
+    pub(crate) fn branch<R, T>(
+        router: impl Fn(&S) -> R + Send + Sync + 'static,
+        targets: impl IntoIterator<Item = T>,
+    ) -> Self
+    where
+        R: Into<Cow<'static, str>>,
+        T: Into<Cow<'static, str>>,
+    {
+        Edge::Branch {
+            router: Arc::new(move |state| router(state).into()),
+            targets: targets.into_iter().map(step_name).collect(),
+        }
+    }
+
+    pub(crate) fn targets(&self) -> &[Name] {
+        match self {
+            Edge::When { to, .. } => std::slice::from_ref(to),
+            Edge::Branch { targets, .. } => targets,
+        }
+    }
+}
+
+/// Declared edges by the step they leave from.
+pub(crate) type Edges<S> = HashMap<Name, Vec<Edge<S>>>;
+
+/// Check that every edge leaves from and leads to a step that exists.
+pub(crate) fn check_edges<S>(
+    edges: &Edges<S>,
+    exists: impl Fn(&str) -> bool,
+) -> Result<(), WorkflowError> {
+    for (from, edges) in edges {
+        if !exists(from) {
+            return Err(WorkflowError::UnknownStep(from.to_string()));
+        }
+        for edge in edges {
+            for target in edge.targets() {
+                if !exists(target) {
+                    return Err(WorkflowError::UnknownStep(target.to_string()));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Where `Continue` from `from` leads for this state: the first matching
+/// `when`/`branch` edge, else `default_next`.
+pub(crate) fn follow_edges<S>(
+    edges: &Edges<S>,
+    from: &str,
+    state: &S,
+    default_next: Option<Name>,
+) -> Result<Option<Name>, StepError> {
+    for edge in edges.get(from).into_iter().flatten() {
+        match edge {
+            Edge::When { predicate, to } => {
+                if predicate(state) {
+                    return Ok(Some(to.clone()));
+                }
+            }
+            Edge::Branch { router, targets } => {
+                let target = router(state);
+                let Some(target) = targets.iter().find(|t| ***t == *target) else {
+                    return Err(StepError::other(format!(
+                        "branch from '{from}' chose '{target}', which is not one of its targets"
+                    )));
+                };
+                return Ok(Some(target.clone()));
+            }
+        }
+    }
+    Ok(default_next)
+}
+
+// ---------------------------------------------------------------------------
+// Step names
+// ---------------------------------------------------------------------------
+
+/// A step or workflow name. Names can be built at runtime, so the
+/// workflow owns them; clones share the text instead of copying it.
+pub(crate) type Name = Arc<str>;
+
+/// The [`Name`] for a name passed to a builder.
+pub(crate) fn step_name(name: impl Into<Cow<'static, str>>) -> Name {
+    Name::from(name.into().as_ref())
+}
+
+// ---------------------------------------------------------------------------
+// Wiring (start step and `then` chains, shared with the async builder)
+// ---------------------------------------------------------------------------
+
+#[derive(Default)]
+pub(crate) struct Wiring {
+    start: Option<Name>,
+    chain_last: Option<Name>,
+    pub(crate) default_next: HashMap<Name, Name>,
+    pub(crate) duplicate: Option<Name>,
+}
+
+impl Wiring {
+    /// Record a newly registered agent.
+    pub(crate) fn registered(&mut self, name: Name) {
         // If this is the first agent added and start isn't set, default start to it.
         if self.start.is_none() {
-            self.start = Some(name);
+            self.start = Some(name.clone());
         }
 
         // Also initialize chain_last if it's not set.
         if self.chain_last.is_none() {
             self.chain_last = Some(name);
         }
+    }
 
-        self
+    pub(crate) fn chain_last(&self) -> Option<Name> {
+        self.chain_last.clone()
     }
 
-    /// Set which agent runs first (overrides the default).
-    pub fn start_at(mut self, step: &'static str) -> Self {
-        self.start = Some(step);
+    pub(crate) fn start_at(&mut self, step: Name) {
+        self.start = Some(step.clone());
         self.chain_last = Some(step);
-        self
     }
 
-    /// Chain the next step: current(chain_last) -> next
-    pub fn then(mut self, next: &'static str) -> Self {
-        let Some(current) = self.chain_last else {
+    pub(crate) fn then(&mut self, next: Name) {
+        let Some(current) = self.chain_last.replace(next.clone()) else {
             // No prior step; treat `next` as the start
             self.start = Some(next);
-            self.chain_last = Some(next);
-            return self;
+            return;
         };
 
         self.default_next.insert(current, next);
-        self.chain_last = Some(next);
-        self
     }
 
-    /// Validate and build the workflow. Returns an error if agents are
-    /// missing, duplicated, or if routing targets don't exist.
-    pub fn build(self) -> Result<Workflow<S>, WorkflowError> {
+    /// Check duplicates, the start step and every `then` target against the
+    /// registered agents. Returns the start step.
+    pub(crate) fn validate(&self, exists: impl Fn(&str) -> bool) -> Result<Name, WorkflowError> {
         // Check for duplicate agents.
-        if let Some(name) = self.duplicate {
-            return Err(WorkflowError::DuplicateAgent(name));
+        if let Some(name) = &self.duplicate {
+            return Err(WorkflowError::DuplicateAgent(name.to_string()));
         }
 
         // Check for a start step.
-        let start = self.start.ok_or(WorkflowError::MissingStart)?;
+        let start = self.start.clone().ok_or(WorkflowError::MissingStart)?;
 
         // Validate start_at target exists as a registered agent.
-        if !self.agents.contains_key(start) {
-            return Err(WorkflowError::UnknownStep(start));
+        if !exists(&start) {
+            return Err(WorkflowError::UnknownStep(start.to_string()));
         }
 
         // Validate every `then` target exists as a registered agent.
-        for &target in self.default_next.values() {
-            if !self.agents.contains_key(target) {
-                return Err(WorkflowError::UnknownStep(target));
+        for target in self.default_next.values() {
+            if !exists(target) {
+                return Err(WorkflowError::UnknownStep(target.to_string()));
             }
         }
 
+        Ok(start)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Workflow (validated, only constructed via build())
+// ---------------------------------------------------------------------------
+
+/// A validated workflow of agents. Built via [`Workflow::builder`].
+pub struct Workflow<S: Clone + Send + 'static> {
+    name: Name,
+    start: Name,
+    nodes: HashMap<Name, Node<S>>,
+    factories: HashMap<Name, AgentFactory<S>>,
+    edges: Edges<S>,
+    default_next: HashMap<Name, Name>,
+    retry_policies: HashMap<Name, RetryPolicy>,
+    timeouts: HashMap<Name, Duration>,
+    error_handlers: HashMap<Name, Name>,
+    catch: Option<Name>,
+    max_visits: HashMap<Name, usize>,
+    loop_exits: HashMap<Name, Name>,
+    /// Agents still running on a worker thread after timing out.
+    stray: HashMap<Name, Receiver<Finished<S>>>,
+    /// Steps whose agent panicked on a worker thread and had no factory to
+    /// rebuild it from.
+    lost: HashSet<Name>,
+}
+
+impl<S: Clone + Send + 'static> Workflow<S> {
+    /// Create a new builder with the given workflow name.
+    pub fn builder(name: impl Into<Cow<'static, str>>) -> WorkflowBuilder<S> {
+        WorkflowBuilder {
+            name: step_name(name),
+            nodes: HashMap::new(),
+            factories: HashMap::new(),
+            edges: HashMap::new(),
+            retry_policies: HashMap::new(),
+            timeouts: HashMap::new(),
+            error_handlers: HashMap::new(),
+            catch: None,
+            max_visits: HashMap::new(),
+            loop_exits: HashMap::new(),
+            layers: Vec::new(),
+            agent_layers: HashMap::new(),
+            item_layers: HashMap::new(),
+            unanchored_edge: false,
+            wiring: Wiring::default(),
+        }
+    }
+
+    /// The workflow's name (set at builder creation).
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// A fresh copy of the workflow with new agents from the factories they
+    /// were registered with. The graph, policies and layers are shared, so
+    /// this is cheap: build a definition once and instantiate it per run to
+    /// run it on several threads at once.
+    ///
+    /// Fails with [`WorkflowError::MissingFactory`] if an agent was
+    /// registered with [`register`](WorkflowBuilder::register) rather than
+    /// [`register_with`](WorkflowBuilder::register_with).
+    ///
+    /// ```rust
+    /// use agent_line::{Agent, Ctx, Outcome, Runner, StepResult, Workflow};
+    ///
+    /// struct Counter(u32);
+    /// impl Agent<u32> for Counter {
+    ///     fn name(&self) -> &'static str { "counter" }
+    ///     fn run(&mut self, n: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+    ///         self.0 += 1;
+    ///         Ok((n + self.0, Outcome::Done))
+    ///     }
+    /// }
+    ///
+    /// let template = Workflow::builder("count")
+    ///     .register_with(|| Counter(0))
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let handles: Vec<_> = (0..4)
+    ///     .map(|i| {
+    ///         let mut runner = Runner::new(template.instantiate().unwrap());
+    ///         std::thread::spawn(move || runner.run(i * 10, &mut Ctx::new()))
+    ///     })
+    ///     .collect();
+    /// for (i, handle) in handles.into_iter().enumerate() {
+    ///     assert_eq!(handle.join().unwrap().unwrap(), i as u32 * 10 + 1);
+    /// }
+    /// ```
+    pub fn instantiate(&self) -> Result<Workflow<S>, WorkflowError> {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|(name, node)| {
+                let node = match node {
+                    Node::Agent(_) => {
+                        let factory = self
+                            .factories
+                            .get(name)
+                            .ok_or_else(|| WorkflowError::MissingFactory(name.to_string()))?;
+                        Node::Agent(factory())
+                    }
+                    Node::Parallel(parallel) => Node::Parallel(parallel.clone()),
+                    Node::Workflow(inner) => Node::Workflow(inner.instantiate()?),
+                    Node::Map(map) => Node::Map(map.instantiate()),
+                };
+                Ok((name.clone(), node))
+            })
+            .collect::<Result<_, WorkflowError>>()?;
         Ok(Workflow {
-            name: self.name,
-            start,
-            agents: self.agents,
-            default_next: self.default_next,
+            name: self.name.clone(),
+            start: self.start.clone(),
+            nodes,
+            factories: self.factories.clone(),
+            edges: self.edges.clone(),
+            default_next: self.default_next.clone(),
+            retry_policies: self.retry_policies.clone(),
+            timeouts: self.timeouts.clone(),
+            error_handlers: self.error_handlers.clone(),
+            catch: self.catch.clone(),
+            max_visits: self.max_visits.clone(),
+            loop_exits: self.loop_exits.clone(),
+            stray: HashMap::new(),
+            lost: HashSet::new(),
         })
     }
-}
 
-// ---------------------------------------------------------------------------
-// Workflow (validated, only constructed via build())
-// ---------------------------------------------------------------------------
+    /// Check the graph for steps that can't be reached, `Continue`s with
+    /// nowhere to go, and steps that can never lead to `Done`. Returns every
+    /// issue found, or an empty list. Sub-workflows are checked as single
+    /// steps; validate them on their own before embedding them.
+    pub fn validate(&self) -> Vec<GraphIssue> {
+        let mut names: Vec<&Name> = self.nodes.keys().collect();
+        names.sort_unstable();
+        let routes: HashMap<&Name, Option<Routes>> = names
+            .iter()
+            .map(|&name| (name, self.declared_routes(name)))
+            .collect();
+
+        let mut issues = Vec::new();
+
+        // Parallel branches are reachable through their parallel step, but
+        // only steps the run routes to need a way forward.
+        let reachable = self.reachable(&names, &routes);
+        let branches: HashSet<&Name> = reachable
+            .iter()
+            .filter_map(|&name| match &self.nodes[name] {
+                Node::Parallel(parallel) => Some(parallel.branches.iter()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        for &name in &names {
+            if !reachable.contains(name) && !branches.contains(name) {
+                issues.push(GraphIssue::Unreachable(name.to_string()));
+            }
+        }
+
+        for &name in &names {
+            let continues = match &routes[name] {
+                Some(routes) => routes.can_continue(),
+                None => !matches!(self.nodes[name], Node::Agent(_)),
+            };
+            let has_next = self.default_next.contains_key(name)
+                || self
+                    .edges
+                    .get(name)
+                    .into_iter()
+                    .flatten()
+                    .any(|edge| matches!(edge, Edge::Branch { .. }));
+            if continues && !has_next && reachable.contains(name) {
+                issues.push(GraphIssue::ContinueWithoutNext(name.to_string()));
+            }
+        }
+
+        // Walk backwards from every step that can finish.
+        let mut exits: HashSet<&Name> = names
+            .iter()
+            .copied()
+            .filter(|&name| match &routes[name] {
+                Some(routes) => routes.can_finish(),
+                None => matches!(self.nodes[name], Node::Agent(_)),
+            })
+            .collect();
+        loop {
+            let before = exits.len();
+            for &name in &names {
+                if !exits.contains(name)
+                    && self
+                        .successors(name, &routes, &names)
+                        .iter()
+                        .any(|next| exits.contains(next))
+                {
+                    exits.insert(name);
+                }
+            }
+            if exits.len() == before {
+                break;
+            }
+        }
+        for &name in &names {
+            if reachable.contains(name) && !exits.contains(name) {
+                issues.push(GraphIssue::NoExit(name.to_string()));
+            }
+        }
+
+        issues
+    }
+
+    /// The steps and every edge between them, for drawing.
+    pub(crate) fn graph(&self) -> Graph<'_> {
+        let mut names: Vec<&str> = self.nodes.keys().map(|name| &**name).collect();
+        names.sort_unstable();
+
+        let mut nodes = Vec::with_capacity(names.len());
+        let mut edges = Vec::new();
+        for &name in &names {
+            let kind = match &self.nodes[name] {
+                Node::Agent(_) => NodeKind::Agent,
+                Node::Parallel(_) => NodeKind::Parallel,
+                Node::Workflow(_) => NodeKind::Workflow,
+                Node::Map(_) => NodeKind::Map,
+            };
+            nodes.push(GraphNode { name, kind });
+
+            let mut edge = |to, kind| {
+                edges.push(GraphEdge {
+                    from: name,
+                    to,
+                    kind,
+                })
+            };
+            if let Some(to) = self.default_next(name) {
+                edge(to, EdgeKind::Then);
+            }
+            for declared in self.edges.get(name).into_iter().flatten() {
+                match declared {
+                    Edge::When { to, .. } => edge(to, EdgeKind::When),
+                    Edge::Branch { targets, .. } => {
+                        for to in targets {
+                            edge(to, EdgeKind::Branch);
+                        }
+                    }
+                }
+            }
+            match &self.nodes[name] {
+                Node::Agent(agent) => {
+                    for to in agent.routes().iter().flat_map(Routes::next_steps) {
+                        if let Some(to) = self.step_named(to) {
+                            edge(to, EdgeKind::Next);
+                        }
+                    }
+                }
+                Node::Parallel(parallel) => {
+                    for to in &parallel.branches {
+                        edge(to, EdgeKind::Fork);
+                    }
+                }
+                Node::Workflow(_) | Node::Map(_) => {}
+            }
+            if let Some(to) = self.handler_of(name) {
+                edge(to, EdgeKind::Error);
+            }
+            if let Some(to) = self.loop_exits.get(name) {
+                edge(to, EdgeKind::Exit);
+            }
+        }
+
+        Graph {
+            name: &self.name,
+            start: &self.start,
+            nodes,
+            edges,
+        }
+    }
+
+    fn declared_routes(&self, name: &str) -> Option<Routes> {
+        match self.nodes.get(name)? {
+            Node::Agent(agent) => agent.routes(),
+            // Parallel steps and sub-workflows always continue.
+            _ => Some(Routes::new().continues()),
+        }
+    }
+
+    /// Steps the run can move to from `name`, not counting re-runs.
+    fn successors<'a>(
+        &'a self,
+        name: &Name,
+        routes: &HashMap<&Name, Option<Routes>>,
+        all: &[&'a Name],
+    ) -> Vec<&'a Name> {
+        let Some(routes) = &routes[name] else {
+            return all.to_vec();
+        };
+        let mut next: Vec<_> = routes
+            .next_steps()
+            .iter()
+            .filter_map(|step| self.step_named(step))
+            .collect();
+        if routes.can_continue() {
+            for edge in self.edges.get(name).into_iter().flatten() {
+                next.extend(edge.targets());
+            }
+            next.extend(self.default_next(name));
+        }
+        next.extend(self.handler_of(name));
+        next.extend(self.loop_exits.get(name));
+        next
+    }
+
+    fn reachable<'a>(
+        &'a self,
+        names: &[&'a Name],
+        routes: &HashMap<&Name, Option<Routes>>,
+    ) -> HashSet<&'a Name> {
+        let mut seen = HashSet::from([&self.start]);
+        let mut stack = vec![&self.start];
+        while let Some(name) = stack.pop() {
+            for step in self.successors(name, routes, names) {
+                if seen.insert(step) {
+                    stack.push(step);
+                }
+            }
+        }
+        seen
+    }
+
+    // --- stuff the runner uses (keep pub(crate)) ---
+    pub(crate) fn start(&self) -> &Name {
+        &self.start
+    }
+
+    /// Look up a step by a name from outside the workflow, e.g. one read
+    /// back from a checkpoint.
+    pub(crate) fn step_named(&self, name: &str) -> Option<&Name> {
+        self.nodes.get_key_value(name).map(|(step, _)| step)
+    }
+
+    pub(crate) fn node_mut(&mut self, name: &str) -> Option<&mut Node<S>> {
+        self.nodes.get_mut(name)
+    }
+
+    /// Take an agent out so it can run on another thread. Put it back with
+    /// [`restore_agent`](Self::restore_agent).
+    pub(crate) fn take_agent(&mut self, name: &str) -> Option<Box<dyn Agent<S>>> {
+        match self.nodes.remove_entry(name)? {
+            (_, Node::Agent(agent)) => Some(agent),
+            (name, other) => {
+                self.nodes.insert(name, other);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn restore_agent(&mut self, name: &Name, agent: Box<dyn Agent<S>>) {
+        self.nodes.insert(name.clone(), Node::Agent(agent));
+    }
+
+    /// Put a fresh agent in for `name` after the last one panicked on a
+    /// worker thread. Without a factory the step is marked lost instead.
+    pub(crate) fn replace_agent(&mut self, name: &Name) {
+        match self.factories.get(name) {
+            Some(factory) => {
+                let agent = factory();
+                self.restore_agent(name, agent);
+            }
+            None => {
+                self.lost.insert(name.clone());
+            }
+        }
+    }
+
+    /// The error for a step whose agent isn't there to run.
+    pub(crate) fn missing_agent(&self, name: &str) -> StepError {
+        if self.lost.contains(name) {
+            StepError::other(format!(
+                "step '{name}' panicked earlier and its agent was not registered with a factory"
+            ))
+        } else {
+            StepError::other(format!("unknown step: {name}"))
+        }
+    }
+
+    pub(crate) fn retry_policy(&self, step: &str) -> Option<&RetryPolicy> {
+        self.retry_policies.get(step)
+    }
+
+    pub(crate) fn timeout(&self, step: &str) -> Option<Duration> {
+        self.timeouts.get(step).copied()
+    }
+
+    pub(crate) fn max_visits(&self, step: &str) -> Option<usize> {
+        self.max_visits.get(step).copied()
+    }
+
+    pub(crate) fn loop_exit(&self, step: &str) -> Option<&Name> {
+        self.loop_exits.get(step)
+    }
 
-/// A validated workflow of agents. Built via [`Workflow::builder`].
-pub struct Workflow<S: Clone + 'static> {
-    name: &'static str,
-    start: &'static str,
-    agents: HashMap<&'static str, Box<dyn Agent<S>>>,
-    default_next: HashMap<&'static str, &'static str>,
-}
+    /// Remember an agent that timed out, so it can be put back once its
+    /// worker thread finishes.
+    pub(crate) fn park(&mut self, name: &Name, running: Receiver<Finished<S>>) {
+        self.stray.insert(name.clone(), running);
+    }
 
-impl<S: Clone + 'static> Workflow<S> {
-    /// Create a new builder with the given workflow name.
-    pub fn builder(name: &'static str) -> WorkflowBuilder<S> {
-        WorkflowBuilder {
-            name,
-            start: None,
-            chain_last: None,
-            agents: HashMap::new(),
-            default_next: HashMap::new(),
-            duplicate: None,
+    /// If agent `name` timed out earlier, wait up to `wait` for it to come
+    /// back and restore it. Its late result is dropped.
+    pub(crate) fn reclaim(&mut self, name: &Name, wait: Duration) -> Result<(), StepError> {
+        let Some(running) = self.stray.remove(name) else {
+            return Ok(());
+        };
+        match running.recv_timeout(wait) {
+            Ok((agent, _, _)) => {
+                self.restore_agent(name, agent);
+                Ok(())
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                self.stray.insert(name.clone(), running);
+                Err(StepError::Timeout(format!(
+                    "step '{name}' is still running from an earlier attempt that timed out"
+                )))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                self.replace_agent(name);
+                Err(StepError::other(format!(
+                    "step '{name}' panicked after timing out"
+                )))
+            }
         }
     }
 
-    /// The workflow's name (set at builder creation).
-    pub fn name(&self) -> &'static str {
-        self.name
+    /// The step that handles failures of `from`, if any. A step never
+    /// handles its own failures.
+    fn handler_of(&self, from: &str) -> Option<&Name> {
+        let handler = self.error_handlers.get(from).or(self.catch.as_ref())?;
+        (**handler != *from).then_some(handler)
     }
 
-    // --- stuff the runner uses (keep pub(crate)) ---
-    pub(crate) fn start(&self) -> &'static str {
-        self.start
+    /// Where a failure of `from` goes instead of ending the run.
+    /// Cancellation always ends it.
+    pub(crate) fn error_handler(&self, from: &str, err: &StepError) -> Option<&Name> {
+        if matches!(err, StepError::Cancelled(_)) {
+            return None;
+        }
+        self.handler_of(from)
     }
 
-    pub(crate) fn agent_mut(&mut self, name: &'static str) -> Option<&mut Box<dyn Agent<S>>> {
-        self.agents.get_mut(name)
+    pub(crate) fn default_next(&self, from: &str) -> Option<&Name> {
+        self.default_next.get(from)
     }
 
-    pub(crate) fn default_next(&self, from: &'static str) -> Option<&'static str> {
-        self.default_next.get(from).copied()
+    /// Where `Continue` from `from` leads for this state: the first matching
+    /// `when`/`branch` edge, else the `then` default.
+    pub(crate) fn next_after(&self, from: &str, state: &S) -> Result<Option<Name>, StepError> {
+        follow_edges(&self.edges, from, state, self.default_next(from).cloned())
     }
 }
 
@@ -193,8 +1487,8 @@ mod tests {
         assert!(wf.is_ok());
         let wf = wf.unwrap();
         assert_eq!(wf.name(), "test");
-        assert_eq!(wf.start(), "a");
-        assert_eq!(wf.default_next("a"), Some("b"));
+        assert_eq!(&**wf.start(), "a");
+        assert_eq!(wf.default_next("a").map(|n| &**n), Some("b"));
     }
 
     #[test]
@@ -212,7 +1506,7 @@ mod tests {
             .err()
             .unwrap();
 
-        assert!(matches!(err, WorkflowError::UnknownStep("missing")));
+        assert!(matches!(err, WorkflowError::UnknownStep(name) if name == "missing"));
     }
 
     #[test]
@@ -225,7 +1519,7 @@ mod tests {
             .err()
             .unwrap();
 
-        assert!(matches!(err, WorkflowError::UnknownStep("missing")));
+        assert!(matches!(err, WorkflowError::UnknownStep(name) if name == "missing"));
     }
 
     #[test]
@@ -237,6 +1531,324 @@ mod tests {
         assert!(wf.is_ok());
     }
 
+    #[test]
+    fn branch_target_must_exist() {
+        let err = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .register(FakeAgent("b"))
+            .branch("a", |_| "b", ["b", "missing"])
+            .build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, WorkflowError::UnknownStep(name) if name == "missing"));
+    }
+
+    #[test]
+    fn branch_source_must_exist() {
+        let err = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .branch("missing", |_| "a", ["a"])
+            .build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, WorkflowError::UnknownStep(name) if name == "missing"));
+    }
+
+    #[test]
+    fn when_target_must_exist() {
+        let err = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .start_at("a")
+            .when(|_| true, "missing")
+            .build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, WorkflowError::UnknownStep(name) if name == "missing"));
+    }
+
+    #[test]
+    fn when_without_chain_step_is_rejected() {
+        let err = Workflow::<S>::builder("test")
+            .when(|_| true, "a")
+            .register(FakeAgent("a"))
+            .build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, WorkflowError::MissingStart));
+    }
+
+    #[test]
+    fn next_after_prefers_edges_over_then() {
+        let wf = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .register(FakeAgent("b"))
+            .register(FakeAgent("c"))
+            .start_at("a")
+            .when(|_| false, "b")
+            .when(|_| true, "c")
+            .then("b")
+            .build()
+            .unwrap();
+
+        assert_eq!(wf.next_after("a", &S).unwrap().as_deref(), Some("c"));
+        assert_eq!(wf.next_after("b", &S).unwrap(), None);
+    }
+
+    #[test]
+    fn parallel_branch_must_exist() {
+        let err = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .parallel("fan", ["a", "missing"], |s, _| s)
+            .build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, WorkflowError::UnknownStep(name) if name == "missing"));
+    }
+
+    #[test]
+    fn parallel_branch_cannot_be_parallel() {
+        let err = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .parallel("inner", ["a"], |s, _| s)
+            .parallel("outer", ["inner"], |s, _| s)
+            .build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, WorkflowError::InvalidBranch(name) if name == "inner"));
+    }
+
+    #[test]
+    fn parallel_name_collides_with_agent() {
+        let err = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .parallel("a", ["a"], |s, _| s)
+            .build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, WorkflowError::DuplicateAgent(name) if name == "a"));
+    }
+
+    #[test]
+    fn subworkflow_is_registered_under_its_name() {
+        let inner = Workflow::builder("inner")
+            .register(FakeAgent("a"))
+            .build()
+            .unwrap();
+        let wf = Workflow::builder("outer")
+            .register(FakeAgent("b"))
+            .subworkflow(inner)
+            .start_at("b")
+            .then("inner")
+            .build()
+            .unwrap();
+
+        assert_eq!(wf.default_next("b").map(|n| &**n), Some("inner"));
+    }
+
+    #[test]
+    fn subworkflow_name_collides_with_agent() {
+        let inner = Workflow::builder("a")
+            .register(FakeAgent("x"))
+            .build()
+            .unwrap();
+        let err = Workflow::builder("outer")
+            .register(FakeAgent("a"))
+            .subworkflow(inner)
+            .build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, WorkflowError::DuplicateAgent(name) if name == "a"));
+    }
+
+    #[test]
+    fn retry_policy_step_must_exist() {
+        let result = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .retry_policy("missing", crate::RetryPolicy::new(1))
+            .build();
+        assert!(matches!(
+            result.err().unwrap(),
+            WorkflowError::UnknownStep(name) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn timeout_only_on_agents() {
+        let result = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .parallel("fan", ["a"], |s, _| s)
+            .timeout("fan", Duration::from_secs(1))
+            .build();
+        assert!(matches!(
+            result.err().unwrap(),
+            WorkflowError::InvalidTimeout(name) if name == "fan"
+        ));
+    }
+
+    // --- graph analysis ---
+
+    struct Declared(&'static str, Routes);
+    impl Agent<S> for Declared {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn run(&mut self, state: S, _ctx: &mut Ctx) -> StepResult<S> {
+            Ok((state, Outcome::Done))
+        }
+        fn routes(&self) -> Option<Routes> {
+            Some(self.1.clone())
+        }
+    }
+
+    #[test]
+    fn declared_next_target_must_exist() {
+        let err = Workflow::builder("test")
+            .register(Declared("tester", Routes::new().done().next("coder")))
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, WorkflowError::UnknownStep(name) if name == "coder"));
+    }
+
+    #[test]
+    fn validate_accepts_a_sound_graph() {
+        let wf = Workflow::builder("test")
+            .register(Declared("coder", Routes::new().continues()))
+            .register(Declared("tester", Routes::new().done().next("coder")))
+            .start_at("coder")
+            .then("tester")
+            .build()
+            .unwrap();
+        assert_eq!(wf.validate(), vec![]);
+    }
+
+    #[test]
+    fn visit_limits_and_loop_exits_must_exist() {
+        let result = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .max_visits("missing", 2)
+            .build();
+        assert!(matches!(
+            result.err().unwrap(),
+            WorkflowError::UnknownStep(name) if name == "missing"
+        ));
+
+        let result = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .loop_exit("a", "missing")
+            .build();
+        assert!(matches!(
+            result.err().unwrap(),
+            WorkflowError::UnknownStep(name) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn error_handlers_must_exist() {
+        let result = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .on_error("a", "missing")
+            .build();
+        assert!(matches!(
+            result.err().unwrap(),
+            WorkflowError::UnknownStep(name) if name == "missing"
+        ));
+
+        let result = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .catch("missing")
+            .build();
+        assert!(matches!(
+            result.err().unwrap(),
+            WorkflowError::UnknownStep(name) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn error_handlers_are_reachable() {
+        let wf = Workflow::builder("test")
+            .register(Declared("a", Routes::new().done()))
+            .register(Declared("restore", Routes::new().done()))
+            .on_error("a", "restore")
+            .build()
+            .unwrap();
+        assert!(wf.validate().is_empty());
+    }
+
+    #[test]
+    fn validate_reports_unreachable_steps() {
+        let wf = Workflow::builder("test")
+            .register(Declared("a", Routes::new().done()))
+            .register(Declared("b", Routes::new().done()))
+            .build()
+            .unwrap();
+        assert_eq!(wf.validate(), vec![GraphIssue::Unreachable("b".into())]);
+    }
+
+    #[test]
+    fn undeclared_agents_may_go_anywhere() {
+        let wf = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .register(Declared("b", Routes::new().done()))
+            .build()
+            .unwrap();
+        assert_eq!(wf.validate(), vec![]);
+    }
+
+    #[test]
+    fn validate_reports_continue_without_next() {
+        let wf = Workflow::builder("test")
+            .register(Declared("a", Routes::new().continues().done()))
+            .start_at("a")
+            .when(|_: &S| true, "a")
+            .build()
+            .unwrap();
+        assert_eq!(
+            wf.validate(),
+            vec![GraphIssue::ContinueWithoutNext("a".into())]
+        );
+    }
+
+    #[test]
+    fn validate_reports_cycles_with_no_exit() {
+        let wf = Workflow::builder("test")
+            .register(Declared("writer", Routes::new().next("editor")))
+            .register(Declared("editor", Routes::new().next("writer")))
+            .register(Declared("publish", Routes::new().done()))
+            .build()
+            .unwrap();
+        assert_eq!(
+            wf.validate(),
+            vec![
+                GraphIssue::Unreachable("publish".into()),
+                GraphIssue::NoExit("editor".into()),
+                GraphIssue::NoExit("writer".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parallel_branches_need_no_next_step() {
+        let wf = Workflow::builder("test")
+            .register(Declared("a", Routes::new().continues()))
+            .register(Declared("b", Routes::new().continues()))
+            .register(Declared("done", Routes::new().done()))
+            .parallel("fan", ["a", "b"], |s, _| s)
+            .start_at("fan")
+            .then("done")
+            .build()
+            .unwrap();
+        assert_eq!(wf.validate(), vec![]);
+    }
+
     #[test]
     fn duplicate_agent_rejected() {
         let err = Workflow::builder("test")
@@ -246,6 +1858,143 @@ mod tests {
             .err()
             .unwrap();
 
-        assert!(matches!(err, WorkflowError::DuplicateAgent("a")));
+        assert!(matches!(err, WorkflowError::DuplicateAgent(name) if name == "a"));
+    }
+
+    /// Adds its run count, so a shared instance would show up.
+    struct Tally(u32);
+
+    impl Agent<u32> for Tally {
+        fn name(&self) -> &'static str {
+            "tally"
+        }
+        fn run(&mut self, n: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            self.0 += 1;
+            Ok((n + self.0, Outcome::Done))
+        }
+    }
+
+    struct Stop;
+
+    impl Agent<u32> for Stop {
+        fn name(&self) -> &'static str {
+            "stop"
+        }
+        fn run(&mut self, n: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            Ok((n, Outcome::Done))
+        }
+    }
+
+    struct Double;
+
+    impl Middleware<u32> for Double {
+        fn call(
+            &self,
+            n: u32,
+            ctx: &mut Ctx,
+            next: crate::middleware::Next<'_, u32>,
+        ) -> StepResult<u32> {
+            let (n, outcome) = next.run(n, ctx)?;
+            Ok((n * 2, outcome))
+        }
+    }
+
+    #[test]
+    fn instances_get_fresh_agents_and_keep_layers() {
+        use crate::Runner;
+
+        let inner = Workflow::builder("inner")
+            .register_with(|| Tally(0))
+            .build()
+            .unwrap();
+        let template = Workflow::builder("outer")
+            .subworkflow(inner)
+            .register_with(|| Stop)
+            .then("stop")
+            .layer_for("stop", Double)
+            .build()
+            .unwrap();
+
+        let mut first = Runner::new(template.instantiate().unwrap());
+        assert_eq!(first.run(10, &mut Ctx::new()).unwrap(), 22);
+        assert_eq!(first.run(10, &mut Ctx::new()).unwrap(), 24);
+        let mut second = Runner::new(template.instantiate().unwrap());
+        assert_eq!(second.run(10, &mut Ctx::new()).unwrap(), 22);
+
+        let layered = Workflow::builder("layered")
+            .register_with(|| Tally(0))
+            .layer(Double)
+            .build()
+            .unwrap();
+        let mut runner = Runner::new(layered.instantiate().unwrap());
+        assert_eq!(runner.run(10, &mut Ctx::new()).unwrap(), 22);
+    }
+
+    #[test]
+    fn instantiate_needs_a_factory_for_every_agent() {
+        let wf = Workflow::builder("test")
+            .register(FakeAgent("a"))
+            .build()
+            .unwrap();
+        let err = wf.instantiate().err().unwrap();
+        assert!(matches!(err, WorkflowError::MissingFactory(name) if name == "a"));
+    }
+
+    #[test]
+    fn renamed_agents_with_factories_can_be_instantiated() {
+        use crate::Runner;
+
+        let template = Workflow::builder("tallies")
+            .register_as_with(format!("tally-{}", 1), || Tally(0))
+            .register_as_with("tally-2", || Tally(10))
+            .build()
+            .unwrap();
+        assert!(template.step_named("tally-1").is_some());
+
+        let mut first = Runner::new(template.instantiate().unwrap());
+        assert_eq!(first.run(0, &mut Ctx::new()).unwrap(), 1);
+        assert_eq!(first.run(0, &mut Ctx::new()).unwrap(), 2);
+        let mut second = Runner::new(template.instantiate().unwrap());
+        assert_eq!(second.run(0, &mut Ctx::new()).unwrap(), 1);
+    }
+
+    struct Bump;
+
+    impl Agent<u32> for Bump {
+        fn name(&self) -> &'static str {
+            "bump"
+        }
+        fn run(&mut self, n: u32, _ctx: &mut Ctx) -> StepResult<u32> {
+            Ok((n + 1, Outcome::Continue))
+        }
+    }
+
+    #[test]
+    fn register_as_adds_instances_of_one_agent_type() {
+        use crate::Runner;
+
+        let personas = ["strict", "kind"];
+        let mut builder = Workflow::builder("review");
+        for persona in personas {
+            builder = builder.register_as(format!("review-{persona}"), Bump);
+        }
+        let wf = builder
+            .register_with(|| Stop)
+            .start_at(format!("review-{}", personas[0]))
+            .then(String::from("review-kind"))
+            .branch(
+                "review-kind",
+                move |&n: &u32| match n {
+                    2 => "stop".to_string(),
+                    _ => format!("review-{}", personas[0]),
+                },
+                ["stop".to_string(), format!("review-{}", personas[0])],
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(&**wf.start(), "review-strict");
+        let mut runner = Runner::new(wf);
+        assert_eq!(runner.run(0, &mut Ctx::new()).unwrap(), 2);
     }
 }